@@ -0,0 +1,11 @@
+-- Create login_states table for one-time OAuth2 state records
+CREATE TABLE login_states (
+    state TEXT PRIMARY KEY NOT NULL,
+    provider TEXT NOT NULL,
+    pkce_verifier TEXT NOT NULL,
+    return_url TEXT,
+    created_at DATETIME NOT NULL
+);
+
+-- Create index for expiry cleanup
+CREATE INDEX idx_login_states_created_at ON login_states(created_at);
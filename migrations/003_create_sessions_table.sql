@@ -0,0 +1,9 @@
+-- Create sessions table for the persistent tower-sessions store
+CREATE TABLE sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    data TEXT NOT NULL,
+    expiry_date INTEGER NOT NULL
+);
+
+-- Create index for expiry cleanup
+CREATE INDEX idx_sessions_expiry_date ON sessions(expiry_date);
@@ -0,0 +1,19 @@
+-- Create identities table so one user can sign in with several providers
+CREATE TABLE identities (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    provider TEXT NOT NULL,
+    provider_id TEXT NOT NULL,
+    email TEXT,
+    linked_at DATETIME NOT NULL,
+    UNIQUE(provider, provider_id)
+);
+
+-- Create index for listing a user's identities
+CREATE INDEX idx_identities_user_id ON identities(user_id);
+
+-- Every existing user keeps the identity it was created with
+-- (users.provider/provider_id now record the user's primary identity)
+INSERT INTO identities (user_id, provider, provider_id, email, linked_at)
+SELECT id, provider, provider_id, email, COALESCE(created_at, strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now'))
+FROM users;
@@ -0,0 +1,2 @@
+-- A login started from the dashboard links the new identity to this user
+ALTER TABLE login_states ADD COLUMN link_user_id INTEGER;
@@ -0,0 +1,2 @@
+-- Record when a user changed their display name locally, so logins stop overwriting it
+ALTER TABLE users ADD COLUMN username_edited_at DATETIME;
@@ -0,0 +1,2 @@
+-- OpenID Connect logins remember the nonce their ID token must carry
+ALTER TABLE login_states ADD COLUMN nonce TEXT;
@@ -0,0 +1,10 @@
+-- Roles granted to users (e.g. 'admin')
+CREATE TABLE user_roles (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL,
+    granted_at DATETIME NOT NULL,
+    PRIMARY KEY (user_id, role)
+);
+
+-- Create index for finding the holders of a role
+CREATE INDEX idx_user_roles_role ON user_roles(role);
@@ -0,0 +1,2 @@
+-- Record when an admin disabled a user; NULL means the account is active
+ALTER TABLE users ADD COLUMN disabled_at DATETIME;
@@ -0,0 +1,2 @@
+-- Explanation shown to a disabled user when they try to sign in
+ALTER TABLE users ADD COLUMN disabled_reason TEXT;
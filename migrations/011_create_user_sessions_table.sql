@@ -0,0 +1,12 @@
+-- Track each sign-in so users and admins can list and revoke sessions
+CREATE TABLE user_sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at DATETIME NOT NULL,
+    last_seen DATETIME NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT
+);
+
+-- Create index for listing a user's sessions
+CREATE INDEX idx_user_sessions_user_id ON user_sessions(user_id);
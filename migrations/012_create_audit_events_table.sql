@@ -0,0 +1,19 @@
+-- Persistent record of sign-ins, sign-outs, account changes and admin actions.
+-- User IDs are plain values so events outlive deleted users.
+CREATE TABLE audit_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    occurred_at DATETIME NOT NULL,
+    event_type TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    user_id INTEGER,
+    actor_id INTEGER,
+    provider TEXT,
+    ip_address TEXT,
+    user_agent TEXT,
+    error_kind TEXT,
+    details TEXT
+);
+
+-- Create indexes for filtering by date and by user
+CREATE INDEX idx_audit_events_occurred_at ON audit_events(occurred_at);
+CREATE INDEX idx_audit_events_user_id ON audit_events(user_id);
@@ -0,0 +1,52 @@
+-- Applications that may sign users in through this app's own OpenID provider
+CREATE TABLE oauth_clients (
+    client_id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    -- SHA-256 of the client secret; NULL for public clients, which rely on PKCE alone
+    secret_hash TEXT,
+    -- Exact redirect URIs the client may use, one per line
+    redirect_uris TEXT NOT NULL,
+    created_at DATETIME NOT NULL
+);
+
+-- One-time authorization codes, stored hashed
+CREATE TABLE oauth_authorization_codes (
+    code_hash TEXT PRIMARY KEY NOT NULL,
+    client_id TEXT NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    redirect_uri TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    nonce TEXT,
+    code_challenge TEXT NOT NULL,
+    auth_time DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL
+);
+
+-- Access tokens for the userinfo endpoint, stored hashed
+CREATE TABLE oauth_access_tokens (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    client_id TEXT NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    scope TEXT NOT NULL,
+    expires_at DATETIME NOT NULL
+);
+
+-- Scopes each user has agreed to share with each client
+CREATE TABLE oauth_consents (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    client_id TEXT NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    scope TEXT NOT NULL,
+    granted_at DATETIME NOT NULL,
+    PRIMARY KEY (user_id, client_id)
+);
+
+-- ES256 keys that sign ID tokens; the newest signs, all are published
+CREATE TABLE oauth_signing_keys (
+    kid TEXT PRIMARY KEY NOT NULL,
+    private_key_pem TEXT NOT NULL,
+    created_at DATETIME NOT NULL
+);
+
+-- Create indexes for purging expired codes and tokens
+CREATE INDEX idx_oauth_authorization_codes_expires_at ON oauth_authorization_codes(expires_at);
+CREATE INDEX idx_oauth_access_tokens_expires_at ON oauth_access_tokens(expires_at);
@@ -0,0 +1,18 @@
+-- Where a client accepts OpenID back-channel logout tokens; NULL if it does not
+ALTER TABLE oauth_clients ADD COLUMN backchannel_logout_uri TEXT;
+
+-- The sign-in codes and tokens were issued under; ending it revokes them
+ALTER TABLE oauth_authorization_codes ADD COLUMN session_id TEXT REFERENCES user_sessions(id) ON DELETE CASCADE;
+ALTER TABLE oauth_access_tokens ADD COLUMN session_id TEXT REFERENCES user_sessions(id) ON DELETE CASCADE;
+
+-- Clients each sign-in was used for, to be told when it ends
+CREATE TABLE oauth_client_sessions (
+    session_id TEXT NOT NULL REFERENCES user_sessions(id) ON DELETE CASCADE,
+    client_id TEXT NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (session_id, client_id)
+);
+
+-- Create index for ending all of a user's sessions
+CREATE INDEX idx_oauth_client_sessions_user_id ON oauth_client_sessions(user_id);
@@ -0,0 +1,16 @@
+-- Personal access tokens that let scripts call the API as their user
+CREATE TABLE api_tokens (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- SHA-256 of the token; the token itself is only shown once, at creation
+    token_hash TEXT NOT NULL UNIQUE,
+    -- Granted scopes, space-separated
+    scopes TEXT NOT NULL,
+    created_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL,
+    last_used_at DATETIME
+);
+
+-- Create index for listing a user's tokens
+CREATE INDEX idx_api_tokens_user_id ON api_tokens(user_id);
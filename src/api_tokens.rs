@@ -0,0 +1,434 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header, request::Parts},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::sqlite::SqlitePool;
+
+use crate::{
+    database::UserRepository,
+    error::{AppError, AuthError},
+    models::{Role, SessionData},
+    oauth_server::{hash_token, random_token},
+    session::{AuthenticatedUser, SessionPolicy},
+};
+
+// Marks a string as one of our tokens, so leaked ones are easy to scan for
+pub const TOKEN_PREFIX: &str = "sso_pat_";
+
+// Lifetimes offered when creating a token
+pub const TOKEN_LIFETIME_DAYS: &[i64] = &[7, 30, 90, 365];
+
+// Longest token name kept
+pub const MAX_TOKEN_NAME_LEN: usize = 100;
+
+/// What a personal access token may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    // Read the token owner's own account
+    Read,
+    // Change the token owner's own account
+    Write,
+    // Use admin endpoints, as long as the owner is still an admin
+    Admin,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 3] = [ApiScope::Read, ApiScope::Write, ApiScope::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Write => "write",
+            ApiScope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scope| scope.as_str() == value)
+    }
+
+    // Shown next to the scope's checkbox on the dashboard
+    pub fn description(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "Read your account",
+            ApiScope::Write => "Update your account",
+            ApiScope::Admin => "Use admin endpoints",
+        }
+    }
+}
+
+// A personal access token as listed on the dashboard; never holds the token itself
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub user_id: i64,
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+
+    pub fn scope_list(&self) -> String {
+        self.scopes.iter().map(ApiScope::as_str).collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl sqlx::FromRow<'_, sqlx::sqlite::SqliteRow> for ApiToken {
+    fn from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        use sqlx::Row;
+
+        let decode_error = |index: &str, message: String| sqlx::Error::ColumnDecode {
+            index: index.to_string(),
+            source: message.into(),
+        };
+        let parse = |column: &str, value: String| -> Result<DateTime<Utc>, sqlx::Error> {
+            DateTime::parse_from_rfc3339(&value)
+                .map(|value| value.with_timezone(&Utc))
+                .map_err(|e| decode_error(column, e.to_string()))
+        };
+
+        let scopes = row
+            .try_get::<String, _>("scopes")?
+            .split_whitespace()
+            .map(|scope| ApiScope::parse(scope).ok_or_else(|| decode_error("scopes", format!("unknown scope '{}'", scope))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let last_used_at = match row.try_get::<Option<String>, _>("last_used_at")? {
+            Some(value) => Some(parse("last_used_at", value)?),
+            None => None,
+        };
+
+        Ok(ApiToken {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            name: row.try_get("name")?,
+            scopes,
+            created_at: parse("created_at", row.try_get("created_at")?)?,
+            expires_at: parse("expires_at", row.try_get("expires_at")?)?,
+            last_used_at,
+        })
+    }
+}
+
+// A freshly created token, the only time its secret is known
+#[derive(Debug, Clone)]
+pub struct CreatedApiToken {
+    pub token: ApiToken,
+    pub secret: String,
+}
+
+/// Personal access tokens, stored as SHA-256 hashes.
+#[derive(Debug, Clone)]
+pub struct ApiTokenStore {
+    pool: SqlitePool,
+}
+
+impl ApiTokenStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create(
+        &self,
+        user_id: i64,
+        name: &str,
+        scopes: &[ApiScope],
+        lifetime_days: i64,
+    ) -> Result<CreatedApiToken, AppError> {
+        let secret = format!("{}{}", TOKEN_PREFIX, random_token());
+        let now = Utc::now();
+        let token = sqlx::query_as::<_, ApiToken>(
+            "INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at, expires_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)
+             RETURNING id, user_id, name, scopes, created_at, expires_at, last_used_at"
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(user_id)
+        .bind(name)
+        .bind(hash_token(&secret))
+        .bind(scopes.iter().map(ApiScope::as_str).collect::<Vec<_>>().join(" "))
+        .bind(now.to_rfc3339())
+        .bind((now + Duration::days(lifetime_days)).to_rfc3339())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(CreatedApiToken { token, secret })
+    }
+
+    pub async fn list(&self, user_id: i64) -> Result<Vec<ApiToken>, AppError> {
+        let tokens = sqlx::query_as::<_, ApiToken>(
+            "SELECT id, user_id, name, scopes, created_at, expires_at, last_used_at
+             FROM api_tokens
+             WHERE user_id = ?
+             ORDER BY created_at DESC"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    /// Revoke one of a user's tokens, returning whether it existed.
+    pub async fn revoke(&self, user_id: i64, token_id: &str) -> Result<bool, AppError> {
+        let result = sqlx::query("DELETE FROM api_tokens WHERE id = ? AND user_id = ?")
+            .bind(token_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Look up an unexpired token by its secret, recording that it was used.
+    pub async fn authenticate(&self, secret: &str) -> Result<Option<ApiToken>, AppError> {
+        let now = Utc::now().to_rfc3339();
+        let token = sqlx::query_as::<_, ApiToken>(
+            "UPDATE api_tokens SET last_used_at = ?
+             WHERE token_hash = ? AND expires_at > ?
+             RETURNING id, user_id, name, scopes, created_at, expires_at, last_used_at"
+        )
+        .bind(&now)
+        .bind(hash_token(secret))
+        .bind(&now)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(token)
+    }
+}
+
+/// Caller of an API endpoint, by personal access token or browser session.
+///
+/// An `Authorization: Bearer` token resolves to the same [`AuthenticatedUser`]
+/// a session would, limited to the token's scopes; without one, the session
+/// extractor runs and every scope is allowed.
+#[derive(Debug, Clone)]
+pub struct ApiUser {
+    pub user: AuthenticatedUser,
+    // The token used, if any
+    pub token: Option<ApiToken>,
+}
+
+impl ApiUser {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        // Sessions may do whatever their user may
+        self.token.as_ref().is_none_or(|token| token.has_scope(scope))
+    }
+
+    pub fn require_scope(&self, scope: ApiScope) -> Result<(), AppError> {
+        if self.has_scope(scope) {
+            return Ok(());
+        }
+        Err(AuthError::InsufficientScope(scope.as_str()).into())
+    }
+
+    /// Admin endpoints need both the admin role and, with a token, the admin scope.
+    pub fn require_admin(&self) -> Result<(), AppError> {
+        if !self.user.session_data.has_role(Role::Admin) {
+            return Err(AuthError::Forbidden.into());
+        }
+        self.require_scope(ApiScope::Admin)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ApiUser
+where
+    S: Send + Sync,
+    SessionPolicy: FromRef<S>,
+    UserRepository: FromRef<S>,
+    ApiTokenStore: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(authorization) = parts.headers.get(header::AUTHORIZATION) else {
+            let user = AuthenticatedUser::from_request_parts_without_return_to(parts, state).await?;
+            return Ok(ApiUser { user, token: None });
+        };
+        let secret = authorization
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(AuthError::InvalidApiToken)?;
+
+        let Some(token) = ApiTokenStore::from_ref(state).authenticate(secret).await? else {
+            return Err(AuthError::InvalidApiToken.into());
+        };
+
+        // Tokens stop working with their owner's account
+        let users = UserRepository::from_ref(state);
+        let user = match users.find_by_id(token.user_id).await? {
+            Some(user) if !user.is_disabled() => user,
+            _ => {
+                tracing::warn!("Refusing API token of disabled or deleted user ID {}", token.user_id);
+                return Err(AuthError::InvalidApiToken.into());
+            }
+        };
+
+        let session_data = SessionData {
+            user_id: user.id,
+            username: user.username,
+            provider: user.provider,
+            issued_at: token.created_at,
+            last_seen: Utc::now(),
+            roles: users.list_roles(user.id).await?,
+            session_id: None,
+        };
+        Ok(ApiUser {
+            user: AuthenticatedUser { session_data },
+            token: Some(token),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Config, SessionStoreKind},
+        database::Database,
+        models::CreateUser,
+        session::SessionManager,
+    };
+    use axum::{http::StatusCode, routing::get, Router};
+    use tempfile::NamedTempFile;
+
+    async fn setup() -> (ApiTokenStore, UserRepository, SqlitePool, NamedTempFile) {
+        let temp_file = NamedTempFile::new().unwrap();
+        let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
+        let db = Database::new(&database_url).await.unwrap();
+        let users = UserRepository::new(db.pool().clone());
+        users
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "12345".to_string(),
+                username: "octocat".to_string(),
+                email: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+
+        (ApiTokenStore::new(db.pool().clone()), users, db.pool().clone(), temp_file)
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_stored_hashed_and_revocable() {
+        let (store, _users, pool, _db_file) = setup().await;
+
+        let created = store.create(1, "CI", &[ApiScope::Read], 30).await.unwrap();
+        assert!(created.secret.starts_with(TOKEN_PREFIX));
+        assert_eq!(created.token.scopes, vec![ApiScope::Read]);
+        assert!(created.token.last_used_at.is_none());
+        let stored: String = sqlx::query_scalar("SELECT token_hash FROM api_tokens").fetch_one(&pool).await.unwrap();
+        assert_ne!(stored, created.secret);
+
+        // Using the token records when
+        let used = store.authenticate(&created.secret).await.unwrap().unwrap();
+        assert_eq!(used.id, created.token.id);
+        assert!(used.last_used_at.is_some());
+        assert!(store.authenticate("sso_pat_wrong").await.unwrap().is_none());
+
+        // Only the owner can revoke it, and then it stops working
+        assert!(!store.revoke(2, &created.token.id).await.unwrap());
+        assert!(store.revoke(1, &created.token.id).await.unwrap());
+        assert!(store.authenticate(&created.secret).await.unwrap().is_none());
+        assert!(store.list(1).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expired_tokens_are_refused() {
+        let (store, _users, pool, _db_file) = setup().await;
+
+        let created = store.create(1, "Old", &[ApiScope::Read], 7).await.unwrap();
+        sqlx::query("UPDATE api_tokens SET expires_at = ?")
+            .bind((Utc::now() - Duration::minutes(1)).to_rfc3339())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(store.authenticate(&created.secret).await.unwrap().is_none());
+        assert!(store.list(1).await.unwrap()[0].is_expired());
+    }
+
+    // Router state for extractor tests
+    #[derive(Clone, FromRef)]
+    struct ExtractorState {
+        policy: SessionPolicy,
+        users: UserRepository,
+        tokens: ApiTokenStore,
+    }
+
+    #[tokio::test]
+    async fn test_bearer_token_resolves_to_its_user() {
+        async fn me(api_user: ApiUser) -> Result<String, AppError> {
+            api_user.require_scope(ApiScope::Read)?;
+            Ok(api_user.user.session_data.username)
+        }
+
+        async fn admin(api_user: ApiUser) -> Result<String, AppError> {
+            api_user.require_admin()?;
+            Ok("admin".to_string())
+        }
+
+        let (store, users, pool, _db_file) = setup().await;
+        let config = Config {
+            session_secret: "test_session_secret_key_for_testing_purposes".to_string(),
+            session_store: SessionStoreKind::Memory,
+            ..Config::default()
+        };
+        let manager = SessionManager::new(&config, pool).unwrap();
+        let state = ExtractorState {
+            policy: manager.policy(),
+            users: users.clone(),
+            tokens: store.clone(),
+        };
+        let app = Router::new()
+            .route("/me", get(me))
+            .route("/admin", get(admin))
+            .with_state(state);
+        let server = axum_test::TestServer::new(manager.apply(app)).unwrap();
+
+        let read_only = store.create(1, "CLI", &[ApiScope::Read], 30).await.unwrap().secret;
+        let write_only = store.create(1, "Bot", &[ApiScope::Write, ApiScope::Admin], 30).await.unwrap().secret;
+        let bearer = |secret: &str| axum::http::HeaderValue::from_str(&format!("Bearer {}", secret)).unwrap();
+
+        let response = server.get("/me").add_header(header::AUTHORIZATION, bearer(&read_only)).await;
+        assert_eq!(response.text(), "octocat");
+
+        // Scopes are enforced, and admin endpoints also need the role
+        let response = server.get("/me").add_header(header::AUTHORIZATION, bearer(&write_only)).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let response = server.get("/admin").add_header(header::AUTHORIZATION, bearer(&write_only)).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        users.grant_role(1, Role::Admin).await.unwrap();
+        let response = server.get("/admin").add_header(header::AUTHORIZATION, bearer(&write_only)).await;
+        assert_eq!(response.text(), "admin");
+
+        // Unknown tokens get a 401 rather than the login page
+        let response = server.get("/me").add_header(header::AUTHORIZATION, bearer("sso_pat_nope")).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get("www-authenticate").is_some());
+
+        // Tokens of disabled users stop working
+        users.disable_user(1, None).await.unwrap();
+        let response = server.get("/me").add_header(header::AUTHORIZATION, bearer(&read_only)).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,387 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+
+use crate::{error::AppError, session::ClientInfo};
+
+/// What happened in an audited event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventType {
+    Login,
+    Logout,
+    Link,
+    Unlink,
+    ProfileUpdated,
+    SessionRevoked,
+    SignedOutEverywhere,
+    UserDisabled,
+    UserEnabled,
+    UserDeleted,
+    UserSessionsRevoked,
+    ClientRegistered,
+    ClientDeleted,
+    ClientAuthorized,
+    ApiTokenCreated,
+    ApiTokenRevoked,
+}
+
+impl AuditEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEventType::Login => "login",
+            AuditEventType::Logout => "logout",
+            AuditEventType::Link => "link",
+            AuditEventType::Unlink => "unlink",
+            AuditEventType::ProfileUpdated => "profile_updated",
+            AuditEventType::SessionRevoked => "session_revoked",
+            AuditEventType::SignedOutEverywhere => "signed_out_everywhere",
+            AuditEventType::UserDisabled => "user_disabled",
+            AuditEventType::UserEnabled => "user_enabled",
+            AuditEventType::UserDeleted => "user_deleted",
+            AuditEventType::UserSessionsRevoked => "user_sessions_revoked",
+            AuditEventType::ClientRegistered => "client_registered",
+            AuditEventType::ClientDeleted => "client_deleted",
+            AuditEventType::ClientAuthorized => "client_authorized",
+            AuditEventType::ApiTokenCreated => "api_token_created",
+            AuditEventType::ApiTokenRevoked => "api_token_revoked",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [
+            AuditEventType::Login,
+            AuditEventType::Logout,
+            AuditEventType::Link,
+            AuditEventType::Unlink,
+            AuditEventType::ProfileUpdated,
+            AuditEventType::SessionRevoked,
+            AuditEventType::SignedOutEverywhere,
+            AuditEventType::UserDisabled,
+            AuditEventType::UserEnabled,
+            AuditEventType::UserDeleted,
+            AuditEventType::UserSessionsRevoked,
+            AuditEventType::ClientRegistered,
+            AuditEventType::ClientDeleted,
+            AuditEventType::ClientAuthorized,
+            AuditEventType::ApiTokenCreated,
+            AuditEventType::ApiTokenRevoked,
+        ]
+        .into_iter()
+        .find(|event_type| event_type.as_str() == value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditOutcome::Success => "success",
+            AuditOutcome::Failure => "failure",
+        }
+    }
+}
+
+// A recorded audit event
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub event_type: AuditEventType,
+    pub outcome: AuditOutcome,
+    // The user the event is about
+    pub user_id: Option<i64>,
+    // The admin who acted on `user_id`, for admin actions
+    pub actor_id: Option<i64>,
+    pub provider: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub error_kind: Option<String>,
+    pub details: Option<String>,
+}
+
+impl sqlx::FromRow<'_, sqlx::sqlite::SqliteRow> for AuditEvent {
+    fn from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        use sqlx::Row;
+
+        let decode_error = |index: &str, message: String| sqlx::Error::ColumnDecode {
+            index: index.to_string(),
+            source: message.into(),
+        };
+
+        let occurred_at_str: String = row.try_get("occurred_at")?;
+        let occurred_at = DateTime::parse_from_rfc3339(&occurred_at_str)
+            .map_err(|e| decode_error("occurred_at", e.to_string()))?
+            .with_timezone(&Utc);
+
+        let event_type_str: String = row.try_get("event_type")?;
+        let event_type = AuditEventType::parse(&event_type_str)
+            .ok_or_else(|| decode_error("event_type", format!("unknown event type '{}'", event_type_str)))?;
+
+        let outcome = match row.try_get::<String, _>("outcome")?.as_str() {
+            "success" => AuditOutcome::Success,
+            "failure" => AuditOutcome::Failure,
+            other => return Err(decode_error("outcome", format!("unknown outcome '{}'", other))),
+        };
+
+        Ok(AuditEvent {
+            id: row.try_get("id")?,
+            occurred_at,
+            event_type,
+            outcome,
+            user_id: row.try_get("user_id")?,
+            actor_id: row.try_get("actor_id")?,
+            provider: row.try_get("provider")?,
+            ip_address: row.try_get("ip_address")?,
+            user_agent: row.try_get("user_agent")?,
+            error_kind: row.try_get("error_kind")?,
+            details: row.try_get("details")?,
+        })
+    }
+}
+
+// An event about to be recorded, built up with the `with_*` methods
+#[derive(Debug, Clone)]
+pub struct NewAuditEvent {
+    pub event_type: AuditEventType,
+    pub outcome: AuditOutcome,
+    pub user_id: Option<i64>,
+    pub actor_id: Option<i64>,
+    pub provider: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub error_kind: Option<String>,
+    pub details: Option<String>,
+}
+
+impl NewAuditEvent {
+    pub fn success(event_type: AuditEventType) -> Self {
+        Self {
+            event_type,
+            outcome: AuditOutcome::Success,
+            user_id: None,
+            actor_id: None,
+            provider: None,
+            ip_address: None,
+            user_agent: None,
+            error_kind: None,
+            details: None,
+        }
+    }
+
+    /// The same event, failed with `error`.
+    pub fn failed(mut self, error: &AppError) -> Self {
+        self.outcome = AuditOutcome::Failure;
+        self.error_kind = Some(error.kind().to_string());
+        self.details = Some(error.to_string());
+        self
+    }
+
+    pub fn with_user(mut self, user_id: Option<i64>) -> Self {
+        self.user_id = user_id;
+        self
+    }
+
+    pub fn with_actor(mut self, actor_id: i64) -> Self {
+        self.actor_id = Some(actor_id);
+        self
+    }
+
+    pub fn with_provider(mut self, provider: &str) -> Self {
+        self.provider = Some(provider.to_string());
+        self
+    }
+
+    pub fn with_client(mut self, client: &ClientInfo) -> Self {
+        self.ip_address = client.ip_address.clone();
+        self.user_agent = client.user_agent.clone();
+        self
+    }
+
+    pub fn with_details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+}
+
+// Which events to list or export; `until` is exclusive
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub user_id: Option<i64>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Append-only store for audit events.
+///
+/// Recording never fails the request being audited: a write error is logged
+/// and the request carries on. Every event is also emitted as a `tracing`
+/// event with the `audit` target.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    pool: SqlitePool,
+}
+
+impl AuditLog {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn record(&self, event: NewAuditEvent) {
+        tracing::info!(
+            target: "audit",
+            event_type = event.event_type.as_str(),
+            outcome = event.outcome.as_str(),
+            user_id = event.user_id,
+            actor_id = event.actor_id,
+            provider = event.provider.as_deref(),
+            error_kind = event.error_kind.as_deref(),
+            details = event.details.as_deref(),
+            "Audit event"
+        );
+
+        let result = sqlx::query(
+            "INSERT INTO audit_events
+                (occurred_at, event_type, outcome, user_id, actor_id, provider, ip_address, user_agent, error_kind, details)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(Utc::now().to_rfc3339())
+        .bind(event.event_type.as_str())
+        .bind(event.outcome.as_str())
+        .bind(event.user_id)
+        .bind(event.actor_id)
+        .bind(&event.provider)
+        .bind(&event.ip_address)
+        .bind(&event.user_agent)
+        .bind(&event.error_kind)
+        .bind(&event.details)
+        .execute(&self.pool)
+        .await;
+
+        if let Err(e) = result {
+            tracing::error!("Failed to record {} audit event: {}", event.event_type.as_str(), e);
+        }
+    }
+
+    /// Matching events, newest first.
+    pub async fn list(&self, filter: &AuditFilter, limit: i64, offset: i64) -> Result<Vec<AuditEvent>, AppError> {
+        let events = sqlx::query_as::<_, AuditEvent>(
+            "SELECT id, occurred_at, event_type, outcome, user_id, actor_id, provider, ip_address, user_agent, error_kind, details
+             FROM audit_events
+             WHERE (?1 IS NULL OR user_id = ?1 OR actor_id = ?1)
+               AND (?2 IS NULL OR occurred_at >= ?2)
+               AND (?3 IS NULL OR occurred_at < ?3)
+             ORDER BY occurred_at DESC, id DESC
+             LIMIT ?4 OFFSET ?5"
+        )
+        .bind(filter.user_id)
+        .bind(filter.since.map(|since| since.to_rfc3339()))
+        .bind(filter.until.map(|until| until.to_rfc3339()))
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(events)
+    }
+
+    pub async fn count(&self, filter: &AuditFilter) -> Result<i64, AppError> {
+        let count = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM audit_events
+             WHERE (?1 IS NULL OR user_id = ?1 OR actor_id = ?1)
+               AND (?2 IS NULL OR occurred_at >= ?2)
+               AND (?3 IS NULL OR occurred_at < ?3)"
+        )
+        .bind(filter.user_id)
+        .bind(filter.since.map(|since| since.to_rfc3339()))
+        .bind(filter.until.map(|until| until.to_rfc3339()))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::Database, error::AuthError};
+    use chrono::Duration;
+    use tempfile::NamedTempFile;
+
+    async fn setup_test_log() -> (AuditLog, NamedTempFile) {
+        let temp_file = NamedTempFile::new().unwrap();
+        let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
+        let db = Database::new(&database_url).await.unwrap();
+        (AuditLog::new(db.pool().clone()), temp_file)
+    }
+
+    #[tokio::test]
+    async fn test_record_and_list_events() {
+        let (log, _db_file) = setup_test_log().await;
+        let client = ClientInfo {
+            ip_address: Some("192.0.2.1".to_string()),
+            user_agent: Some("Firefox".to_string()),
+        };
+
+        log.record(
+            NewAuditEvent::success(AuditEventType::Login)
+                .with_user(Some(1))
+                .with_provider("github")
+                .with_client(&client),
+        )
+        .await;
+        log.record(
+            NewAuditEvent::success(AuditEventType::Login)
+                .with_provider("github")
+                .failed(&AuthError::AccessDenied("email domain not allowed".to_string()).into()),
+        )
+        .await;
+        log.record(NewAuditEvent::success(AuditEventType::UserDisabled).with_user(Some(2)).with_actor(1))
+            .await;
+
+        let all = log.list(&AuditFilter::default(), 10, 0).await.unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].event_type, AuditEventType::UserDisabled);
+
+        let denied = &all[1];
+        assert_eq!(denied.outcome, AuditOutcome::Failure);
+        assert_eq!(denied.error_kind.as_deref(), Some("access_denied"));
+        assert!(denied.details.as_deref().unwrap().contains("email domain not allowed"));
+
+        let login = &all[2];
+        assert_eq!(login.outcome, AuditOutcome::Success);
+        assert_eq!(login.ip_address.as_deref(), Some("192.0.2.1"));
+        assert_eq!(login.user_agent.as_deref(), Some("Firefox"));
+
+        // A user's events include admin actions they took
+        let by_user = AuditFilter {
+            user_id: Some(1),
+            ..AuditFilter::default()
+        };
+        assert_eq!(log.count(&by_user).await.unwrap(), 2);
+        assert_eq!(log.list(&by_user, 1, 1).await.unwrap()[0].event_type, AuditEventType::Login);
+    }
+
+    #[tokio::test]
+    async fn test_filter_by_date_range() {
+        let (log, _db_file) = setup_test_log().await;
+        log.record(NewAuditEvent::success(AuditEventType::Logout).with_user(Some(1))).await;
+
+        let now = Utc::now();
+        let window = |since: Duration, until: Duration| AuditFilter {
+            user_id: None,
+            since: Some(now + since),
+            until: Some(now + until),
+        };
+        assert_eq!(log.count(&window(Duration::hours(-1), Duration::hours(1))).await.unwrap(), 1);
+        assert_eq!(log.count(&window(Duration::hours(1), Duration::hours(2))).await.unwrap(), 0);
+        assert_eq!(log.count(&window(Duration::hours(-2), Duration::hours(-1))).await.unwrap(), 0);
+    }
+}
@@ -1,347 +1,342 @@
-use oauth2::{
-    basic::BasicClient, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken,
-    PkceCodeChallenge, RedirectUrl, Scope, TokenResponse, TokenUrl,
-};
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::Utc;
+use oauth2::PkceCodeVerifier;
 use reqwest::Client as HttpClient;
-use serde::Deserialize;
 
 use crate::{
     config::Config,
     database::UserRepository,
     error::{AppError, AuthError},
-    models::{CreateUser, User},
+    models::{Identity, User},
 };
 
+pub mod access_policy;
+pub mod admin_bootstrap;
+pub mod github;
+pub mod login_state;
+pub mod microsoft;
+pub mod oidc;
+pub mod profile_sync;
+pub mod provider;
+pub mod return_to;
+
+pub use access_policy::AccessPolicy;
+pub use admin_bootstrap::AdminBootstrap;
+pub use github::{GitHubEmail, GitHubOrg, GitHubProvider, GitHubTeamMembership, GitHubUserProfile};
+pub use login_state::{LoginState, LoginStateStore};
+pub use microsoft::{MicrosoftProvider, MicrosoftUserProfile};
+pub use oidc::{IdTokenClaims, JwksCache, OidcDiscoveryDocument, OidcProvider};
+pub use profile_sync::ProfileSyncPolicy;
+pub use provider::{AuthorizationRequest, IdentityProvider, ProviderProfile};
+pub use return_to::sanitize_return_to;
+
+// Registry of identity providers keyed by their slug
 #[derive(Debug, Clone)]
 pub struct OAuth2Config {
-    pub microsoft_client: BasicClient,
-    pub github_client: BasicClient,
+    providers: HashMap<String, Arc<dyn IdentityProvider>>,
     pub http_client: HttpClient,
 }
 
 impl OAuth2Config {
     pub fn new(config: &Config) -> Result<Self, AppError> {
-        // Microsoft OAuth2 client
-        let microsoft_client = BasicClient::new(
-            ClientId::new(config.microsoft_client_id.clone()),
-            Some(ClientSecret::new(config.microsoft_client_secret.clone())),
-            AuthUrl::new("https://login.microsoftonline.com/common/oauth2/v2.0/authorize".to_string())
-                .map_err(|e| AppError::Config(std::env::VarError::NotPresent))?,
-            Some(
-                TokenUrl::new("https://login.microsoftonline.com/common/oauth2/v2.0/token".to_string())
-                    .map_err(|e| AppError::Config(std::env::VarError::NotPresent))?,
-            ),
-        )
-        .set_redirect_uri(
-            RedirectUrl::new(format!("{}/auth/callback/microsoft", config.base_url))
-                .map_err(|e| AppError::Config(std::env::VarError::NotPresent))?,
-        );
-
-        // GitHub OAuth2 client
-        let github_client = BasicClient::new(
-            ClientId::new(config.github_client_id.clone()),
-            Some(ClientSecret::new(config.github_client_secret.clone())),
-            AuthUrl::new("https://github.com/login/oauth/authorize".to_string())
-                .map_err(|e| AppError::Config(std::env::VarError::NotPresent))?,
-            Some(
-                TokenUrl::new("https://github.com/login/oauth/access_token".to_string())
-                    .map_err(|e| AppError::Config(std::env::VarError::NotPresent))?,
-            ),
-        )
-        .set_redirect_uri(
-            RedirectUrl::new(format!("{}/auth/callback/github", config.base_url))
-                .map_err(|e| AppError::Config(std::env::VarError::NotPresent))?,
-        );
-
         let http_client = HttpClient::new();
 
-        Ok(OAuth2Config {
-            microsoft_client,
-            github_client,
-            http_client,
-        })
+        let mut oauth2_config = OAuth2Config {
+            providers: HashMap::new(),
+            http_client: http_client.clone(),
+        };
+
+        oauth2_config.register(MicrosoftProvider::new(config, http_client.clone())?);
+        oauth2_config.register(GitHubProvider::new(config, http_client)?);
+
+        Ok(oauth2_config)
+    }
+
+    pub fn register<P: IdentityProvider + 'static>(&mut self, provider: P) {
+        self.providers
+            .insert(provider.slug().to_string(), Arc::new(provider));
+    }
+
+    pub fn providers(&self) -> Vec<Arc<dyn IdentityProvider>> {
+        let mut providers: Vec<_> = self.providers.values().cloned().collect();
+        providers.sort_by(|a, b| a.slug().cmp(b.slug()));
+        providers
     }
+
+    pub fn provider(&self, slug: &str) -> Result<Arc<dyn IdentityProvider>, AuthError> {
+        self.providers
+            .get(slug)
+            .cloned()
+            .ok_or_else(|| AuthError::InvalidProvider(slug.to_string()))
+    }
+}
+
+// Result of a completed OAuth2 callback
+#[derive(Debug, Clone)]
+pub enum CallbackOutcome {
+    // The identity signed a user in (creating the user on first login),
+    // who should be sent on to `return_url` if the login started with one;
+    // `profile_changes` names the fields refreshed from the provider
+    SignedIn {
+        user: User,
+        return_url: Option<String>,
+        profile_changes: Vec<&'static str>,
+    },
+    // The identity was linked to the already signed-in user
+    Linked(Identity),
 }
 
 #[derive(Debug, Clone)]
 pub struct AuthService {
     oauth2_config: OAuth2Config,
     user_repository: UserRepository,
+    login_states: LoginStateStore,
+    profile_sync: ProfileSyncPolicy,
+    access_policy: AccessPolicy,
+    admin_bootstrap: AdminBootstrap,
+    upstream_logout: bool,
 }
 
 impl AuthService {
-    pub fn new(oauth2_config: OAuth2Config, user_repository: UserRepository) -> Self {
+    pub fn new(
+        oauth2_config: OAuth2Config,
+        user_repository: UserRepository,
+        login_states: LoginStateStore,
+    ) -> Self {
         Self {
             oauth2_config,
             user_repository,
+            login_states,
+            profile_sync: ProfileSyncPolicy::default(),
+            access_policy: AccessPolicy::default(),
+            admin_bootstrap: AdminBootstrap::default(),
+            upstream_logout: false,
         }
     }
 
-    pub fn initiate_microsoft_auth(&self) -> Result<(String, CsrfToken), AuthError> {
-        let (pkce_challenge, _pkce_verifier) = PkceCodeChallenge::new_random_sha256();
-
-        let (auth_url, csrf_token) = self
-            .oauth2_config
-            .microsoft_client
-            .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new("openid".to_string()))
-            .add_scope(Scope::new("profile".to_string()))
-            .add_scope(Scope::new("email".to_string()))
-            .set_pkce_challenge(pkce_challenge)
-            .url();
-
-        Ok((auth_url.to_string(), csrf_token))
+    pub fn with_profile_sync(mut self, profile_sync: ProfileSyncPolicy) -> Self {
+        self.profile_sync = profile_sync;
+        self
     }
 
-    pub fn initiate_github_auth(&self) -> Result<(String, CsrfToken), AuthError> {
-        let (auth_url, csrf_token) = self
-            .oauth2_config
-            .github_client
-            .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new("user:email".to_string()))
-            .url();
-
-        Ok((auth_url.to_string(), csrf_token))
+    pub fn with_access_policy(mut self, access_policy: AccessPolicy) -> Self {
+        self.access_policy = access_policy;
+        self
     }
-}
 
-// Microsoft Graph API user profile response
-#[derive(Debug, Deserialize)]
-pub struct MicrosoftUserProfile {
-    pub id: String,
-    #[serde(rename = "displayName")]
-    pub display_name: Option<String>,
-    #[serde(rename = "userPrincipalName")]
-    pub user_principal_name: Option<String>,
-    pub mail: Option<String>,
-}
-
-// GitHub API user profile response
-#[derive(Debug, Deserialize)]
-pub struct GitHubUserProfile {
-    pub id: u64,
-    pub login: String,
-    pub name: Option<String>,
-    pub email: Option<String>,
-    pub avatar_url: Option<String>,
-}
-
-// GitHub API email response
-#[derive(Debug, Deserialize)]
-pub struct GitHubEmail {
-    pub email: String,
-    pub primary: bool,
-    pub verified: bool,
-}
-
-impl AuthService {
-    pub async fn handle_microsoft_callback(
-        &self,
-        code: String,
-        state: String,
-        expected_csrf_token: CsrfToken,
-    ) -> Result<User, AuthError> {
-        // Verify CSRF token
-        if state != *expected_csrf_token.secret() {
-            return Err(AuthError::StateMismatch);
-        }
-
-        // Exchange authorization code for access token
-        let token_result = self
-            .oauth2_config
-            .microsoft_client
-            .exchange_code(AuthorizationCode::new(code))
-            .request_async(oauth2::reqwest::async_http_client)
-            .await
-            .map_err(|e| AuthError::TokenExchange(e.to_string()))?;
+    pub fn with_admin_bootstrap(mut self, admin_bootstrap: AdminBootstrap) -> Self {
+        self.admin_bootstrap = admin_bootstrap;
+        self
+    }
 
-        let access_token = token_result.access_token().secret();
+    pub fn with_upstream_logout(mut self, upstream_logout: bool) -> Self {
+        self.upstream_logout = upstream_logout;
+        self
+    }
 
-        // Fetch user profile from Microsoft Graph API
-        let profile_response = self
-            .oauth2_config
-            .http_client
-            .get("https://graph.microsoft.com/v1.0/me")
-            .bearer_auth(access_token)
-            .send()
-            .await
-            .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+    pub fn provider(&self, slug: &str) -> Result<Arc<dyn IdentityProvider>, AuthError> {
+        self.oauth2_config.provider(slug)
+    }
 
-        if !profile_response.status().is_success() {
-            return Err(AuthError::ProfileFetch(format!(
-                "HTTP {}",
-                profile_response.status()
-            )));
+    /// Where to end the session at the provider the user signed in with, if anywhere.
+    pub fn end_session_url(&self, provider: &str) -> Option<String> {
+        if !self.upstream_logout {
+            return None;
         }
+        self.provider(provider).ok()?.end_session_url()
+    }
 
-        let profile: MicrosoftUserProfile = profile_response
-            .json()
-            .await
-            .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+    /// Start a login, remembering where to send the user once they are signed in.
+    pub async fn initiate_auth(
+        &self,
+        provider: &str,
+        return_url: Option<String>,
+    ) -> Result<AuthorizationRequest, AppError> {
+        self.start_login(provider, return_url, None).await
+    }
 
-        // Check if user exists or create new user
-        let user = match self
-            .user_repository
-            .find_by_provider_id("microsoft", &profile.id)
-            .await
-        {
-            Ok(Some(existing_user)) => {
-                // Update last login
-                self.user_repository
-                    .update_last_login(existing_user.id)
-                    .await
-                    .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
-                existing_user
-            }
-            Ok(None) => {
-                // Create new user
-                let create_user = CreateUser {
-                    provider: "microsoft".to_string(),
-                    provider_id: profile.id,
-                    username: profile
-                        .display_name
-                        .or(profile.user_principal_name)
-                        .unwrap_or_else(|| "Microsoft User".to_string()),
-                    email: profile.mail,
-                    avatar_url: None,
-                };
+    /// Start a login whose identity will be linked to `user_id` instead of signing in.
+    pub async fn initiate_link(
+        &self,
+        provider: &str,
+        user_id: i64,
+    ) -> Result<AuthorizationRequest, AppError> {
+        self.start_login(provider, None, Some(user_id)).await
+    }
 
-                self.user_repository
-                    .create_user(create_user)
-                    .await
-                    .map_err(|e| AuthError::ProfileFetch(e.to_string()))?
-            }
-            Err(e) => return Err(AuthError::ProfileFetch(e.to_string())),
-        };
+    async fn start_login(
+        &self,
+        provider: &str,
+        return_url: Option<String>,
+        link_user_id: Option<i64>,
+    ) -> Result<AuthorizationRequest, AppError> {
+        let identity_provider = self.provider(provider)?;
+        let request = identity_provider.authorize_url();
+
+        // Remember the state server-side so the callback can be verified exactly once
+        self.login_states
+            .insert(&LoginState {
+                state: request.csrf_token.secret().clone(),
+                provider: identity_provider.slug().to_string(),
+                pkce_verifier: request.pkce_verifier.secret().clone(),
+                return_url,
+                link_user_id,
+                nonce: request.nonce.clone(),
+                created_at: Utc::now(),
+            })
+            .await?;
+
+        Ok(request)
+    }
 
-        Ok(user)
+    /// Discard the login state for a callback that will not be completed.
+    pub async fn abandon_auth(&self, provider: &str, state: &str, session_state: Option<&str>) {
+        let _ = self.login_states.consume(state, provider, session_state).await;
     }
 
-    pub async fn handle_github_callback(
+    pub async fn handle_callback(
         &self,
+        provider: &str,
         code: String,
         state: String,
-        expected_csrf_token: CsrfToken,
-    ) -> Result<User, AuthError> {
-        // Verify CSRF token
-        if state != *expected_csrf_token.secret() {
-            return Err(AuthError::StateMismatch);
+        session_state: Option<String>,
+        session_user_id: Option<i64>,
+    ) -> Result<CallbackOutcome, AppError> {
+        let identity_provider = self.provider(provider)?;
+
+        // Verify and consume the one-time login state
+        let login_state = self
+            .login_states
+            .consume(&state, identity_provider.slug(), session_state.as_deref())
+            .await?;
+
+        // A link must complete in the session of the user who started it
+        if login_state.link_user_id.is_some() && login_state.link_user_id != session_user_id {
+            tracing::warn!("Account link completed outside the session that started it");
+            return Err(AuthError::StateMismatch.into());
         }
 
-        // Exchange authorization code for access token
-        let token_result = self
-            .oauth2_config
-            .github_client
-            .exchange_code(AuthorizationCode::new(code))
-            .request_async(oauth2::reqwest::async_http_client)
-            .await
-            .map_err(|e| AuthError::TokenExchange(e.to_string()))?;
-
-        let access_token = token_result.access_token().secret();
-
-        // Fetch user profile from GitHub API
-        let profile_response = self
-            .oauth2_config
-            .http_client
-            .get("https://api.github.com/user")
-            .bearer_auth(access_token)
-            .header("User-Agent", "sso-web-app")
-            .send()
-            .await
-            .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+        // Exchange the authorization code (proving the PKCE verifier) and
+        // fetch the normalized user profile
+        let profile = identity_provider
+            .authenticate(
+                code,
+                PkceCodeVerifier::new(login_state.pkce_verifier),
+                login_state.nonce.as_deref(),
+            )
+            .await?;
+
+        if let Some(user_id) = login_state.link_user_id {
+            // A disabled user's session may still be live until their next request
+            if let Some(user) = self.user_repository.find_by_id(user_id).await? {
+                if user.is_disabled() {
+                    return Err(AuthError::AccountDisabled(user.disabled_reason).into());
+                }
+            }
 
-        if !profile_response.status().is_success() {
-            return Err(AuthError::ProfileFetch(format!(
-                "HTTP {}",
-                profile_response.status()
-            )));
+            let identity = self
+                .user_repository
+                .link_identity(
+                    user_id,
+                    identity_provider.slug(),
+                    &profile.provider_id,
+                    profile.email.as_deref(),
+                )
+                .await?;
+            return Ok(CallbackOutcome::Linked(identity));
         }
 
-        let mut profile: GitHubUserProfile = profile_response
-            .json()
-            .await
-            .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
-
-        // If email is not public, fetch it from the emails endpoint
-        if profile.email.is_none() {
-            let emails_response = self
-                .oauth2_config
-                .http_client
-                .get("https://api.github.com/user/emails")
-                .bearer_auth(access_token)
-                .header("User-Agent", "sso-web-app")
-                .send()
-                .await
-                .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
-
-            if emails_response.status().is_success() {
-                let emails: Vec<GitHubEmail> = emails_response
-                    .json()
-                    .await
-                    .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
-
-                // Find primary verified email
-                profile.email = emails
-                    .into_iter()
-                    .find(|email| email.primary && email.verified)
-                    .map(|email| email.email);
-            }
-        }
+        // Decide whether this account may sign in before creating or updating anything
+        self.access_policy.check(identity_provider.slug(), &profile)?;
+        // Only an address the provider vouches for may match ADMIN_EMAILS
+        let verified_email = profile.verified_email().map(str::to_string);
 
         // Check if user exists or create new user
-        let user = match self
+        let (user, profile_changes) = match self
             .user_repository
-            .find_by_provider_id("github", &profile.id.to_string())
+            .find_by_provider_id(identity_provider.slug(), &profile.provider_id)
             .await
         {
             Ok(Some(existing_user)) => {
-                // Update last login
-                self.user_repository
-                    .update_last_login(existing_user.id)
+                if existing_user.is_disabled() {
+                    tracing::warn!(
+                        target: "audit",
+                        user_id = existing_user.id,
+                        provider = identity_provider.slug(),
+                        "Sign-in refused for disabled account"
+                    );
+                    return Err(AuthError::AccountDisabled(existing_user.disabled_reason).into());
+                }
+
+                // Update last login and pick up profile changes made at the provider
+                let update = self
+                    .profile_sync
+                    .changes(&existing_user, identity_provider.slug(), &profile);
+                let user = self
+                    .user_repository
+                    .record_login(existing_user.id, &update)
                     .await
                     .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
-                existing_user
+
+                (user, update.changed_fields())
             }
             Ok(None) => {
                 // Create new user
-                let create_user = CreateUser {
-                    provider: "github".to_string(),
-                    provider_id: profile.id.to_string(),
-                    username: profile.name.unwrap_or(profile.login),
-                    email: profile.email,
-                    avatar_url: profile.avatar_url,
-                };
-
-                self.user_repository
-                    .create_user(create_user)
+                let user = self
+                    .user_repository
+                    .create_user(profile.into_create_user(identity_provider.slug()))
                     .await
-                    .map_err(|e| AuthError::ProfileFetch(e.to_string()))?
+                    .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+                (user, Vec::new())
             }
-            Err(e) => return Err(AuthError::ProfileFetch(e.to_string())),
+            Err(e) => return Err(AuthError::ProfileFetch(e.to_string()).into()),
         };
 
-        Ok(user)
+        self.admin_bootstrap
+            .apply(&self.user_repository, &user, verified_email.as_deref())
+            .await?;
+
+        Ok(CallbackOutcome::SignedIn {
+            user,
+            return_url: login_state.return_url,
+            profile_changes,
+        })
+    }
+
+    /// Every registered provider, for rendering sign-in and link buttons.
+    pub fn providers(&self) -> Vec<Arc<dyn IdentityProvider>> {
+        self.oauth2_config.providers()
     }
-}#[cfg(test)
-]
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::Database;
+    use crate::{config::LoginStateMode, database::Database, models::ProfileUpdate};
     use tempfile::NamedTempFile;
-    use wiremock::{matchers::{method, path, header}, Mock, MockServer, ResponseTemplate};
+    use wiremock::MockServer;
 
-    async fn setup_test_auth_service() -> (AuthService, MockServer) {
+    async fn setup_test_auth_service() -> (AuthService, MockServer, NamedTempFile) {
+        setup_test_auth_service_with(|_, _| {}).await
+    }
+
+    // Like `setup_test_auth_service`, letting the test point config (e.g. provider endpoints) at the mock server
+    async fn setup_test_auth_service_with(
+        configure: impl FnOnce(&mut Config, &MockServer),
+    ) -> (AuthService, MockServer, NamedTempFile) {
         // Set up test database
         let temp_file = NamedTempFile::new().unwrap();
         let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
         let db = Database::new(&database_url).await.unwrap();
         let user_repo = UserRepository::new(db.pool().clone());
+        let login_states = LoginStateStore::new(db.pool().clone(), LoginStateMode::Strict);
 
         // Set up mock server
         let mock_server = MockServer::start().await;
 
-        // Create test config with mock server URLs
-        let config = Config {
+        // Create test config
+        let mut config = Config {
             database_url,
             microsoft_client_id: "test_ms_client_id".to_string(),
             microsoft_client_secret: "test_ms_client_secret".to_string(),
@@ -349,72 +344,289 @@ mod tests {
             github_client_secret: "test_gh_client_secret".to_string(),
             session_secret: "test_session_secret".to_string(),
             base_url: "http://localhost:3000".to_string(),
+            ..Config::default()
         };
+        configure(&mut config, &mock_server);
 
         let oauth2_config = OAuth2Config::new(&config).unwrap();
-        let auth_service = AuthService::new(oauth2_config, user_repo);
+        let auth_service = AuthService::new(oauth2_config, user_repo, login_states);
 
-        (auth_service, mock_server)
+        (auth_service, mock_server, temp_file)
     }
 
     #[tokio::test]
     async fn test_initiate_microsoft_auth() {
-        let (auth_service, _mock_server) = setup_test_auth_service().await;
+        let (auth_service, _mock_server, _db_file) = setup_test_auth_service().await;
 
-        let result = auth_service.initiate_microsoft_auth();
+        let result = auth_service.initiate_auth("microsoft", None).await;
         assert!(result.is_ok());
 
-        let (auth_url, csrf_token) = result.unwrap();
-        assert!(auth_url.contains("login.microsoftonline.com"));
-        assert!(auth_url.contains("client_id=test_ms_client_id"));
-        assert!(auth_url.contains("scope=openid") && auth_url.contains("profile") && auth_url.contains("email"));
-        assert!(!csrf_token.secret().is_empty());
+        let request = result.unwrap();
+        assert!(request.url.contains("login.microsoftonline.com"));
+        assert!(request.url.contains("client_id=test_ms_client_id"));
+        assert!(request.url.contains("scope=openid") && request.url.contains("profile") && request.url.contains("email"));
+        assert!(request.url.contains("code_challenge_method=S256"));
+        assert!(!request.csrf_token.secret().is_empty());
+        assert!(!request.pkce_verifier.secret().is_empty());
     }
 
     #[tokio::test]
     async fn test_initiate_github_auth() {
-        let (auth_service, _mock_server) = setup_test_auth_service().await;
+        let (auth_service, _mock_server, _db_file) = setup_test_auth_service().await;
 
-        let result = auth_service.initiate_github_auth();
+        let result = auth_service.initiate_auth("github", None).await;
         assert!(result.is_ok());
 
-        let (auth_url, csrf_token) = result.unwrap();
-        assert!(auth_url.contains("github.com/login/oauth/authorize"));
-        assert!(auth_url.contains("client_id=test_gh_client_id"));
-        assert!(auth_url.contains("scope=user%3Aemail"));
-        assert!(!csrf_token.secret().is_empty());
+        let request = result.unwrap();
+        assert!(request.url.contains("github.com/login/oauth/authorize"));
+        assert!(request.url.contains("client_id=test_gh_client_id"));
+        assert!(request.url.contains("scope=user%3Aemail"));
+        assert!(request.url.contains("code_challenge="));
+        assert!(!request.csrf_token.secret().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_github_callback_creates_then_signs_in_user() {
+        use wiremock::{
+            matchers::{body_string_contains, header, method, path},
+            Mock, ResponseTemplate,
+        };
+
+        let (auth_service, mock_server, _db_file) = setup_test_auth_service_with(|config, mock_server| {
+            config.github_oauth_url = format!("{}/login/oauth", mock_server.uri());
+            config.github_api_url = mock_server.uri();
+        })
+        .await;
+
+        Mock::given(method("POST"))
+            .and(path("/login/oauth/access_token"))
+            .and(body_string_contains("code=test_code"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "gh_token",
+                "token_type": "bearer"
+            })))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user"))
+            .and(header("authorization", "Bearer gh_token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 4242,
+                "login": "octocat",
+                "name": "The Octocat",
+                "email": "octocat@example.com",
+                "avatar_url": "https://example.com/octocat.png"
+            })))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let mut user_ids = Vec::new();
+        let mut changes = Vec::new();
+        for attempt in 0..2 {
+            if attempt == 1 {
+                // The address changes at GitHub between the two logins
+                let update = ProfileUpdate {
+                    email: Some(Some("old@example.com".to_string())),
+                    ..ProfileUpdate::default()
+                };
+                auth_service.user_repository.record_login(user_ids[0], &update).await.unwrap();
+            }
+
+            let request = auth_service.initiate_auth("github", None).await.unwrap();
+            let state = request.csrf_token.secret().clone();
+            let outcome = auth_service
+                .handle_callback("github", "test_code".to_string(), state.clone(), Some(state), None)
+                .await
+                .unwrap();
+
+            let CallbackOutcome::SignedIn { user, profile_changes, .. } = outcome else {
+                panic!("expected a sign-in");
+            };
+            assert_eq!(user.provider_id, "4242");
+            assert_eq!(user.username, "The Octocat");
+            assert_eq!(user.email.as_deref(), Some("octocat@example.com"));
+            user_ids.push(user.id);
+            changes.push(profile_changes);
+        }
+
+        // The second login finds the user created by the first, and reports what it refreshed
+        assert_eq!(user_ids[0], user_ids[1]);
+        assert!(changes[0].is_empty());
+        assert_eq!(changes[1], ["email"]);
+    }
+
+    #[tokio::test]
+    async fn test_callback_applies_access_policy() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, ResponseTemplate,
+        };
+
+        let (auth_service, mock_server, _db_file) = setup_test_auth_service_with(|config, mock_server| {
+            config.github_oauth_url = format!("{}/login/oauth", mock_server.uri());
+            config.github_api_url = mock_server.uri();
+        })
+        .await;
+        let auth_service = auth_service.with_access_policy(AccessPolicy::from_config(&Config {
+            denied_email_domains: vec!["example.com".to_string()],
+            ..Config::default()
+        }));
+
+        Mock::given(method("POST"))
+            .and(path("/login/oauth/access_token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "gh_token",
+                "token_type": "bearer"
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 4242,
+                "login": "octocat",
+                "name": null,
+                "email": "octocat@example.com",
+                "avatar_url": null
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let request = auth_service.initiate_auth("github", None).await.unwrap();
+        let state = request.csrf_token.secret().clone();
+        let result = auth_service
+            .handle_callback("github", "test_code".to_string(), state.clone(), Some(state), None)
+            .await;
+
+        assert!(matches!(result, Err(AppError::Auth(AuthError::AccessDenied(_)))));
+        // The denied account was never created
+        assert!(auth_service
+            .user_repository
+            .find_by_provider_id("github", "4242")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_initiate_unknown_provider() {
+        let (auth_service, _mock_server, _db_file) = setup_test_auth_service().await;
+
+        let result = auth_service.initiate_auth("myspace", None).await;
+        assert!(matches!(result, Err(AppError::Auth(AuthError::InvalidProvider(slug))) if slug == "myspace"));
+    }
+
+    #[tokio::test]
+    async fn test_end_session_url_only_with_upstream_logout() {
+        let (auth_service, _mock_server, _db_file) = setup_test_auth_service().await;
+        assert_eq!(auth_service.end_session_url("microsoft"), None);
+
+        let auth_service = auth_service.with_upstream_logout(true);
+        let url = auth_service.end_session_url("microsoft").unwrap();
+        assert!(url.ends_with("/logout?post_logout_redirect_uri=http%3A%2F%2Flocalhost%3A3000%2Flogin"));
+        // GitHub has no way to end its session from here
+        assert_eq!(auth_service.end_session_url("github"), None);
     }
 
     #[tokio::test]
     async fn test_microsoft_callback_csrf_mismatch() {
-        let (auth_service, _mock_server) = setup_test_auth_service().await;
+        let (auth_service, _mock_server, _db_file) = setup_test_auth_service().await;
 
-        let csrf_token = CsrfToken::new("expected_token".to_string());
+        let request = auth_service.initiate_auth("microsoft", None).await.unwrap();
         let result = auth_service
-            .handle_microsoft_callback(
+            .handle_callback(
+                "microsoft",
                 "test_code".to_string(),
                 "wrong_token".to_string(),
-                csrf_token,
+                Some(request.csrf_token.secret().clone()),
+                None,
             )
             .await;
 
-        assert!(matches!(result, Err(AuthError::StateMismatch)));
+        assert!(matches!(result, Err(AppError::Auth(AuthError::StateMismatch))));
     }
 
     #[tokio::test]
     async fn test_github_callback_csrf_mismatch() {
-        let (auth_service, _mock_server) = setup_test_auth_service().await;
+        let (auth_service, _mock_server, _db_file) = setup_test_auth_service().await;
 
-        let csrf_token = CsrfToken::new("expected_token".to_string());
+        let request = auth_service.initiate_auth("github", None).await.unwrap();
         let result = auth_service
-            .handle_github_callback(
+            .handle_callback(
+                "github",
                 "test_code".to_string(),
                 "wrong_token".to_string(),
-                csrf_token,
+                Some(request.csrf_token.secret().clone()),
+                None,
+            )
+            .await;
+
+        assert!(matches!(result, Err(AppError::Auth(AuthError::StateMismatch))));
+    }
+
+    #[tokio::test]
+    async fn test_callback_unknown_provider() {
+        let (auth_service, _mock_server, _db_file) = setup_test_auth_service().await;
+
+        let result = auth_service
+            .handle_callback(
+                "myspace",
+                "code".to_string(),
+                "token".to_string(),
+                Some("token".to_string()),
+                None,
+            )
+            .await;
+
+        assert!(matches!(result, Err(AppError::Auth(AuthError::InvalidProvider(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_callback_without_session_state_is_rejected() {
+        let (auth_service, _mock_server, _db_file) = setup_test_auth_service().await;
+
+        // A valid state whose session binding was lost must not fall back to trusting the query
+        let request = auth_service.initiate_auth("github", None).await.unwrap();
+        let result = auth_service
+            .handle_callback(
+                "github",
+                "test_code".to_string(),
+                request.csrf_token.secret().clone(),
+                None,
+                None,
             )
             .await;
 
-        assert!(matches!(result, Err(AuthError::StateMismatch)));
+        assert!(matches!(result, Err(AppError::Auth(AuthError::StateMismatch))));
+    }
+
+    #[tokio::test]
+    async fn test_callback_state_for_other_provider_is_rejected() {
+        let (auth_service, _mock_server, _db_file) = setup_test_auth_service().await;
+
+        let request = auth_service.initiate_auth("github", None).await.unwrap();
+        let state = request.csrf_token.secret().clone();
+        let result = auth_service
+            .handle_callback("microsoft", "test_code".to_string(), state.clone(), Some(state), None)
+            .await;
+
+        assert!(matches!(result, Err(AppError::Auth(AuthError::StateMismatch))));
+    }
+
+    #[tokio::test]
+    async fn test_link_callback_requires_linking_user_session() {
+        let (auth_service, _mock_server, _db_file) = setup_test_auth_service().await;
+
+        // A link started by user 1 cannot be completed by another (or no) signed-in user
+        let request = auth_service.initiate_link("github", 1).await.unwrap();
+        let state = request.csrf_token.secret().clone();
+        let result = auth_service
+            .handle_callback("github", "test_code".to_string(), state.clone(), Some(state), Some(2))
+            .await;
+
+        assert!(matches!(result, Err(AppError::Auth(AuthError::StateMismatch))));
     }
 
     #[test]
@@ -427,67 +639,15 @@ mod tests {
             github_client_secret: "gh_client_secret".to_string(),
             session_secret: "session_secret".to_string(),
             base_url: "http://localhost:3000".to_string(),
+            ..Config::default()
         };
 
         let oauth2_config = OAuth2Config::new(&config);
         assert!(oauth2_config.is_ok());
-    }
-
-    #[test]
-    fn test_microsoft_user_profile_deserialization() {
-        let json = r#"{
-            "id": "12345",
-            "displayName": "Test User",
-            "userPrincipalName": "test@example.com",
-            "mail": "test@example.com"
-        }"#;
-
-        let profile: Result<MicrosoftUserProfile, _> = serde_json::from_str(json);
-        assert!(profile.is_ok());
-
-        let profile = profile.unwrap();
-        assert_eq!(profile.id, "12345");
-        assert_eq!(profile.display_name, Some("Test User".to_string()));
-        assert_eq!(profile.user_principal_name, Some("test@example.com".to_string()));
-        assert_eq!(profile.mail, Some("test@example.com".to_string()));
-    }
 
-    #[test]
-    fn test_github_user_profile_deserialization() {
-        let json = r#"{
-            "id": 12345,
-            "login": "testuser",
-            "name": "Test User",
-            "email": "test@example.com",
-            "avatar_url": "https://example.com/avatar.jpg"
-        }"#;
-
-        let profile: Result<GitHubUserProfile, _> = serde_json::from_str(json);
-        assert!(profile.is_ok());
-
-        let profile = profile.unwrap();
-        assert_eq!(profile.id, 12345);
-        assert_eq!(profile.login, "testuser");
-        assert_eq!(profile.name, Some("Test User".to_string()));
-        assert_eq!(profile.email, Some("test@example.com".to_string()));
-        assert_eq!(profile.avatar_url, Some("https://example.com/avatar.jpg".to_string()));
+        let oauth2_config = oauth2_config.unwrap();
+        assert_eq!(oauth2_config.provider("microsoft").unwrap().slug(), "microsoft");
+        assert_eq!(oauth2_config.provider("github").unwrap().slug(), "github");
+        assert!(oauth2_config.provider("google").is_err());
     }
-
-    #[test]
-    fn test_github_email_deserialization() {
-        let json = r#"[{
-            "email": "test@example.com",
-            "primary": true,
-            "verified": true
-        }]"#;
-
-        let emails: Result<Vec<GitHubEmail>, _> = serde_json::from_str(json);
-        assert!(emails.is_ok());
-
-        let emails = emails.unwrap();
-        assert_eq!(emails.len(), 1);
-        assert_eq!(emails[0].email, "test@example.com");
-        assert!(emails[0].primary);
-        assert!(emails[0].verified);
-    }
-}
\ No newline at end of file
+}
@@ -0,0 +1,181 @@
+use crate::{config::Config, error::AuthError};
+
+use super::ProviderProfile;
+
+/// Email rules deciding who may sign in, checked after the provider profile is
+/// fetched on every login (before a new user is created, and again each time
+/// an existing user returns).
+///
+/// Blocked addresses and denied domains always win; when an allow-list of
+/// domains is configured, only verified addresses in those domains get through.
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicy {
+    allowed_domains: Vec<String>,
+    denied_domains: Vec<String>,
+    blocked_emails: Vec<String>,
+    require_verified_email: bool,
+}
+
+impl AccessPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        let lowercase = |values: &[String]| -> Vec<String> {
+            values.iter().map(|value| value.to_ascii_lowercase()).collect()
+        };
+
+        Self {
+            allowed_domains: lowercase(&config.allowed_email_domains),
+            denied_domains: lowercase(&config.denied_email_domains),
+            blocked_emails: lowercase(&config.blocked_emails),
+            require_verified_email: config.require_verified_email,
+        }
+    }
+
+    /// Allow or deny a sign-in with `profile` from `provider`, logging the decision.
+    pub fn check(&self, provider: &str, profile: &ProviderProfile) -> Result<(), AuthError> {
+        match self.denial_reason(profile) {
+            None => {
+                tracing::info!(
+                    target: "audit",
+                    provider,
+                    provider_id = %profile.provider_id,
+                    email = ?profile.email,
+                    "Access policy allowed sign-in"
+                );
+                Ok(())
+            }
+            Some(reason) => {
+                tracing::warn!(
+                    target: "audit",
+                    provider,
+                    provider_id = %profile.provider_id,
+                    email = ?profile.email,
+                    reason,
+                    "Access policy denied sign-in"
+                );
+                Err(AuthError::AccessDenied(reason.to_string()))
+            }
+        }
+    }
+
+    fn denial_reason(&self, profile: &ProviderProfile) -> Option<&'static str> {
+        let email = profile.email.as_deref().map(str::to_ascii_lowercase);
+
+        if let Some(email) = &email {
+            if self.blocked_emails.contains(email) {
+                return Some("email address is blocked");
+            }
+        }
+
+        // Anyone could claim an allowed domain with an address nobody checked
+        let require_verified = self.require_verified_email || !self.allowed_domains.is_empty();
+        if require_verified && email.is_some() && !profile.email_verified {
+            return Some("a verified email address is required");
+        }
+        if self.require_verified_email && email.is_none() {
+            return Some("a verified email address is required");
+        }
+
+        let domain = email
+            .as_deref()
+            .and_then(|email| email.rsplit_once('@'))
+            .map(|(_, domain)| domain);
+
+        match domain {
+            Some(domain) if self.denied_domains.iter().any(|denied| denied == domain) => {
+                Some("email domain is denied")
+            }
+            Some(domain)
+                if !self.allowed_domains.is_empty()
+                    && !self.allowed_domains.iter().any(|allowed| allowed == domain) =>
+            {
+                Some("email domain is not allowed")
+            }
+            None if !self.allowed_domains.is_empty() => Some("an email address is required"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(email: Option<&str>, email_verified: bool) -> ProviderProfile {
+        ProviderProfile {
+            provider_id: "12345".to_string(),
+            username: "octocat".to_string(),
+            email: email.map(str::to_string),
+            email_verified,
+            avatar_url: None,
+        }
+    }
+
+    fn policy(configure: impl FnOnce(&mut Config)) -> AccessPolicy {
+        let mut config = Config::default();
+        configure(&mut config);
+        AccessPolicy::from_config(&config)
+    }
+
+    #[test]
+    fn test_default_policy_allows_everyone() {
+        let policy = AccessPolicy::default();
+        assert!(policy.check("github", &profile(Some("a@example.com"), false)).is_ok());
+        assert!(policy.check("github", &profile(None, false)).is_ok());
+    }
+
+    #[test]
+    fn test_allowed_domains() {
+        let policy = policy(|config| config.allowed_email_domains = vec!["Example.com".to_string()]);
+
+        assert!(policy.check("github", &profile(Some("a@EXAMPLE.com"), true)).is_ok());
+        assert!(matches!(
+            policy.check("github", &profile(Some("a@example.org"), true)),
+            Err(AuthError::AccessDenied(_))
+        ));
+        // Subdomains and look-alikes are different domains
+        assert!(policy.check("github", &profile(Some("a@mail.example.com"), true)).is_err());
+        assert!(policy.check("github", &profile(Some("a@notexample.com"), true)).is_err());
+        assert!(policy.check("github", &profile(None, false)).is_err());
+    }
+
+    #[test]
+    fn test_allowed_domains_require_a_verified_email() {
+        let policy = policy(|config| config.allowed_email_domains = vec!["example.com".to_string()]);
+
+        assert!(matches!(
+            policy.check("microsoft", &profile(Some("ceo@example.com"), false)),
+            Err(AuthError::AccessDenied(reason)) if reason.contains("verified")
+        ));
+    }
+
+    #[test]
+    fn test_denied_domains_and_blocked_emails() {
+        let policy = policy(|config| {
+            config.denied_email_domains = vec!["spam.example".to_string()];
+            config.blocked_emails = vec!["Mallory@example.com".to_string()];
+        });
+
+        assert!(policy.check("github", &profile(Some("a@spam.example"), true)).is_err());
+        assert!(policy.check("github", &profile(Some("mallory@example.com"), true)).is_err());
+        assert!(policy.check("github", &profile(Some("alice@example.com"), true)).is_ok());
+    }
+
+    #[test]
+    fn test_blocked_email_wins_over_allowed_domain() {
+        let policy = policy(|config| {
+            config.allowed_email_domains = vec!["example.com".to_string()];
+            config.blocked_emails = vec!["mallory@example.com".to_string()];
+        });
+
+        assert!(policy.check("github", &profile(Some("mallory@example.com"), true)).is_err());
+    }
+
+    #[test]
+    fn test_require_verified_email() {
+        let policy = policy(|config| config.require_verified_email = true);
+
+        assert!(policy.check("oidc", &profile(Some("a@example.com"), true)).is_ok());
+        assert!(policy.check("oidc", &profile(Some("a@example.com"), false)).is_err());
+        assert!(policy.check("oidc", &profile(None, false)).is_err());
+    }
+}
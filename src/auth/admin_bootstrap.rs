@@ -0,0 +1,121 @@
+use crate::{
+    config::Config,
+    database::UserRepository,
+    error::AppError,
+    models::{Role, User},
+};
+
+/// Rules that make a signing-in user an admin without an existing admin's help.
+///
+/// Configured addresses are matched against the provider's verified email on
+/// every sign-in; the first-user rule only fires while nobody is admin.
+#[derive(Debug, Clone, Default)]
+pub struct AdminBootstrap {
+    emails: Vec<String>,
+    first_user: bool,
+}
+
+impl AdminBootstrap {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            emails: config
+                .admin_emails
+                .iter()
+                .map(|email| email.to_ascii_lowercase())
+                .collect(),
+            first_user: config.admin_bootstrap_first_user,
+        }
+    }
+
+    /// Grant `user` the admin role if a rule applies to them.
+    ///
+    /// `verified_email` must come from `ProviderProfile::verified_email`; an
+    /// address the provider does not vouch for would let anyone claim one.
+    pub async fn apply(
+        &self,
+        user_repository: &UserRepository,
+        user: &User,
+        verified_email: Option<&str>,
+    ) -> Result<(), AppError> {
+        let configured = verified_email
+            .map(str::to_ascii_lowercase)
+            .is_some_and(|email| self.emails.contains(&email));
+
+        let (granted, reason) = if configured {
+            (user_repository.grant_role(user.id, Role::Admin).await?, "configured admin email")
+        } else if self.first_user {
+            (user_repository.grant_initial_admin(user.id).await?, "first user")
+        } else {
+            (false, "")
+        };
+
+        if granted {
+            tracing::info!(
+                target: "audit",
+                user_id = user.id,
+                role = Role::Admin.as_str(),
+                reason,
+                "Role granted"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::Database, models::CreateUser};
+    use tempfile::NamedTempFile;
+
+    async fn setup() -> (UserRepository, User, User, NamedTempFile) {
+        let temp_file = NamedTempFile::new().unwrap();
+        let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
+        let db = Database::new(&database_url).await.unwrap();
+        let repo = UserRepository::new(db.pool().clone());
+
+        let create = |provider_id: &str| CreateUser {
+            provider: "github".to_string(),
+            provider_id: provider_id.to_string(),
+            username: provider_id.to_string(),
+            email: None,
+            avatar_url: None,
+        };
+        let first = repo.create_user(create("gh-1")).await.unwrap();
+        let second = repo.create_user(create("gh-2")).await.unwrap();
+        (repo, first, second, temp_file)
+    }
+
+    #[tokio::test]
+    async fn test_configured_email_becomes_admin() {
+        let (repo, _first, second, _db_file) = setup().await;
+        let bootstrap = AdminBootstrap::from_config(&Config {
+            admin_emails: vec!["Boss@Example.com".to_string()],
+            ..Config::default()
+        });
+
+        bootstrap.apply(&repo, &second, Some("someone@example.com")).await.unwrap();
+        assert!(repo.list_roles(second.id).await.unwrap().is_empty());
+
+        bootstrap.apply(&repo, &second, Some("boss@example.com")).await.unwrap();
+        assert_eq!(repo.list_roles(second.id).await.unwrap(), vec![Role::Admin]);
+    }
+
+    #[tokio::test]
+    async fn test_first_user_becomes_admin_only_when_enabled() {
+        let (repo, first, second, _db_file) = setup().await;
+
+        AdminBootstrap::default().apply(&repo, &first, None).await.unwrap();
+        assert!(repo.list_roles(first.id).await.unwrap().is_empty());
+
+        let bootstrap = AdminBootstrap::from_config(&Config {
+            admin_bootstrap_first_user: true,
+            ..Config::default()
+        });
+        bootstrap.apply(&repo, &second, None).await.unwrap();
+        bootstrap.apply(&repo, &first, None).await.unwrap();
+        assert!(repo.list_roles(second.id).await.unwrap().is_empty());
+        assert_eq!(repo.list_roles(first.id).await.unwrap(), vec![Role::Admin]);
+    }
+}
@@ -0,0 +1,412 @@
+use axum::async_trait;
+use oauth2::{
+    basic::BasicClient, AuthUrl, ClientId, ClientSecret, PkceCodeVerifier, RedirectUrl,
+    TokenUrl,
+};
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+
+use super::provider::{
+    pkce_authorize_url, pkce_exchange_code, AuthorizationRequest, IdentityProvider,
+    ProviderProfile,
+};
+use crate::{
+    config::Config,
+    error::{AppError, AuthError},
+};
+
+// GitHub API user profile response
+#[derive(Debug, Deserialize)]
+pub struct GitHubUserProfile {
+    pub id: u64,
+    pub login: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+// GitHub API email response
+#[derive(Debug, Deserialize)]
+pub struct GitHubEmail {
+    pub email: String,
+    pub primary: bool,
+    pub verified: bool,
+}
+
+// GitHub API organization (from `/user/orgs`)
+#[derive(Debug, Deserialize)]
+pub struct GitHubOrg {
+    pub login: String,
+}
+
+// GitHub API team membership (from `/orgs/{org}/teams/{team}/memberships/{user}`)
+#[derive(Debug, Deserialize)]
+pub struct GitHubTeamMembership {
+    pub state: String,
+}
+
+impl From<GitHubUserProfile> for ProviderProfile {
+    fn from(profile: GitHubUserProfile) -> Self {
+        ProviderProfile {
+            provider_id: profile.id.to_string(),
+            username: profile.name.unwrap_or(profile.login),
+            // GitHub only shows verified addresses publicly, and only verified ones are taken from /user/emails
+            email_verified: profile.email.is_some(),
+            email: profile.email,
+            avatar_url: profile.avatar_url,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GitHubProvider {
+    client: BasicClient,
+    http_client: HttpClient,
+    api_url: String,
+    allowed_orgs: Vec<String>,
+    // (org, team slug) pairs
+    allowed_teams: Vec<(String, String)>,
+}
+
+impl GitHubProvider {
+    pub fn new(config: &Config, http_client: HttpClient) -> Result<Self, AppError> {
+        let oauth_url = config.github_oauth_url.trim_end_matches('/');
+        let client = BasicClient::new(
+            ClientId::new(config.github_client_id.clone()),
+            Some(ClientSecret::new(config.github_client_secret.clone())),
+            AuthUrl::new(format!("{}/authorize", oauth_url))
+                .map_err(|_| AppError::Config(std::env::VarError::NotPresent))?,
+            Some(
+                TokenUrl::new(format!("{}/access_token", oauth_url))
+                    .map_err(|_| AppError::Config(std::env::VarError::NotPresent))?,
+            ),
+        )
+        .set_redirect_uri(
+            RedirectUrl::new(format!("{}/auth/callback/github", config.base_url))
+                .map_err(|_| AppError::Config(std::env::VarError::NotPresent))?,
+        );
+
+        Ok(Self {
+            client,
+            http_client,
+            api_url: config.github_api_url.trim_end_matches('/').to_string(),
+            allowed_orgs: config.github_allowed_orgs.clone(),
+            allowed_teams: config
+                .github_allowed_teams
+                .iter()
+                .filter_map(|team| team.split_once('/'))
+                .map(|(org, team)| (org.to_string(), team.to_string()))
+                .collect(),
+        })
+    }
+
+    fn restricts_membership(&self) -> bool {
+        !self.allowed_orgs.is_empty() || !self.allowed_teams.is_empty()
+    }
+
+    fn api_get(&self, path: &str, access_token: &str) -> reqwest::RequestBuilder {
+        self.http_client
+            .get(format!("{}{}", self.api_url, path))
+            .bearer_auth(access_token)
+            .header("User-Agent", "sso-web-app")
+    }
+
+    // Whether `login` belongs to an allowed organization or team
+    async fn is_member(&self, access_token: &str, login: &str) -> Result<bool, AuthError> {
+        if !self.allowed_orgs.is_empty() {
+            let response = self
+                .api_get("/user/orgs?per_page=100", access_token)
+                .send()
+                .await
+                .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+            if !response.status().is_success() {
+                return Err(AuthError::ProfileFetch(format!(
+                    "HTTP {} listing organizations",
+                    response.status()
+                )));
+            }
+
+            let orgs: Vec<GitHubOrg> = response
+                .json()
+                .await
+                .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+            if orgs.iter().any(|org| {
+                self.allowed_orgs
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(&org.login))
+            }) {
+                return Ok(true);
+            }
+        }
+
+        for (org, team) in &self.allowed_teams {
+            let response = self
+                .api_get(
+                    &format!("/orgs/{}/teams/{}/memberships/{}", org, team, login),
+                    access_token,
+                )
+                .send()
+                .await
+                .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+            // GitHub answers 404 when the user is not on the team
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                continue;
+            }
+            if !response.status().is_success() {
+                return Err(AuthError::ProfileFetch(format!(
+                    "HTTP {} checking team membership",
+                    response.status()
+                )));
+            }
+
+            let membership: GitHubTeamMembership = response
+                .json()
+                .await
+                .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+            // Pending invitations do not count
+            if membership.state == "active" {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for GitHubProvider {
+    fn slug(&self) -> &str {
+        "github"
+    }
+
+    fn display_name(&self) -> &str {
+        "GitHub"
+    }
+
+    fn authorize_url(&self) -> AuthorizationRequest {
+        if self.restricts_membership() {
+            // Private organization and team memberships are only visible with read:org
+            pkce_authorize_url(&self.client, &["user:email", "read:org"])
+        } else {
+            pkce_authorize_url(&self.client, &["user:email"])
+        }
+    }
+
+    async fn exchange_code(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+    ) -> Result<String, AuthError> {
+        pkce_exchange_code(&self.client, code, pkce_verifier).await
+    }
+
+    async fn fetch_profile(&self, access_token: &str) -> Result<ProviderProfile, AuthError> {
+        // Fetch user profile from GitHub API
+        let profile_response = self
+            .api_get("/user", access_token)
+            .send()
+            .await
+            .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+        if !profile_response.status().is_success() {
+            return Err(AuthError::ProfileFetch(format!(
+                "HTTP {}",
+                profile_response.status()
+            )));
+        }
+
+        let mut profile: GitHubUserProfile = profile_response
+            .json()
+            .await
+            .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+        if self.restricts_membership() && !self.is_member(access_token, &profile.login).await? {
+            tracing::warn!(login = %profile.login, "GitHub user is not in an allowed organization or team");
+            return Err(AuthError::AccessDenied(format!(
+                "GitHub user {} is not in an allowed organization or team",
+                profile.login
+            )));
+        }
+
+        // If email is not public, fetch it from the emails endpoint
+        if profile.email.is_none() {
+            let emails_response = self
+                .api_get("/user/emails", access_token)
+                .send()
+                .await
+                .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+            if emails_response.status().is_success() {
+                let emails: Vec<GitHubEmail> = emails_response
+                    .json()
+                    .await
+                    .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+                // Find primary verified email
+                profile.email = emails
+                    .into_iter()
+                    .find(|email| email.primary && email.verified)
+                    .map(|email| email.email);
+            }
+        }
+
+        Ok(profile.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    // Provider whose API is served by `mock_server`, restricted to the given orgs and teams
+    async fn gated_provider(mock_server: &MockServer, orgs: &[&str], teams: &[&str]) -> GitHubProvider {
+        Mock::given(method("GET"))
+            .and(path("/user"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": 42,
+                "login": "octocat",
+                "name": "The Octocat",
+                "email": "octocat@example.com",
+                "avatar_url": null
+            })))
+            .mount(mock_server)
+            .await;
+
+        let config = Config {
+            github_api_url: mock_server.uri(),
+            github_allowed_orgs: orgs.iter().map(|org| org.to_string()).collect(),
+            github_allowed_teams: teams.iter().map(|team| team.to_string()).collect(),
+            ..Config::default()
+        };
+        GitHubProvider::new(&config, HttpClient::new()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_org_member_is_allowed() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/user/orgs"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "login": "other-org" },
+                { "login": "Acme" }
+            ])))
+            .mount(&mock_server)
+            .await;
+
+        let provider = gated_provider(&mock_server, &["acme"], &[]).await;
+        let profile = provider.fetch_profile("token").await.unwrap();
+        assert_eq!(profile.provider_id, "42");
+    }
+
+    #[tokio::test]
+    async fn test_team_member_is_allowed() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/orgs/acme/teams/platform/memberships/octocat"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "state": "active" })))
+            .mount(&mock_server)
+            .await;
+
+        let provider = gated_provider(&mock_server, &[], &["acme/security", "acme/platform"]).await;
+        assert!(provider.fetch_profile("token").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_non_member_is_denied() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/user/orgs"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{ "login": "other-org" }])))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/orgs/acme/teams/platform/memberships/octocat"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "state": "pending" })))
+            .mount(&mock_server)
+            .await;
+
+        // Not in the org, and only invited to the team
+        let provider = gated_provider(&mock_server, &["acme"], &["acme/platform"]).await;
+        let result = provider.fetch_profile("token").await;
+        assert!(matches!(result, Err(AuthError::AccessDenied(_))));
+
+        // Unknown teams answer 404, which is also a denial rather than an error
+        let provider = gated_provider(&mock_server, &[], &["acme/unknown"]).await;
+        let result = provider.fetch_profile("token").await;
+        assert!(matches!(result, Err(AuthError::AccessDenied(_))));
+    }
+
+    #[tokio::test]
+    async fn test_gating_requests_read_org_scope() {
+        let mock_server = MockServer::start().await;
+
+        let provider = gated_provider(&mock_server, &["acme"], &[]).await;
+        assert!(provider.authorize_url().url.contains("scope=user%3Aemail+read%3Aorg"));
+
+        let provider = gated_provider(&mock_server, &[], &[]).await;
+        assert!(!provider.authorize_url().url.contains("read%3Aorg"));
+    }
+
+    #[test]
+    fn test_github_user_profile_deserialization() {
+        let json = r#"{
+            "id": 12345,
+            "login": "testuser",
+            "name": "Test User",
+            "email": "test@example.com",
+            "avatar_url": "https://example.com/avatar.jpg"
+        }"#;
+
+        let profile: Result<GitHubUserProfile, _> = serde_json::from_str(json);
+        assert!(profile.is_ok());
+
+        let profile = profile.unwrap();
+        assert_eq!(profile.id, 12345);
+        assert_eq!(profile.login, "testuser");
+        assert_eq!(profile.name, Some("Test User".to_string()));
+        assert_eq!(profile.email, Some("test@example.com".to_string()));
+        assert_eq!(profile.avatar_url, Some("https://example.com/avatar.jpg".to_string()));
+    }
+
+    #[test]
+    fn test_github_email_deserialization() {
+        let json = r#"[{
+            "email": "test@example.com",
+            "primary": true,
+            "verified": true
+        }]"#;
+
+        let emails: Result<Vec<GitHubEmail>, _> = serde_json::from_str(json);
+        assert!(emails.is_ok());
+
+        let emails = emails.unwrap();
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].email, "test@example.com");
+        assert!(emails[0].primary);
+        assert!(emails[0].verified);
+    }
+
+    #[test]
+    fn test_github_profile_normalization_falls_back_to_login() {
+        let profile = GitHubUserProfile {
+            id: 42,
+            login: "octocat".to_string(),
+            name: None,
+            email: None,
+            avatar_url: Some("https://example.com/avatar.jpg".to_string()),
+        };
+
+        let normalized = ProviderProfile::from(profile);
+        assert_eq!(normalized.provider_id, "42");
+        assert_eq!(normalized.username, "octocat");
+        assert_eq!(normalized.avatar_url, Some("https://example.com/avatar.jpg".to_string()));
+    }
+}
@@ -0,0 +1,256 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::sqlite::SqlitePool;
+
+use crate::{
+    config::LoginStateMode,
+    error::{AppError, AuthError},
+};
+
+// How long a login may take between redirect and callback
+const LOGIN_STATE_TTL_MINUTES: i64 = 10;
+
+// Server-side record of an in-flight OAuth2 login
+#[derive(Debug, Clone)]
+pub struct LoginState {
+    pub state: String,
+    pub provider: String,
+    pub pkce_verifier: String,
+    pub return_url: Option<String>,
+    // Set when a signed-in user is linking another account
+    pub link_user_id: Option<i64>,
+    // OpenID Connect nonce expected in the ID token
+    pub nonce: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl sqlx::FromRow<'_, sqlx::sqlite::SqliteRow> for LoginState {
+    fn from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        use sqlx::Row;
+
+        let created_at_str: String = row.try_get("created_at")?;
+        let created_at = DateTime::parse_from_rfc3339(&created_at_str)
+            .map_err(|e| sqlx::Error::ColumnDecode {
+                index: "created_at".to_string(),
+                source: Box::new(e),
+            })?
+            .with_timezone(&Utc);
+
+        Ok(LoginState {
+            state: row.try_get("state")?,
+            provider: row.try_get("provider")?,
+            pkce_verifier: row.try_get("pkce_verifier")?,
+            return_url: row.try_get("return_url")?,
+            link_user_id: row.try_get("link_user_id")?,
+            nonce: row.try_get("nonce")?,
+            created_at,
+        })
+    }
+}
+
+/// One-time, expiring store for OAuth2 login state.
+///
+/// A record is written when the login starts and deleted by the first callback
+/// that presents its state, so a replayed or forged state never validates.
+#[derive(Debug, Clone)]
+pub struct LoginStateStore {
+    pool: SqlitePool,
+    mode: LoginStateMode,
+    ttl: Duration,
+}
+
+impl LoginStateStore {
+    pub fn new(pool: SqlitePool, mode: LoginStateMode) -> Self {
+        Self {
+            pool,
+            mode,
+            ttl: Duration::minutes(LOGIN_STATE_TTL_MINUTES),
+        }
+    }
+
+    pub async fn insert(&self, login_state: &LoginState) -> Result<(), AppError> {
+        // Opportunistically drop abandoned logins
+        self.delete_expired().await?;
+
+        sqlx::query(
+            "INSERT INTO login_states (state, provider, pkce_verifier, return_url, link_user_id, nonce, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(&login_state.state)
+        .bind(&login_state.provider)
+        .bind(&login_state.pkce_verifier)
+        .bind(&login_state.return_url)
+        .bind(login_state.link_user_id)
+        .bind(&login_state.nonce)
+        .bind(login_state.created_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Consume the record for `state`, failing with `StateMismatch` if it is
+    /// unknown, already used, expired, issued for another provider, or not
+    /// bound to the current session.
+    ///
+    /// Only a matching callback deletes the record, so a forged one from
+    /// another browser cannot use up the real user's state.
+    pub async fn consume(
+        &self,
+        state: &str,
+        provider: &str,
+        session_state: Option<&str>,
+    ) -> Result<LoginState, AppError> {
+        let login_state = sqlx::query_as::<_, LoginState>(
+            "DELETE FROM login_states
+             WHERE state = ?1 AND provider = ?2 AND (state = ?3 OR (?3 IS NULL AND ?4))
+             RETURNING state, provider, pkce_verifier, return_url, link_user_id, nonce, created_at"
+        )
+        .bind(state)
+        .bind(provider)
+        .bind(session_state)
+        .bind(self.mode == LoginStateMode::Unbound)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(login_state) = login_state else {
+            tracing::warn!(
+                "Unknown or already used login state, or one issued for another provider or browser session"
+            );
+            return Err(AuthError::StateMismatch.into());
+        };
+
+        if Utc::now() - login_state.created_at > self.ttl {
+            tracing::warn!("Expired login state for provider {}", login_state.provider);
+            return Err(AuthError::StateMismatch.into());
+        }
+
+        if session_state.is_none() {
+            tracing::debug!("Accepting login state without session binding (unbound mode)");
+        }
+
+        Ok(login_state)
+    }
+
+    pub async fn delete_expired(&self) -> Result<u64, AppError> {
+        let cutoff = Utc::now() - self.ttl;
+
+        let result = sqlx::query("DELETE FROM login_states WHERE created_at < ?")
+            .bind(cutoff.to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use tempfile::NamedTempFile;
+
+    async fn setup_test_store(mode: LoginStateMode) -> (LoginStateStore, NamedTempFile) {
+        let temp_file = NamedTempFile::new().unwrap();
+        let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
+        let db = Database::new(&database_url).await.unwrap();
+        (LoginStateStore::new(db.pool().clone(), mode), temp_file)
+    }
+
+    fn login_state(state: &str, provider: &str) -> LoginState {
+        LoginState {
+            state: state.to_string(),
+            provider: provider.to_string(),
+            pkce_verifier: "verifier".to_string(),
+            return_url: None,
+            link_user_id: None,
+            nonce: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_consume_is_one_time() {
+        let (store, _db_file) = setup_test_store(LoginStateMode::Strict).await;
+        store.insert(&login_state("abc", "github")).await.unwrap();
+
+        let consumed = store.consume("abc", "github", Some("abc")).await.unwrap();
+        assert_eq!(consumed.pkce_verifier, "verifier");
+
+        // Replaying the same state must fail
+        let replay = store.consume("abc", "github", Some("abc")).await;
+        assert!(matches!(replay, Err(AppError::Auth(AuthError::StateMismatch))));
+    }
+
+    #[tokio::test]
+    async fn test_consume_unknown_state() {
+        let (store, _db_file) = setup_test_store(LoginStateMode::Strict).await;
+
+        let result = store.consume("missing", "github", Some("missing")).await;
+        assert!(matches!(result, Err(AppError::Auth(AuthError::StateMismatch))));
+    }
+
+    #[tokio::test]
+    async fn test_consume_expired_state() {
+        let (store, _db_file) = setup_test_store(LoginStateMode::Strict).await;
+        let mut expired = login_state("old", "github");
+        expired.created_at = Utc::now() - Duration::minutes(LOGIN_STATE_TTL_MINUTES + 1);
+        store.insert(&expired).await.unwrap();
+
+        let result = store.consume("old", "github", Some("old")).await;
+        assert!(matches!(result, Err(AppError::Auth(AuthError::StateMismatch))));
+    }
+
+    #[tokio::test]
+    async fn test_consume_wrong_provider() {
+        let (store, _db_file) = setup_test_store(LoginStateMode::Strict).await;
+        store.insert(&login_state("abc", "github")).await.unwrap();
+
+        let result = store.consume("abc", "microsoft", Some("abc")).await;
+        assert!(matches!(result, Err(AppError::Auth(AuthError::StateMismatch))));
+
+        // The mismatched callback leaves the state for the real one
+        assert!(store.consume("abc", "github", Some("abc")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode_requires_session_binding() {
+        let (store, _db_file) = setup_test_store(LoginStateMode::Strict).await;
+        store.insert(&login_state("abc", "github")).await.unwrap();
+        store.insert(&login_state("def", "github")).await.unwrap();
+
+        let missing = store.consume("abc", "github", None).await;
+        assert!(matches!(missing, Err(AppError::Auth(AuthError::StateMismatch))));
+
+        let other = store.consume("def", "github", Some("abc")).await;
+        assert!(matches!(other, Err(AppError::Auth(AuthError::StateMismatch))));
+
+        // Forged callbacks from other sessions did not use up the real ones
+        assert!(store.consume("abc", "github", Some("abc")).await.is_ok());
+        assert!(store.consume("def", "github", Some("def")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unbound_mode_accepts_missing_session_state() {
+        let (store, _db_file) = setup_test_store(LoginStateMode::Unbound).await;
+        store.insert(&login_state("abc", "github")).await.unwrap();
+        store.insert(&login_state("def", "github")).await.unwrap();
+
+        assert!(store.consume("abc", "github", None).await.is_ok());
+
+        // A session that holds a different state is still rejected
+        let other = store.consume("def", "github", Some("abc")).await;
+        assert!(matches!(other, Err(AppError::Auth(AuthError::StateMismatch))));
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired() {
+        let (store, _db_file) = setup_test_store(LoginStateMode::Strict).await;
+        store.insert(&login_state("new", "github")).await.unwrap();
+        let mut expired = login_state("old", "github");
+        expired.created_at = Utc::now() - Duration::minutes(LOGIN_STATE_TTL_MINUTES + 1);
+        store.insert(&expired).await.unwrap();
+
+        assert_eq!(store.delete_expired().await.unwrap(), 1);
+        assert!(store.consume("new", "github", Some("new")).await.is_ok());
+    }
+}
@@ -0,0 +1,404 @@
+use axum::async_trait;
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use oauth2::{
+    AuthUrl, ClientId, ClientSecret, PkceCodeVerifier, RedirectUrl, TokenResponse, TokenUrl,
+};
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+
+use super::{
+    oidc::OidcClient,
+    provider::{
+        pkce_authorize_url, pkce_exchange, pkce_exchange_code, AuthorizationRequest,
+        IdentityProvider, ProviderProfile,
+    },
+};
+use crate::{
+    config::{Config, MicrosoftTenant},
+    error::{AppError, AuthError},
+};
+
+// Tenant that personal Microsoft accounts belong to
+const CONSUMERS_TENANT_ID: &str = "9188040d-6c67-4c5b-b112-36a304b66dad";
+
+impl MicrosoftTenant {
+    // Path segment selecting the login.microsoftonline.com authority
+    fn authority(&self) -> &str {
+        match self {
+            MicrosoftTenant::Common => "common",
+            MicrosoftTenant::Consumers => "consumers",
+            MicrosoftTenant::Tenants(tenants) if tenants.len() == 1 => &tenants[0],
+            MicrosoftTenant::Organizations | MicrosoftTenant::Tenants(_) => "organizations",
+        }
+    }
+
+    // Whether an account from tenant `tenant_id` (the ID token's `tid`) may sign in
+    fn allows(&self, tenant_id: Option<&str>) -> bool {
+        match (self, tenant_id) {
+            (MicrosoftTenant::Common, _) => true,
+            (_, None) => false,
+            (MicrosoftTenant::Organizations, Some(tid)) => !tid.eq_ignore_ascii_case(CONSUMERS_TENANT_ID),
+            (MicrosoftTenant::Consumers, Some(tid)) => tid.eq_ignore_ascii_case(CONSUMERS_TENANT_ID),
+            (MicrosoftTenant::Tenants(tenants), Some(tid)) => {
+                tenants.iter().any(|tenant| tenant.eq_ignore_ascii_case(tid))
+            }
+        }
+    }
+
+    // Whether the operator named this tenant in MICROSOFT_TENANT_ID, and so
+    // trusts the addresses its admins assign
+    fn is_configured(&self, tenant_id: Option<&str>) -> bool {
+        matches!(self, MicrosoftTenant::Tenants(_)) && self.allows(tenant_id)
+    }
+}
+
+// The parts of a Microsoft ID token needed to enforce MICROSOFT_TENANT_ID and
+// to tell whether the email address was verified
+#[derive(Debug, Default, Deserialize)]
+struct MicrosoftIdTokenClaims {
+    tid: Option<String>,
+    email: Option<String>,
+    // Optional claim: the email domain's owner was verified by the tenant
+    xms_edov: Option<serde_json::Value>,
+}
+
+impl MicrosoftIdTokenClaims {
+    fn domain_owner_verified(&self) -> bool {
+        match &self.xms_edov {
+            Some(serde_json::Value::Bool(verified)) => *verified,
+            Some(serde_json::Value::String(verified)) => verified == "1" || verified.eq_ignore_ascii_case("true"),
+            _ => false,
+        }
+    }
+
+    // Graph's `mail` is whatever the user's tenant admin typed in, so it only
+    // counts as verified in a tenant the operator trusts, or when the ID token
+    // vouches for the same address's domain
+    fn verifies(&self, mail: &str, tenant: &MicrosoftTenant) -> bool {
+        if tenant.is_configured(self.tid.as_deref()) {
+            return true;
+        }
+        self.domain_owner_verified()
+            && self.email.as_deref().is_some_and(|email| email.eq_ignore_ascii_case(mail))
+    }
+}
+
+// Read the claims of an ID token received directly from Microsoft's token
+// endpoint; TLS authenticates the token there, so the signature is not re-checked
+fn id_token_claims(id_token: &str) -> Result<MicrosoftIdTokenClaims, AuthError> {
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    validation.required_spec_claims.clear();
+
+    decode::<MicrosoftIdTokenClaims>(id_token, &DecodingKey::from_secret(&[]), &validation)
+        .map(|data| data.claims)
+        .map_err(|e| AuthError::InvalidIdToken(e.to_string()))
+}
+
+// Microsoft Graph API user profile response
+#[derive(Debug, Deserialize)]
+pub struct MicrosoftUserProfile {
+    pub id: String,
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    pub user_principal_name: Option<String>,
+    pub mail: Option<String>,
+}
+
+impl From<MicrosoftUserProfile> for ProviderProfile {
+    fn from(profile: MicrosoftUserProfile) -> Self {
+        ProviderProfile {
+            provider_id: profile.id,
+            username: profile
+                .display_name
+                .or(profile.user_principal_name)
+                .unwrap_or_else(|| "Microsoft User".to_string()),
+            // `mail` is set by whoever administers the user's tenant; only
+            // `authenticate` can vouch for it
+            email_verified: false,
+            email: profile.mail,
+            avatar_url: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MicrosoftProvider {
+    client: OidcClient,
+    http_client: HttpClient,
+    tenant: MicrosoftTenant,
+    profile_url: String,
+    // RP-initiated logout, returning to our login page
+    logout_url: String,
+}
+
+impl MicrosoftProvider {
+    pub fn new(config: &Config, http_client: HttpClient) -> Result<Self, AppError> {
+        let authority = format!(
+            "{}/{}/oauth2/v2.0",
+            config.microsoft_login_url.trim_end_matches('/'),
+            config.microsoft_tenant.authority()
+        );
+        let client = OidcClient::new(
+            ClientId::new(config.microsoft_client_id.clone()),
+            Some(ClientSecret::new(config.microsoft_client_secret.clone())),
+            AuthUrl::new(format!("{}/authorize", authority))
+                .map_err(|_| AppError::Config(std::env::VarError::NotPresent))?,
+            Some(
+                TokenUrl::new(format!("{}/token", authority))
+                    .map_err(|_| AppError::Config(std::env::VarError::NotPresent))?,
+            ),
+        )
+        .set_redirect_uri(
+            RedirectUrl::new(format!("{}/auth/callback/microsoft", config.base_url))
+                .map_err(|_| AppError::Config(std::env::VarError::NotPresent))?,
+        );
+
+        Ok(Self {
+            client,
+            http_client,
+            tenant: config.microsoft_tenant.clone(),
+            profile_url: format!("{}/me", config.microsoft_graph_url.trim_end_matches('/')),
+            logout_url: format!(
+                "{}/logout?post_logout_redirect_uri={}",
+                authority,
+                urlencoding::encode(&format!("{}/login", config.base_url))
+            ),
+        })
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for MicrosoftProvider {
+    fn slug(&self) -> &str {
+        "microsoft"
+    }
+
+    fn display_name(&self) -> &str {
+        "Microsoft"
+    }
+
+    fn authorize_url(&self) -> AuthorizationRequest {
+        pkce_authorize_url(&self.client, &["openid", "profile", "email"])
+    }
+
+    async fn exchange_code(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+    ) -> Result<String, AuthError> {
+        pkce_exchange_code(&self.client, code, pkce_verifier).await
+    }
+
+    async fn fetch_profile(&self, access_token: &str) -> Result<ProviderProfile, AuthError> {
+        // Fetch user profile from Microsoft Graph API
+        let profile_response = self
+            .http_client
+            .get(&self.profile_url)
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+        if !profile_response.status().is_success() {
+            return Err(AuthError::ProfileFetch(format!(
+                "HTTP {}",
+                profile_response.status()
+            )));
+        }
+
+        let profile: MicrosoftUserProfile = profile_response
+            .json()
+            .await
+            .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+        Ok(profile.into())
+    }
+
+    async fn authenticate(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+        _nonce: Option<&str>,
+    ) -> Result<ProviderProfile, AuthError> {
+        let token_result = pkce_exchange(&self.client, code, pkce_verifier).await?;
+        let claims = match token_result.extra_fields().id_token.as_deref() {
+            Some(id_token) => id_token_claims(id_token)?,
+            None => MicrosoftIdTokenClaims::default(),
+        };
+
+        // The authority already narrows who can sign in; the tenant claim is the final word
+        if !self.tenant.allows(claims.tid.as_deref()) {
+            tracing::warn!(tenant = ?claims.tid, "Microsoft sign-in from a tenant that is not allowed");
+            return Err(AuthError::TenantNotAllowed(
+                claims.tid.unwrap_or_else(|| "unknown".to_string()),
+            ));
+        }
+
+        let mut profile = self.fetch_profile(token_result.access_token().secret()).await?;
+        profile.email_verified = profile
+            .email
+            .as_deref()
+            .is_some_and(|mail| claims.verifies(mail, &self.tenant));
+        Ok(profile)
+    }
+
+    fn end_session_url(&self) -> Option<String> {
+        Some(self.logout_url.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_microsoft_user_profile_deserialization() {
+        let json = r#"{
+            "id": "12345",
+            "displayName": "Test User",
+            "userPrincipalName": "test@example.com",
+            "mail": "test@example.com"
+        }"#;
+
+        let profile: Result<MicrosoftUserProfile, _> = serde_json::from_str(json);
+        assert!(profile.is_ok());
+
+        let profile = profile.unwrap();
+        assert_eq!(profile.id, "12345");
+        assert_eq!(profile.display_name, Some("Test User".to_string()));
+        assert_eq!(profile.user_principal_name, Some("test@example.com".to_string()));
+        assert_eq!(profile.mail, Some("test@example.com".to_string()));
+    }
+
+    #[test]
+    fn test_tenant_authority() {
+        assert_eq!(MicrosoftTenant::Common.authority(), "common");
+        assert_eq!(MicrosoftTenant::Consumers.authority(), "consumers");
+        assert_eq!(MicrosoftTenant::Tenants(vec!["contoso-id".to_string()]).authority(), "contoso-id");
+
+        // Several tenants cannot share an authority, so the tenant claim is checked instead
+        let tenants = MicrosoftTenant::Tenants(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(tenants.authority(), "organizations");
+    }
+
+    #[test]
+    fn test_tenant_allows() {
+        let tenants = MicrosoftTenant::Tenants(vec!["contoso-id".to_string(), "fabrikam-id".to_string()]);
+        assert!(tenants.allows(Some("fabrikam-id")));
+        assert!(tenants.allows(Some("CONTOSO-ID")));
+        assert!(!tenants.allows(Some("other-id")));
+        assert!(!tenants.allows(None));
+
+        assert!(MicrosoftTenant::Organizations.allows(Some("contoso-id")));
+        assert!(!MicrosoftTenant::Organizations.allows(Some(CONSUMERS_TENANT_ID)));
+        assert!(MicrosoftTenant::Consumers.allows(Some(CONSUMERS_TENANT_ID)));
+        assert!(!MicrosoftTenant::Consumers.allows(Some("contoso-id")));
+        assert!(MicrosoftTenant::Common.allows(None));
+    }
+
+    #[test]
+    fn test_id_token_claims() {
+        use jsonwebtoken::{encode, EncodingKey, Header};
+
+        let claims = serde_json::json!({ "tid": "contoso-id", "sub": "abc", "xms_edov": true });
+        let id_token = encode(&Header::default(), &claims, &EncodingKey::from_secret(b"unused")).unwrap();
+        let claims = id_token_claims(&id_token).unwrap();
+        assert_eq!(claims.tid.as_deref(), Some("contoso-id"));
+        assert!(claims.domain_owner_verified());
+
+        assert!(matches!(id_token_claims("not-a-jwt"), Err(AuthError::InvalidIdToken(_))));
+    }
+
+    #[test]
+    fn test_mail_from_a_foreign_tenant_is_not_verified() {
+        // Anyone can create a tenant and set `mail` to someone else's address
+        let profile = ProviderProfile::from(MicrosoftUserProfile {
+            id: "12345".to_string(),
+            display_name: Some("Mallory".to_string()),
+            user_principal_name: Some("mallory@attacker.onmicrosoft.com".to_string()),
+            mail: Some("ceo@contoso.com".to_string()),
+        });
+        assert!(!profile.email_verified);
+
+        let foreign = MicrosoftIdTokenClaims {
+            tid: Some("attacker-id".to_string()),
+            ..MicrosoftIdTokenClaims::default()
+        };
+        assert!(!foreign.verifies("ceo@contoso.com", &MicrosoftTenant::Common));
+        assert!(!foreign.verifies("ceo@contoso.com", &MicrosoftTenant::Organizations));
+
+        // The tenant's verified domain must cover the very address Graph reports
+        let domain_verified = MicrosoftIdTokenClaims {
+            email: Some("mallory@attacker.example".to_string()),
+            xms_edov: Some(serde_json::json!(true)),
+            ..foreign
+        };
+        assert!(!domain_verified.verifies("ceo@contoso.com", &MicrosoftTenant::Common));
+        assert!(domain_verified.verifies("Mallory@attacker.example", &MicrosoftTenant::Common));
+    }
+
+    #[test]
+    fn test_mail_from_a_configured_tenant_is_verified() {
+        let tenant = MicrosoftTenant::Tenants(vec!["contoso-id".to_string()]);
+        let claims = MicrosoftIdTokenClaims {
+            tid: Some("contoso-id".to_string()),
+            ..MicrosoftIdTokenClaims::default()
+        };
+        assert!(claims.verifies("ceo@contoso.com", &tenant));
+
+        let other = MicrosoftIdTokenClaims {
+            tid: Some("attacker-id".to_string()),
+            ..MicrosoftIdTokenClaims::default()
+        };
+        assert!(!other.verifies("ceo@contoso.com", &tenant));
+    }
+
+    #[test]
+    fn test_single_tenant_authorize_url() {
+        let config = Config {
+            microsoft_client_id: "client".to_string(),
+            microsoft_tenant: MicrosoftTenant::Tenants(vec!["contoso-id".to_string()]),
+            ..Config::default()
+        };
+        let provider = MicrosoftProvider::new(&config, HttpClient::new()).unwrap();
+
+        let request = provider.authorize_url();
+        assert!(request
+            .url
+            .starts_with("https://login.microsoftonline.com/contoso-id/oauth2/v2.0/authorize"));
+    }
+
+    #[test]
+    fn test_end_session_url_returns_to_login() {
+        let config = Config {
+            microsoft_tenant: MicrosoftTenant::Tenants(vec!["contoso-id".to_string()]),
+            base_url: "https://sso.example.com".to_string(),
+            ..Config::default()
+        };
+        let provider = MicrosoftProvider::new(&config, HttpClient::new()).unwrap();
+
+        assert_eq!(
+            provider.end_session_url().as_deref(),
+            Some("https://login.microsoftonline.com/contoso-id/oauth2/v2.0/logout?post_logout_redirect_uri=https%3A%2F%2Fsso.example.com%2Flogin")
+        );
+    }
+
+    #[test]
+    fn test_microsoft_profile_normalization_falls_back_to_upn() {
+        let profile = MicrosoftUserProfile {
+            id: "12345".to_string(),
+            display_name: None,
+            user_principal_name: Some("test@example.com".to_string()),
+            mail: None,
+        };
+
+        let normalized = ProviderProfile::from(profile);
+        assert_eq!(normalized.provider_id, "12345");
+        assert_eq!(normalized.username, "test@example.com");
+        assert_eq!(normalized.email, None);
+    }
+}
@@ -0,0 +1,704 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::async_trait;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use oauth2::{
+    AuthUrl, ClientId, ClientSecret, CsrfToken, ExtraTokenFields, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope, TokenUrl,
+};
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use super::provider::{
+    pkce_exchange, pkce_exchange_code, AuthorizationRequest, IdentityProvider, ProviderProfile,
+    TokenClient,
+};
+use crate::{config::OidcConfig, error::AuthError};
+
+// How long fetched signing keys are trusted before the JWKS is fetched again
+const JWKS_CACHE_TTL_SECS: u64 = 3600;
+
+// Minimum gap between refetches triggered by an unknown key id
+const JWKS_MIN_REFRESH_SECS: u64 = 30;
+
+// Clock skew tolerated when checking `exp`
+const ID_TOKEN_LEEWAY_SECS: u64 = 60;
+
+// Asymmetric algorithms accepted for ID token signatures
+const ALLOWED_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+// Provider metadata served at `{issuer}/.well-known/openid-configuration`
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcDiscoveryDocument {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub userinfo_endpoint: Option<String>,
+    // RP-initiated logout, when the provider supports it
+    pub end_session_endpoint: Option<String>,
+}
+
+// Claims read from an ID token or the userinfo endpoint
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub name: Option<String>,
+    pub preferred_username: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub picture: Option<String>,
+}
+
+impl From<IdTokenClaims> for ProviderProfile {
+    fn from(claims: IdTokenClaims) -> Self {
+        ProviderProfile {
+            username: claims
+                .preferred_username
+                .or(claims.name)
+                .or_else(|| claims.email.clone())
+                .unwrap_or_else(|| claims.sub.clone()),
+            provider_id: claims.sub,
+            email_verified: claims.email.is_some() && claims.email_verified.unwrap_or(false),
+            email: claims.email,
+            avatar_url: claims.picture,
+        }
+    }
+}
+
+// Token response fields beyond plain OAuth2
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct IdTokenFields {
+    pub id_token: Option<String>,
+}
+
+impl ExtraTokenFields for IdTokenFields {}
+
+pub(crate) type OidcClient = TokenClient<IdTokenFields>;
+
+#[derive(Debug)]
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Signing keys published at a provider's `jwks_uri`.
+///
+/// Keys are cached for an hour; a token signed with an unknown key id
+/// triggers an early refetch so key rotation is picked up, rate limited so
+/// forged key ids cannot hammer the provider.
+#[derive(Debug)]
+pub struct JwksCache {
+    jwks_uri: String,
+    http_client: HttpClient,
+    cached: RwLock<Option<Arc<CachedJwks>>>,
+}
+
+impl JwksCache {
+    pub fn new(jwks_uri: String, http_client: HttpClient) -> Self {
+        Self {
+            jwks_uri,
+            http_client,
+            cached: RwLock::new(None),
+        }
+    }
+
+    /// Find the key for `kid`, fetching the key set if needed.
+    pub async fn key(&self, kid: Option<&str>) -> Result<Jwk, AuthError> {
+        let cached = self.cached.read().await.clone();
+        if let Some(cached) = cached {
+            let age = cached.fetched_at.elapsed();
+            if age < Duration::from_secs(JWKS_CACHE_TTL_SECS) {
+                if let Some(jwk) = find_key(&cached.keys, kid) {
+                    return Ok(jwk.clone());
+                }
+                if age < Duration::from_secs(JWKS_MIN_REFRESH_SECS) {
+                    return Err(AuthError::InvalidIdToken("unknown signing key".to_string()));
+                }
+            }
+        }
+
+        let keys = self.fetch().await?;
+        let jwk = find_key(&keys, kid).cloned();
+        *self.cached.write().await = Some(Arc::new(CachedJwks {
+            keys,
+            fetched_at: Instant::now(),
+        }));
+
+        jwk.ok_or_else(|| AuthError::InvalidIdToken("unknown signing key".to_string()))
+    }
+
+    async fn fetch(&self) -> Result<JwkSet, AuthError> {
+        tracing::debug!("Fetching signing keys from {}", self.jwks_uri);
+        let response = self
+            .http_client
+            .get(&self.jwks_uri)
+            .send()
+            .await
+            .map_err(|e| AuthError::InvalidIdToken(format!("JWKS fetch failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(AuthError::InvalidIdToken(format!(
+                "JWKS fetch failed: HTTP {}",
+                response.status()
+            )));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| AuthError::InvalidIdToken(format!("JWKS is malformed: {}", e)))
+    }
+}
+
+// A token without a key id may only be verified against a single-key set
+fn find_key<'a>(keys: &'a JwkSet, kid: Option<&str>) -> Option<&'a Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid),
+        None if keys.keys.len() == 1 => keys.keys.first(),
+        None => None,
+    }
+}
+
+/// Any OpenID Connect provider, configured from its discovery document.
+///
+/// The user is identified by the validated ID token returned from the token
+/// endpoint rather than by a provider-specific profile API.
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    slug: String,
+    display_name: String,
+    issuer: String,
+    client_id: String,
+    scopes: Vec<String>,
+    client: OidcClient,
+    userinfo_endpoint: Option<String>,
+    end_session_url: Option<String>,
+    jwks: Arc<JwksCache>,
+    http_client: HttpClient,
+}
+
+impl OidcProvider {
+    /// Fetch the provider's discovery document and build the provider from it.
+    pub async fn discover(
+        config: &OidcConfig,
+        base_url: &str,
+        http_client: HttpClient,
+    ) -> Result<Self, AuthError> {
+        let discovery_url = format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer_url.trim_end_matches('/')
+        );
+
+        let response = http_client
+            .get(&discovery_url)
+            .send()
+            .await
+            .map_err(|e| AuthError::Discovery(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(AuthError::Discovery(format!(
+                "HTTP {} from {}",
+                response.status(),
+                discovery_url
+            )));
+        }
+
+        let document: OidcDiscoveryDocument = response
+            .json()
+            .await
+            .map_err(|e| AuthError::Discovery(e.to_string()))?;
+
+        Self::from_discovery(config, base_url, document, http_client)
+    }
+
+    pub fn from_discovery(
+        config: &OidcConfig,
+        base_url: &str,
+        document: OidcDiscoveryDocument,
+        http_client: HttpClient,
+    ) -> Result<Self, AuthError> {
+        // The document must describe the issuer we were configured with
+        if document.issuer.trim_end_matches('/') != config.issuer_url.trim_end_matches('/') {
+            return Err(AuthError::Discovery(format!(
+                "issuer '{}' does not match configured '{}'",
+                document.issuer, config.issuer_url
+            )));
+        }
+
+        let invalid_url = |name: &str| AuthError::Discovery(format!("invalid {}", name));
+        let client = OidcClient::new(
+            ClientId::new(config.client_id.clone()),
+            Some(ClientSecret::new(config.client_secret.clone())),
+            AuthUrl::new(document.authorization_endpoint)
+                .map_err(|_| invalid_url("authorization_endpoint"))?,
+            Some(TokenUrl::new(document.token_endpoint).map_err(|_| invalid_url("token_endpoint"))?),
+        )
+        .set_redirect_uri(
+            RedirectUrl::new(format!("{}/auth/callback/{}", base_url, config.slug))
+                .map_err(|_| invalid_url("redirect URL"))?,
+        );
+
+        // Unauthenticated logout requests name the client and where to come back to
+        let end_session_url = match document.end_session_endpoint {
+            Some(endpoint) => {
+                let mut url = reqwest::Url::parse(&endpoint).map_err(|_| invalid_url("end_session_endpoint"))?;
+                url.query_pairs_mut()
+                    .append_pair("client_id", &config.client_id)
+                    .append_pair("post_logout_redirect_uri", &format!("{}/login", base_url));
+                Some(url.to_string())
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            slug: config.slug.clone(),
+            display_name: config.display_name.clone(),
+            issuer: document.issuer,
+            client_id: config.client_id.clone(),
+            scopes: config.scopes.clone(),
+            client,
+            userinfo_endpoint: document.userinfo_endpoint,
+            end_session_url,
+            jwks: Arc::new(JwksCache::new(document.jwks_uri, http_client.clone())),
+            http_client,
+        })
+    }
+
+    /// Verify an ID token's signature, issuer, audience, expiry and nonce.
+    pub async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: Option<&str>,
+    ) -> Result<IdTokenClaims, AuthError> {
+        let header =
+            decode_header(id_token).map_err(|e| AuthError::InvalidIdToken(e.to_string()))?;
+
+        // Never let the token pick a shared-secret or unsigned algorithm
+        if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+            return Err(AuthError::InvalidIdToken(format!(
+                "algorithm {:?} is not allowed",
+                header.alg
+            )));
+        }
+
+        let jwk = self.jwks.key(header.kid.as_deref()).await?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(|e| AuthError::InvalidIdToken(e.to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.leeway = ID_TOKEN_LEEWAY_SECS;
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| AuthError::InvalidIdToken(e.to_string()))?
+            .claims;
+
+        // The nonce ties the token to the login this browser started
+        match (nonce, claims.nonce.as_deref()) {
+            (Some(expected), Some(actual)) if expected == actual => Ok(claims),
+            _ => Err(AuthError::InvalidIdToken("nonce mismatch".to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for OidcProvider {
+    fn slug(&self) -> &str {
+        &self.slug
+    }
+
+    fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    fn authorize_url(&self) -> AuthorizationRequest {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let nonce = CsrfToken::new_random().secret().clone();
+
+        let (auth_url, csrf_token) = self
+            .client
+            .authorize_url(CsrfToken::new_random)
+            .add_scopes(self.scopes.iter().cloned().map(Scope::new))
+            .add_extra_param("nonce", nonce.clone())
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+
+        AuthorizationRequest {
+            url: auth_url.to_string(),
+            csrf_token,
+            pkce_verifier,
+            nonce: Some(nonce),
+        }
+    }
+
+    async fn exchange_code(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+    ) -> Result<String, AuthError> {
+        pkce_exchange_code(&self.client, code, pkce_verifier).await
+    }
+
+    async fn fetch_profile(&self, access_token: &str) -> Result<ProviderProfile, AuthError> {
+        let userinfo_endpoint = self.userinfo_endpoint.as_deref().ok_or_else(|| {
+            AuthError::ProfileFetch("provider has no userinfo endpoint".to_string())
+        })?;
+
+        let response = self
+            .http_client
+            .get(userinfo_endpoint)
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(AuthError::ProfileFetch(format!("HTTP {}", response.status())));
+        }
+
+        let claims: IdTokenClaims = response
+            .json()
+            .await
+            .map_err(|e| AuthError::ProfileFetch(e.to_string()))?;
+
+        Ok(claims.into())
+    }
+
+    async fn authenticate(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+        nonce: Option<&str>,
+    ) -> Result<ProviderProfile, AuthError> {
+        let token_result = pkce_exchange(&self.client, code, pkce_verifier).await?;
+
+        let id_token = token_result.extra_fields().id_token.as_deref().ok_or_else(|| {
+            AuthError::InvalidIdToken("token response has no id_token".to_string())
+        })?;
+
+        let claims = self.validate_id_token(id_token, nonce).await?;
+        Ok(claims.into())
+    }
+
+    fn end_session_url(&self) -> Option<String> {
+        self.end_session_url.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use p256::pkcs8::{EncodePrivateKey, LineEnding};
+    use serde_json::{json, Value};
+    use wiremock::{
+        matchers::{body_string_contains, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const CLIENT_ID: &str = "test_client";
+
+    // A locally generated ES256 key pair published under `kid`
+    struct TestKey {
+        kid: String,
+        encoding_key: EncodingKey,
+        jwk: Value,
+    }
+
+    impl TestKey {
+        fn generate(kid: &str) -> Self {
+            let secret = p256::SecretKey::random(&mut rand_core::OsRng);
+            let pem = secret.to_pkcs8_pem(LineEnding::LF).unwrap();
+
+            let mut jwk = serde_json::to_value(secret.public_key().to_jwk()).unwrap();
+            jwk["kid"] = json!(kid);
+            jwk["alg"] = json!("ES256");
+            jwk["use"] = json!("sig");
+
+            Self {
+                kid: kid.to_string(),
+                encoding_key: EncodingKey::from_ec_pem(pem.as_bytes()).unwrap(),
+                jwk,
+            }
+        }
+
+        fn sign(&self, claims: &Value) -> String {
+            let mut header = Header::new(Algorithm::ES256);
+            header.kid = Some(self.kid.clone());
+            encode(&header, claims, &self.encoding_key).unwrap()
+        }
+    }
+
+    fn test_config(issuer_url: &str) -> OidcConfig {
+        OidcConfig {
+            slug: "keycloak".to_string(),
+            display_name: "Keycloak".to_string(),
+            issuer_url: issuer_url.to_string(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: "test_secret".to_string(),
+            scopes: vec!["openid".to_string(), "profile".to_string(), "email".to_string()],
+        }
+    }
+
+    fn claims(issuer: &str, nonce: &str) -> Value {
+        json!({
+            "iss": issuer,
+            "aud": CLIENT_ID,
+            "sub": "user-123",
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "iat": chrono::Utc::now().timestamp(),
+            "nonce": nonce,
+            "preferred_username": "jdoe",
+            "email": "jdoe@example.com",
+            "picture": "https://example.com/jdoe.png"
+        })
+    }
+
+    // Serve a discovery document and JWKS for `keys`, returning the discovered provider
+    async fn discovered_provider(server: &MockServer, keys: &[&TestKey]) -> OidcProvider {
+        let issuer = server.uri();
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{}/authorize", issuer),
+                "token_endpoint": format!("{}/token", issuer),
+                "jwks_uri": format!("{}/jwks", issuer),
+                "userinfo_endpoint": format!("{}/userinfo", issuer),
+                "end_session_endpoint": format!("{}/logout", issuer)
+            })))
+            .mount(server)
+            .await;
+        mount_jwks(server, keys).await;
+
+        OidcProvider::discover(&test_config(&issuer), "http://localhost:3000", HttpClient::new())
+            .await
+            .unwrap()
+    }
+
+    async fn mount_jwks(server: &MockServer, keys: &[&TestKey]) {
+        let keys: Vec<Value> = keys.iter().map(|key| key.jwk.clone()).collect();
+        Mock::given(method("GET"))
+            .and(path("/jwks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "keys": keys })))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_discovery_rejects_issuer_mismatch() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "issuer": "https://evil.example.com",
+                "authorization_endpoint": "https://evil.example.com/authorize",
+                "token_endpoint": "https://evil.example.com/token",
+                "jwks_uri": "https://evil.example.com/jwks"
+            })))
+            .mount(&server)
+            .await;
+
+        let result =
+            OidcProvider::discover(&test_config(&server.uri()), "http://localhost:3000", HttpClient::new())
+                .await;
+        assert!(matches!(result, Err(AuthError::Discovery(_))));
+    }
+
+    #[tokio::test]
+    async fn test_authorize_url_includes_nonce_and_scopes() {
+        let server = MockServer::start().await;
+        let provider = discovered_provider(&server, &[]).await;
+
+        let request = provider.authorize_url();
+        let nonce = request.nonce.clone().unwrap();
+
+        assert!(request.url.starts_with(&format!("{}/authorize", server.uri())));
+        assert!(request.url.contains(&format!("nonce={}", nonce)));
+        assert!(request.url.contains("scope=openid+profile+email"));
+        assert!(request.url.contains("code_challenge_method=S256"));
+        assert!(request.url.contains("auth%2Fcallback%2Fkeycloak"));
+    }
+
+    #[tokio::test]
+    async fn test_end_session_url_from_discovery() {
+        let server = MockServer::start().await;
+        let provider = discovered_provider(&server, &[]).await;
+
+        assert_eq!(
+            provider.end_session_url(),
+            Some(format!(
+                "{}/logout?client_id={}&post_logout_redirect_uri=http%3A%2F%2Flocalhost%3A3000%2Flogin",
+                server.uri(),
+                CLIENT_ID
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_maps_validated_id_token() {
+        let server = MockServer::start().await;
+        let key = TestKey::generate("key-1");
+        let provider = discovered_provider(&server, &[&key]).await;
+
+        let id_token = key.sign(&claims(&server.uri(), "expected-nonce"));
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("code_verifier=test_verifier"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "test_access_token",
+                "token_type": "bearer",
+                "id_token": id_token
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let profile = provider
+            .authenticate(
+                "test_code".to_string(),
+                PkceCodeVerifier::new("test_verifier".to_string()),
+                Some("expected-nonce"),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(profile.provider_id, "user-123");
+        assert_eq!(profile.username, "jdoe");
+        assert_eq!(profile.email.as_deref(), Some("jdoe@example.com"));
+        assert_eq!(profile.avatar_url.as_deref(), Some("https://example.com/jdoe.png"));
+
+        let user = profile.into_create_user(provider.slug());
+        assert_eq!(user.provider, "keycloak");
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_requires_id_token() {
+        let server = MockServer::start().await;
+        let provider = discovered_provider(&server, &[]).await;
+
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "test_access_token",
+                "token_type": "bearer"
+            })))
+            .mount(&server)
+            .await;
+
+        let result = provider
+            .authenticate(
+                "test_code".to_string(),
+                PkceCodeVerifier::new("test_verifier".to_string()),
+                Some("nonce"),
+            )
+            .await;
+        assert!(matches!(result, Err(AuthError::InvalidIdToken(_))));
+    }
+
+    #[tokio::test]
+    async fn test_validate_rejects_tampered_claims() {
+        let server = MockServer::start().await;
+        let key = TestKey::generate("key-1");
+        let provider = discovered_provider(&server, &[&key]).await;
+        let issuer = server.uri();
+
+        let mut wrong_issuer = claims(&issuer, "n");
+        wrong_issuer["iss"] = json!("https://other.example.com");
+        let mut wrong_audience = claims(&issuer, "n");
+        wrong_audience["aud"] = json!("another_client");
+        let mut expired = claims(&issuer, "n");
+        expired["exp"] = json!(chrono::Utc::now().timestamp() - 600);
+        let wrong_nonce = claims(&issuer, "other");
+        let mut missing_nonce = claims(&issuer, "n");
+        missing_nonce.as_object_mut().unwrap().remove("nonce");
+
+        for (case, token_claims) in [
+            ("issuer", wrong_issuer),
+            ("audience", wrong_audience),
+            ("expiry", expired),
+            ("nonce", wrong_nonce),
+            ("missing nonce", missing_nonce),
+        ] {
+            let result = provider.validate_id_token(&key.sign(&token_claims), Some("n")).await;
+            assert!(matches!(result, Err(AuthError::InvalidIdToken(_))), "{}", case);
+        }
+
+        assert!(provider
+            .validate_id_token(&key.sign(&claims(&issuer, "n")), Some("n"))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_validate_rejects_bad_signature_and_algorithm() {
+        let server = MockServer::start().await;
+        let key = TestKey::generate("key-1");
+        let provider = discovered_provider(&server, &[&key]).await;
+        let token_claims = claims(&server.uri(), "n");
+
+        // Signed by a different key that claims the published key id
+        let impostor = TestKey::generate("key-1");
+        let result = provider.validate_id_token(&impostor.sign(&token_claims), Some("n")).await;
+        assert!(matches!(result, Err(AuthError::InvalidIdToken(_))));
+
+        // A shared-secret token keyed with something public must not be accepted
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("key-1".to_string());
+        let hs_token = encode(&header, &token_claims, &EncodingKey::from_secret(CLIENT_ID.as_bytes())).unwrap();
+        let result = provider.validate_id_token(&hs_token, Some("n")).await;
+        assert!(matches!(result, Err(AuthError::InvalidIdToken(_))));
+    }
+
+    #[tokio::test]
+    async fn test_jwks_is_cached_and_refetched_for_unknown_key() {
+        let server = MockServer::start().await;
+        let old_key = TestKey::generate("old");
+        let new_key = TestKey::generate("new");
+        let jwks = JwksCache::new(format!("{}/jwks", server.uri()), HttpClient::new());
+
+        Mock::given(method("GET"))
+            .and(path("/jwks"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "keys": [old_key.jwk] })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert!(jwks.key(Some("old")).await.is_ok());
+        assert!(jwks.key(Some("old")).await.is_ok());
+
+        // The provider rotates keys, but a refetch is only allowed once the cache has aged
+        mount_jwks(&server, &[&old_key, &new_key]).await;
+        assert!(jwks.key(Some("new")).await.is_err());
+
+        if let Some(cached) = jwks.cached.write().await.as_mut() {
+            *cached = Arc::new(CachedJwks {
+                keys: cached.keys.clone(),
+                fetched_at: Instant::now() - Duration::from_secs(JWKS_MIN_REFRESH_SECS + 1),
+            });
+        }
+        assert!(jwks.key(Some("new")).await.is_ok());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{
+    config::{Config, FieldSync},
+    models::{ProfileUpdate, User},
+};
+
+use super::ProviderProfile;
+
+/// Per-field rules for refreshing a user's profile from the provider on login.
+///
+/// A display name the user edited locally is never overwritten, and identities
+/// linked after sign-up only fill empty fields so two providers cannot keep
+/// overwriting each other.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProfileSyncPolicy {
+    pub username: FieldSync,
+    pub email: FieldSync,
+    pub avatar_url: FieldSync,
+}
+
+impl ProfileSyncPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            username: config.profile_sync_username,
+            email: config.profile_sync_email,
+            avatar_url: config.profile_sync_avatar,
+        }
+    }
+
+    /// Work out which fields of `user` change after signing in with `profile` from `provider`.
+    pub fn changes(&self, user: &User, provider: &str, profile: &ProviderProfile) -> ProfileUpdate {
+        let primary = user.provider == provider && user.provider_id == profile.provider_id;
+        let effective = |sync: FieldSync| match sync {
+            FieldSync::Always if !primary => FieldSync::FillMissing,
+            sync => sync,
+        };
+
+        let username_sync = if user.username_edited_at.is_some() {
+            FieldSync::Never
+        } else {
+            effective(self.username)
+        };
+
+        ProfileUpdate {
+            username: sync_required(username_sync, &user.username, &profile.username),
+            email: sync_optional(effective(self.email), &user.email, &profile.email),
+            avatar_url: sync_optional(effective(self.avatar_url), &user.avatar_url, &profile.avatar_url),
+        }
+    }
+}
+
+fn sync_required(sync: FieldSync, current: &str, fresh: &str) -> Option<String> {
+    let take = match sync {
+        FieldSync::Always => true,
+        FieldSync::FillMissing => current.is_empty(),
+        FieldSync::Never => false,
+    };
+
+    (take && !fresh.is_empty() && current != fresh).then(|| fresh.to_string())
+}
+
+fn sync_optional(
+    sync: FieldSync,
+    current: &Option<String>,
+    fresh: &Option<String>,
+) -> Option<Option<String>> {
+    let take = match sync {
+        FieldSync::Always => true,
+        FieldSync::FillMissing => current.is_none(),
+        FieldSync::Never => false,
+    };
+
+    (take && current != fresh).then(|| fresh.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn user() -> User {
+        User {
+            id: 1,
+            provider: "github".to_string(),
+            provider_id: "12345".to_string(),
+            username: "octocat".to_string(),
+            email: Some("old@example.com".to_string()),
+            avatar_url: None,
+            created_at: Utc::now(),
+            last_login: Utc::now(),
+            username_edited_at: None,
+            disabled_at: None,
+            disabled_reason: None,
+        }
+    }
+
+    fn profile(provider_id: &str) -> ProviderProfile {
+        ProviderProfile {
+            provider_id: provider_id.to_string(),
+            username: "octocat-renamed".to_string(),
+            email: Some("new@example.com".to_string()),
+            email_verified: true,
+            avatar_url: Some("https://example.com/a.png".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_always_takes_provider_values() {
+        let update = ProfileSyncPolicy::default().changes(&user(), "github", &profile("12345"));
+
+        assert_eq!(update.username, Some("octocat-renamed".to_string()));
+        assert_eq!(update.email, Some(Some("new@example.com".to_string())));
+        assert_eq!(update.avatar_url, Some(Some("https://example.com/a.png".to_string())));
+    }
+
+    #[test]
+    fn test_unchanged_profile_is_empty_update() {
+        let mut current = user();
+        current.username = "octocat-renamed".to_string();
+        current.email = Some("new@example.com".to_string());
+        current.avatar_url = Some("https://example.com/a.png".to_string());
+
+        let update = ProfileSyncPolicy::default().changes(&current, "github", &profile("12345"));
+        assert!(update.is_empty());
+    }
+
+    #[test]
+    fn test_field_policies() {
+        let policy = ProfileSyncPolicy {
+            username: FieldSync::Never,
+            email: FieldSync::FillMissing,
+            avatar_url: FieldSync::FillMissing,
+        };
+        let update = policy.changes(&user(), "github", &profile("12345"));
+
+        assert_eq!(update.changed_fields(), vec!["avatar_url"]);
+    }
+
+    #[test]
+    fn test_locally_edited_username_is_kept() {
+        let mut current = user();
+        current.username_edited_at = Some(Utc::now());
+
+        let update = ProfileSyncPolicy::default().changes(&current, "github", &profile("12345"));
+        assert!(update.username.is_none());
+        assert!(update.email.is_some());
+    }
+
+    #[test]
+    fn test_linked_identity_only_fills_missing_fields() {
+        let update = ProfileSyncPolicy::default().changes(&user(), "microsoft", &profile("ms-1"));
+
+        assert_eq!(update.changed_fields(), vec!["avatar_url"]);
+    }
+}
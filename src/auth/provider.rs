@@ -0,0 +1,207 @@
+use axum::async_trait;
+use oauth2::{
+    basic::{
+        BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
+        BasicTokenType,
+    },
+    AuthorizationCode, Client, CsrfToken, ExtraTokenFields, PkceCodeChallenge, PkceCodeVerifier,
+    Scope, StandardRevocableToken, StandardTokenResponse, TokenResponse,
+};
+
+use crate::{error::AuthError, models::CreateUser};
+
+// Authorization redirect plus the secrets that must be kept for the callback
+#[derive(Debug)]
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub csrf_token: CsrfToken,
+    pub pkce_verifier: PkceCodeVerifier,
+    // OpenID Connect nonce the ID token must echo back
+    pub nonce: Option<String>,
+}
+
+// Normalized user profile returned by every identity provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderProfile {
+    pub provider_id: String,
+    pub username: String,
+    pub email: Option<String>,
+    // Whether the provider vouches that `email` belongs to the user
+    pub email_verified: bool,
+    pub avatar_url: Option<String>,
+}
+
+impl ProviderProfile {
+    /// The email address, only if the provider vouches for it.
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email.as_deref().filter(|_| self.email_verified)
+    }
+
+    pub fn into_create_user(self, provider: &str) -> CreateUser {
+        CreateUser {
+            provider: provider.to_string(),
+            provider_id: self.provider_id,
+            username: self.username,
+            email: self.email,
+            avatar_url: self.avatar_url,
+        }
+    }
+}
+
+/// An OAuth2 identity provider that can be registered with `OAuth2Config`.
+///
+/// Implementations own their OAuth2 client and know how to turn an access
+/// token into a `ProviderProfile`; `AuthService` drives the flow generically.
+#[async_trait]
+pub trait IdentityProvider: std::fmt::Debug + Send + Sync {
+    /// Slug used in `/auth/{provider}` routes and stored in `users.provider`
+    fn slug(&self) -> &str;
+
+    /// Human readable provider name for log and error messages
+    fn display_name(&self) -> &str;
+
+    /// Build the authorization URL the browser is redirected to
+    fn authorize_url(&self) -> AuthorizationRequest;
+
+    /// Exchange an authorization code for an access token, proving the PKCE verifier
+    async fn exchange_code(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+    ) -> Result<String, AuthError>;
+
+    /// Fetch the signed-in user's profile using an access token
+    async fn fetch_profile(&self, access_token: &str) -> Result<ProviderProfile, AuthError>;
+
+    /// Where to send the browser to sign out at the provider as well, coming
+    /// back to the login page; `None` if the provider has no such endpoint
+    fn end_session_url(&self) -> Option<String> {
+        None
+    }
+
+    /// Complete a login: exchange the code and resolve the user's profile.
+    ///
+    /// `nonce` is the value issued with the authorization request, if any.
+    /// Plain OAuth2 providers ignore it and read the profile from their API.
+    async fn authenticate(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+        _nonce: Option<&str>,
+    ) -> Result<ProviderProfile, AuthError> {
+        let access_token = self.exchange_code(code, pkce_verifier).await?;
+        self.fetch_profile(&access_token).await
+    }
+}
+
+// OAuth2 client whose token response carries the extra fields `EF`
+// (`BasicClient` is `TokenClient<EmptyExtraTokenFields>`)
+pub(crate) type TokenClient<EF> = Client<
+    BasicErrorResponse,
+    StandardTokenResponse<EF, BasicTokenType>,
+    BasicTokenType,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+>;
+
+// Build a PKCE-protected authorization request for an OAuth2 client
+pub(crate) fn pkce_authorize_url<EF: ExtraTokenFields>(
+    client: &TokenClient<EF>,
+    scopes: &[&str],
+) -> AuthorizationRequest {
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+    let (auth_url, csrf_token) = client
+        .authorize_url(CsrfToken::new_random)
+        .add_scopes(scopes.iter().map(|scope| Scope::new(scope.to_string())))
+        .set_pkce_challenge(pkce_challenge)
+        .url();
+
+    AuthorizationRequest {
+        url: auth_url.to_string(),
+        csrf_token,
+        pkce_verifier,
+        nonce: None,
+    }
+}
+
+// Exchange an authorization code, replaying the PKCE verifier, and return the full token response
+pub(crate) async fn pkce_exchange<EF: ExtraTokenFields>(
+    client: &TokenClient<EF>,
+    code: String,
+    pkce_verifier: PkceCodeVerifier,
+) -> Result<StandardTokenResponse<EF, BasicTokenType>, AuthError> {
+    client
+        .exchange_code(AuthorizationCode::new(code))
+        .set_pkce_verifier(pkce_verifier)
+        .request_async(oauth2::reqwest::async_http_client)
+        .await
+        .map_err(|e| AuthError::TokenExchange(e.to_string()))
+}
+
+// Exchange an authorization code on an OAuth2 client and keep only the access token
+pub(crate) async fn pkce_exchange_code<EF: ExtraTokenFields>(
+    client: &TokenClient<EF>,
+    code: String,
+    pkce_verifier: PkceCodeVerifier,
+) -> Result<String, AuthError> {
+    let token_result = pkce_exchange(client, code, pkce_verifier).await?;
+    Ok(token_result.access_token().secret().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oauth2::{basic::BasicClient, AuthUrl, ClientId, ClientSecret, TokenUrl};
+    use wiremock::{
+        matchers::{body_string_contains, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn test_client(token_url: String) -> BasicClient {
+        BasicClient::new(
+            ClientId::new("client_id".to_string()),
+            Some(ClientSecret::new("client_secret".to_string())),
+            AuthUrl::new("https://example.com/authorize".to_string()).unwrap(),
+            Some(TokenUrl::new(token_url).unwrap()),
+        )
+    }
+
+    #[test]
+    fn test_pkce_authorize_url_includes_challenge() {
+        let client = test_client("https://example.com/token".to_string());
+        let request = pkce_authorize_url(&client, &["openid", "email"]);
+
+        assert!(request.url.contains("code_challenge="));
+        assert!(request.url.contains("code_challenge_method=S256"));
+        assert!(request.url.contains(&format!("state={}", request.csrf_token.secret())));
+        assert!(!request.pkce_verifier.secret().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pkce_exchange_code_sends_verifier() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("code_verifier=test_verifier"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "test_access_token",
+                "token_type": "bearer"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = test_client(format!("{}/token", mock_server.uri()));
+        let access_token = pkce_exchange_code(
+            &client,
+            "test_code".to_string(),
+            PkceCodeVerifier::new("test_verifier".to_string()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(access_token, "test_access_token");
+    }
+}
@@ -0,0 +1,81 @@
+use reqwest::Url;
+
+// Longest return-to path kept in the session
+const MAX_RETURN_TO_LEN: usize = 2048;
+
+// Paths that are part of signing in, where returning would loop
+const SIGN_IN_PATHS: [&str; 3] = ["/login", "/auth", "/logout"];
+
+/// Check that `target` is a same-origin relative path to send a user to after sign-in.
+///
+/// Anything a browser could resolve to another host is refused: absolute and
+/// scheme-relative URLs (`//evil.example`), backslashes that some browsers treat
+/// as slashes, and control characters. Sign-in pages themselves are refused too.
+pub fn sanitize_return_to(target: &str) -> Option<String> {
+    if target.len() > MAX_RETURN_TO_LEN
+        || !target.starts_with('/')
+        || target.starts_with("//")
+        || target.contains('\\')
+        || target.chars().any(char::is_control)
+    {
+        return None;
+    }
+
+    // Resolve it the way a browser would and make sure the host did not change
+    let base = Url::parse("http://return-to.invalid/").ok()?;
+    let resolved = base.join(target).ok()?;
+    if resolved.origin() != base.origin() {
+        return None;
+    }
+
+    let path = resolved.path();
+    if SIGN_IN_PATHS.iter().any(|sign_in| {
+        path.strip_prefix(sign_in)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }) {
+        return None;
+    }
+
+    Some(target.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts_local_paths() {
+        assert_eq!(sanitize_return_to("/dashboard").as_deref(), Some("/dashboard"));
+        assert_eq!(
+            sanitize_return_to("/admin/users?q=ann&page=2").as_deref(),
+            Some("/admin/users?q=ann&page=2")
+        );
+        assert_eq!(sanitize_return_to("/").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn test_refuses_other_origins() {
+        for target in [
+            "https://evil.example/",
+            "//evil.example/path",
+            "/\\evil.example",
+            "\\\\evil.example",
+            "/\tevil",
+            "/%0d%0aSet-Cookie:x=y\r\n",
+            "javascript:alert(1)",
+            "dashboard",
+            "",
+        ] {
+            assert_eq!(sanitize_return_to(target), None, "{:?} should be refused", target);
+        }
+        assert_eq!(sanitize_return_to(&format!("/{}", "a".repeat(MAX_RETURN_TO_LEN))), None);
+    }
+
+    #[test]
+    fn test_refuses_sign_in_pages() {
+        assert_eq!(sanitize_return_to("/login?error=x"), None);
+        assert_eq!(sanitize_return_to("/auth/github"), None);
+        assert_eq!(sanitize_return_to("/logout"), None);
+        assert!(sanitize_return_to("/login-help").is_some());
+    }
+}
@@ -1,28 +1,581 @@
 use std::env;
 
+// Minimum length of SESSION_SECRET (and previous secrets) in bytes
+pub const MIN_SESSION_SECRET_LEN: usize = 32;
+
+// Default session lifetimes
+const DEFAULT_SESSION_IDLE_TIMEOUT_MINUTES: i64 = 30;
+const DEFAULT_SESSION_ABSOLUTE_LIFETIME_HOURS: i64 = 12;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Environment variable error: {0}")]
+    Env(#[from] env::VarError),
+
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
+}
+
+// How strictly OAuth2 login state is bound to the browser session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoginStateMode {
+    // The callback must arrive in the same session that started the login
+    #[default]
+    Strict,
+    // Development only: accept a valid one-time state even if the session cookie was lost
+    Unbound,
+}
+
+impl LoginStateMode {
+    fn from_env_value(value: Option<String>) -> Self {
+        match value.as_deref().map(str::trim) {
+            None | Some("") | Some("strict") => LoginStateMode::Strict,
+            Some("unbound") => {
+                tracing::warn!("LOGIN_STATE_MODE=unbound: login state is not bound to the browser session. Do not use in production.");
+                LoginStateMode::Unbound
+            }
+            Some(other) => {
+                tracing::warn!("Unknown LOGIN_STATE_MODE '{}', using strict", other);
+                LoginStateMode::Strict
+            }
+        }
+    }
+}
+
+// Backing store for tower-sessions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionStoreKind {
+    // Sessions persist in the `sessions` table of the application database
+    #[default]
+    Sqlite,
+    // Sessions live in process memory and are lost on restart
+    Memory,
+}
+
+impl SessionStoreKind {
+    fn from_env_value(value: Option<String>) -> Self {
+        match value.as_deref().map(str::trim) {
+            None | Some("") | Some("sqlite") => SessionStoreKind::Sqlite,
+            Some("memory") => SessionStoreKind::Memory,
+            Some(other) => {
+                tracing::warn!("Unknown SESSION_STORE '{}', using sqlite", other);
+                SessionStoreKind::Sqlite
+            }
+        }
+    }
+}
+
+// How a user profile field is refreshed from the provider on login
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FieldSync {
+    // Take the provider's value on every login
+    #[default]
+    Always,
+    // Only fill the field while it is empty
+    FillMissing,
+    // Keep the value captured at sign-up
+    Never,
+}
+
+impl FieldSync {
+    fn from_env_value(name: &str, value: Option<String>) -> Self {
+        match value.as_deref().map(str::trim) {
+            None | Some("") | Some("always") => FieldSync::Always,
+            Some("fill_missing") => FieldSync::FillMissing,
+            Some("never") => FieldSync::Never,
+            Some(other) => {
+                tracing::warn!("Unknown {} '{}', using always", name, other);
+                FieldSync::Always
+            }
+        }
+    }
+}
+
+// Provider endpoints; overridable so the OAuth2 flow can run against a mock server
+const DEFAULT_MICROSOFT_LOGIN_URL: &str = "https://login.microsoftonline.com";
+const DEFAULT_MICROSOFT_GRAPH_URL: &str = "https://graph.microsoft.com/v1.0";
+const DEFAULT_GITHUB_OAUTH_URL: &str = "https://github.com/login/oauth";
+const DEFAULT_GITHUB_API_URL: &str = "https://api.github.com";
+
+// Which Microsoft accounts may sign in, from MICROSOFT_TENANT_ID
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum MicrosoftTenant {
+    // Work, school and personal accounts from any tenant
+    #[default]
+    Common,
+    // Work and school accounts from any tenant
+    Organizations,
+    // Personal Microsoft accounts only
+    Consumers,
+    // Only accounts from these tenant IDs
+    Tenants(Vec<String>),
+}
+
+impl MicrosoftTenant {
+    fn from_env_value(value: Option<String>) -> Self {
+        match value.as_deref().map(str::trim) {
+            None | Some("") | Some("common") => MicrosoftTenant::Common,
+            Some("organizations") => MicrosoftTenant::Organizations,
+            Some("consumers") => MicrosoftTenant::Consumers,
+            Some(tenants) => MicrosoftTenant::Tenants(
+                parse_list(tenants)
+                    .into_iter()
+                    .map(|tenant| tenant.to_ascii_lowercase())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+// Scopes requested from a generic OpenID Connect provider unless OIDC_SCOPES is set
+const DEFAULT_OIDC_SCOPES: [&str; 3] = ["openid", "profile", "email"];
+
+// Slugs taken by the built-in providers
+const RESERVED_PROVIDER_SLUGS: [&str; 2] = ["microsoft", "github"];
+
+// Generic OpenID Connect provider (Keycloak, Okta, Auth0, ...) configured from the environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OidcConfig {
+    pub slug: String,
+    pub display_name: String,
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: Vec<String>,
+}
+
+impl OidcConfig {
+    // Only configured when OIDC_ISSUER_URL is set; the client credentials are then required
+    fn from_env() -> Result<Option<Self>, ConfigError> {
+        let issuer_url = env::var("OIDC_ISSUER_URL").unwrap_or_default();
+        if issuer_url.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let scopes = env::var("OIDC_SCOPES")
+            .map(|value| parse_list(&value))
+            .unwrap_or_default();
+
+        Ok(Some(OidcConfig {
+            slug: env::var("OIDC_PROVIDER_SLUG").unwrap_or_else(|_| "oidc".to_string()),
+            display_name: env::var("OIDC_DISPLAY_NAME")
+                .unwrap_or_else(|_| "OpenID Connect".to_string()),
+            issuer_url: issuer_url.trim().to_string(),
+            client_id: env::var("OIDC_CLIENT_ID")?,
+            client_secret: env::var("OIDC_CLIENT_SECRET")?,
+            scopes: if scopes.is_empty() {
+                DEFAULT_OIDC_SCOPES.iter().map(|scope| scope.to_string()).collect()
+            } else {
+                scopes
+            },
+        }))
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let slug_is_valid = !self.slug.is_empty()
+            && self
+                .slug
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !slug_is_valid || RESERVED_PROVIDER_SLUGS.contains(&self.slug.as_str()) {
+            return Err(ConfigError::Invalid(format!(
+                "OIDC_PROVIDER_SLUG '{}' must be lowercase letters, digits or '-' and not a built-in provider",
+                self.slug
+            )));
+        }
+
+        if !self.scopes.iter().any(|scope| scope == "openid") {
+            return Err(ConfigError::Invalid(
+                "OIDC_SCOPES must include 'openid'".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub database_url: String,
     pub microsoft_client_id: String,
     pub microsoft_client_secret: String,
+    pub microsoft_tenant: MicrosoftTenant,
+    // Authority host; `/{tenant}/oauth2/v2.0/authorize` and `/token` are appended
+    pub microsoft_login_url: String,
+    // Graph API root; the profile is read from `/me`
+    pub microsoft_graph_url: String,
     pub github_client_id: String,
     pub github_client_secret: String,
+    // OAuth root; `/authorize` and `/access_token` are appended
+    pub github_oauth_url: String,
+    // REST API root; the profile is read from `/user` and `/user/emails`
+    pub github_api_url: String,
+    // When either list is set, only members of one of these orgs or `org/team` teams may sign in
+    pub github_allowed_orgs: Vec<String>,
+    pub github_allowed_teams: Vec<String>,
     pub session_secret: String,
+    pub previous_session_secrets: Vec<String>,
+    pub session_cookie_encrypted: bool,
+    pub session_idle_timeout_minutes: i64,
+    pub session_absolute_lifetime_hours: i64,
     pub base_url: String,
+    // Signing out also ends the session at the identity provider, when it supports that
+    pub upstream_logout: bool,
+    pub login_state_mode: LoginStateMode,
+    pub session_store: SessionStoreKind,
+    pub profile_sync_username: FieldSync,
+    pub profile_sync_email: FieldSync,
+    pub profile_sync_avatar: FieldSync,
+    // Email rules checked on every sign-in
+    pub allowed_email_domains: Vec<String>,
+    pub denied_email_domains: Vec<String>,
+    pub blocked_emails: Vec<String>,
+    pub require_verified_email: bool,
+    // Users signing in with one of these verified emails become admins
+    pub admin_emails: Vec<String>,
+    // The first user becomes admin while nobody holds the role
+    pub admin_bootstrap_first_user: bool,
+    pub oidc: Option<OidcConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            database_url: "sqlite:sso_app.db".to_string(),
+            microsoft_client_id: String::new(),
+            microsoft_client_secret: String::new(),
+            microsoft_tenant: MicrosoftTenant::default(),
+            microsoft_login_url: DEFAULT_MICROSOFT_LOGIN_URL.to_string(),
+            microsoft_graph_url: DEFAULT_MICROSOFT_GRAPH_URL.to_string(),
+            github_client_id: String::new(),
+            github_client_secret: String::new(),
+            github_oauth_url: DEFAULT_GITHUB_OAUTH_URL.to_string(),
+            github_api_url: DEFAULT_GITHUB_API_URL.to_string(),
+            github_allowed_orgs: Vec::new(),
+            github_allowed_teams: Vec::new(),
+            session_secret: String::new(),
+            previous_session_secrets: Vec::new(),
+            session_cookie_encrypted: false,
+            session_idle_timeout_minutes: DEFAULT_SESSION_IDLE_TIMEOUT_MINUTES,
+            session_absolute_lifetime_hours: DEFAULT_SESSION_ABSOLUTE_LIFETIME_HOURS,
+            base_url: "http://localhost:3000".to_string(),
+            upstream_logout: true,
+            login_state_mode: LoginStateMode::default(),
+            session_store: SessionStoreKind::default(),
+            profile_sync_username: FieldSync::default(),
+            profile_sync_email: FieldSync::default(),
+            profile_sync_avatar: FieldSync::default(),
+            allowed_email_domains: Vec::new(),
+            denied_email_domains: Vec::new(),
+            blocked_emails: Vec::new(),
+            require_verified_email: false,
+            admin_emails: Vec::new(),
+            admin_bootstrap_first_user: false,
+            oidc: None,
+        }
+    }
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, env::VarError> {
-        Ok(Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let config = Config {
             database_url: env::var("DATABASE_URL")
                 .unwrap_or_else(|_| "sqlite:sso_app.db".to_string()),
             microsoft_client_id: env::var("MICROSOFT_CLIENT_ID")?,
             microsoft_client_secret: env::var("MICROSOFT_CLIENT_SECRET")?,
+            microsoft_tenant: MicrosoftTenant::from_env_value(env::var("MICROSOFT_TENANT_ID").ok()),
+            microsoft_login_url: env::var("MICROSOFT_LOGIN_URL")
+                .unwrap_or_else(|_| DEFAULT_MICROSOFT_LOGIN_URL.to_string()),
+            microsoft_graph_url: env::var("MICROSOFT_GRAPH_URL")
+                .unwrap_or_else(|_| DEFAULT_MICROSOFT_GRAPH_URL.to_string()),
             github_client_id: env::var("GITHUB_CLIENT_ID")?,
             github_client_secret: env::var("GITHUB_CLIENT_SECRET")?,
+            github_oauth_url: env::var("GITHUB_OAUTH_URL")
+                .unwrap_or_else(|_| DEFAULT_GITHUB_OAUTH_URL.to_string()),
+            github_api_url: env::var("GITHUB_API_URL")
+                .unwrap_or_else(|_| DEFAULT_GITHUB_API_URL.to_string()),
+            github_allowed_orgs: env::var("GITHUB_ALLOWED_ORGS")
+                .map(|value| parse_list(&value))
+                .unwrap_or_default(),
+            github_allowed_teams: env::var("GITHUB_ALLOWED_TEAMS")
+                .map(|value| parse_list(&value))
+                .unwrap_or_default(),
             session_secret: env::var("SESSION_SECRET")?,
+            previous_session_secrets: env::var("SESSION_SECRET_PREVIOUS")
+                .map(|value| parse_list(&value))
+                .unwrap_or_default(),
+            session_cookie_encrypted: env::var("SESSION_COOKIE_ENCRYPTED")
+                .map(|value| parse_bool(&value))
+                .unwrap_or(false),
+            session_idle_timeout_minutes: parse_number(
+                "SESSION_IDLE_TIMEOUT_MINUTES",
+                env::var("SESSION_IDLE_TIMEOUT_MINUTES").ok(),
+                DEFAULT_SESSION_IDLE_TIMEOUT_MINUTES,
+            )?,
+            session_absolute_lifetime_hours: parse_number(
+                "SESSION_ABSOLUTE_LIFETIME_HOURS",
+                env::var("SESSION_ABSOLUTE_LIFETIME_HOURS").ok(),
+                DEFAULT_SESSION_ABSOLUTE_LIFETIME_HOURS,
+            )?,
             base_url: env::var("BASE_URL")
                 .unwrap_or_else(|_| "http://localhost:3000".to_string()),
-        })
+            upstream_logout: env::var("UPSTREAM_LOGOUT")
+                .map(|value| parse_bool(&value))
+                .unwrap_or(true),
+            login_state_mode: LoginStateMode::from_env_value(env::var("LOGIN_STATE_MODE").ok()),
+            session_store: SessionStoreKind::from_env_value(env::var("SESSION_STORE").ok()),
+            profile_sync_username: FieldSync::from_env_value(
+                "PROFILE_SYNC_USERNAME",
+                env::var("PROFILE_SYNC_USERNAME").ok(),
+            ),
+            profile_sync_email: FieldSync::from_env_value(
+                "PROFILE_SYNC_EMAIL",
+                env::var("PROFILE_SYNC_EMAIL").ok(),
+            ),
+            profile_sync_avatar: FieldSync::from_env_value(
+                "PROFILE_SYNC_AVATAR",
+                env::var("PROFILE_SYNC_AVATAR").ok(),
+            ),
+            allowed_email_domains: env::var("ACCESS_ALLOWED_EMAIL_DOMAINS")
+                .map(|value| parse_list(&value))
+                .unwrap_or_default(),
+            denied_email_domains: env::var("ACCESS_DENIED_EMAIL_DOMAINS")
+                .map(|value| parse_list(&value))
+                .unwrap_or_default(),
+            blocked_emails: env::var("ACCESS_BLOCKED_EMAILS")
+                .map(|value| parse_list(&value))
+                .unwrap_or_default(),
+            require_verified_email: env::var("ACCESS_REQUIRE_VERIFIED_EMAIL")
+                .map(|value| parse_bool(&value))
+                .unwrap_or(false),
+            admin_emails: env::var("ADMIN_EMAILS")
+                .map(|value| parse_list(&value))
+                .unwrap_or_default(),
+            admin_bootstrap_first_user: env::var("ADMIN_BOOTSTRAP_FIRST_USER")
+                .map(|value| parse_bool(&value))
+                .unwrap_or(false),
+            oidc: OidcConfig::from_env()?,
+        };
+
+        config.validate()?;
+        Ok(config)
     }
-}
\ No newline at end of file
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.session_secret.len() < MIN_SESSION_SECRET_LEN {
+            return Err(ConfigError::Invalid(format!(
+                "SESSION_SECRET must be at least {} bytes long",
+                MIN_SESSION_SECRET_LEN
+            )));
+        }
+
+        if self
+            .previous_session_secrets
+            .iter()
+            .any(|secret| secret.len() < MIN_SESSION_SECRET_LEN)
+        {
+            return Err(ConfigError::Invalid(format!(
+                "Every SESSION_SECRET_PREVIOUS entry must be at least {} bytes long",
+                MIN_SESSION_SECRET_LEN
+            )));
+        }
+
+        if self.session_idle_timeout_minutes <= 0 || self.session_absolute_lifetime_hours <= 0 {
+            return Err(ConfigError::Invalid(
+                "Session idle timeout and absolute lifetime must be positive".to_string(),
+            ));
+        }
+
+        if let Some(team) = self.github_allowed_teams.iter().find(|team| {
+            !matches!(team.split_once('/'), Some((org, slug)) if !org.is_empty() && !slug.is_empty() && !slug.contains('/'))
+        }) {
+            return Err(ConfigError::Invalid(format!(
+                "GITHUB_ALLOWED_TEAMS entry '{}' must have the form org/team-slug",
+                team
+            )));
+        }
+
+        if let Some(oidc) = &self.oidc {
+            oidc.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+// Split a comma-separated environment value, dropping empty entries
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Parse an optional numeric environment value, falling back to `default` when unset
+fn parse_number(name: &str, value: Option<String>, default: i64) -> Result<i64, ConfigError> {
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(default),
+        Some(value) => value
+            .parse()
+            .map_err(|_| ConfigError::Invalid(format!("{} must be a whole number", name))),
+    }
+}
+
+fn parse_bool(value: &str) -> bool {
+    matches!(value.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_config() -> Config {
+        Config {
+            session_secret: "a".repeat(MIN_SESSION_SECRET_LEN),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_accepts_long_secret() {
+        assert!(valid_config().validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_short_secret() {
+        let config = Config {
+            session_secret: "too_short".to_string(),
+            ..Config::default()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_validate_rejects_short_previous_secret() {
+        let config = Config {
+            previous_session_secrets: vec!["too_short".to_string()],
+            ..valid_config()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_validate_rejects_non_positive_lifetimes() {
+        let config = Config {
+            session_idle_timeout_minutes: 0,
+            ..valid_config()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+
+        let config = Config {
+            session_absolute_lifetime_hours: -1,
+            ..valid_config()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    fn oidc_config() -> OidcConfig {
+        OidcConfig {
+            slug: "keycloak".to_string(),
+            display_name: "Keycloak".to_string(),
+            issuer_url: "https://sso.example.com/realms/main".to_string(),
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            scopes: vec!["openid".to_string(), "email".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_validate_accepts_oidc_provider() {
+        let config = Config {
+            oidc: Some(oidc_config()),
+            ..valid_config()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_oidc_provider() {
+        // The slug becomes part of /auth/{provider} and must not shadow a built-in provider
+        for slug in ["github", "Key Cloak", ""] {
+            let config = Config {
+                oidc: Some(OidcConfig {
+                    slug: slug.to_string(),
+                    ..oidc_config()
+                }),
+                ..valid_config()
+            };
+            assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))), "{}", slug);
+        }
+
+        let config = Config {
+            oidc: Some(OidcConfig {
+                scopes: vec!["email".to_string()],
+                ..oidc_config()
+            }),
+            ..valid_config()
+        };
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("N", None, 30).unwrap(), 30);
+        assert_eq!(parse_number("N", Some(" ".to_string()), 30).unwrap(), 30);
+        assert_eq!(parse_number("N", Some("45".to_string()), 30).unwrap(), 45);
+        assert!(matches!(
+            parse_number("N", Some("soon".to_string()), 30),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_github_allowed_teams() {
+        let config = Config {
+            github_allowed_teams: vec!["acme/platform".to_string()],
+            ..valid_config()
+        };
+        assert!(config.validate().is_ok());
+
+        for team in ["platform", "acme/", "/platform", "acme/platform/extra"] {
+            let config = Config {
+                github_allowed_teams: vec![team.to_string()],
+                ..valid_config()
+            };
+            assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))), "{}", team);
+        }
+    }
+
+    #[test]
+    fn test_microsoft_tenant_from_env_value() {
+        assert_eq!(MicrosoftTenant::from_env_value(None), MicrosoftTenant::Common);
+        assert_eq!(
+            MicrosoftTenant::from_env_value(Some("organizations".to_string())),
+            MicrosoftTenant::Organizations
+        );
+        assert_eq!(
+            MicrosoftTenant::from_env_value(Some("consumers".to_string())),
+            MicrosoftTenant::Consumers
+        );
+        assert_eq!(
+            MicrosoftTenant::from_env_value(Some(" AAAA-1, bbbb-2 ".to_string())),
+            MicrosoftTenant::Tenants(vec!["aaaa-1".to_string(), "bbbb-2".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(parse_list(" a, b ,,c "), vec!["a", "b", "c"]);
+        assert!(parse_list("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_bool() {
+        assert!(parse_bool("true"));
+        assert!(parse_bool(" 1 "));
+        assert!(!parse_bool("false"));
+        assert!(!parse_bool("nope"));
+    }
+}
@@ -1,5 +1,5 @@
 use sqlx::{sqlite::SqlitePool, migrate::MigrateDatabase, Sqlite};
-use crate::{models::{User, CreateUser}, error::AppError};
+use crate::{models::{User, CreateUser, Identity, ProfileUpdate, Role, UserListQuery, UserSession, UserSort}, error::{AppError, AuthError}};
 
 pub struct Database {
     pool: SqlitePool,
@@ -44,9 +44,10 @@ impl UserRepository {
         provider_id: &str,
     ) -> Result<Option<User>, AppError> {
         let user = sqlx::query_as::<_, User>(
-            "SELECT id, provider, provider_id, username, email, avatar_url, created_at, last_login 
-             FROM users 
-             WHERE provider = ? AND provider_id = ?"
+            "SELECT u.id, u.provider, u.provider_id, u.username, u.email, u.avatar_url, u.created_at, u.last_login, u.username_edited_at, u.disabled_at, u.disabled_reason 
+             FROM users u
+             JOIN identities i ON i.user_id = u.id
+             WHERE i.provider = ? AND i.provider_id = ?"
         )
         .bind(provider)
         .bind(provider_id)
@@ -56,8 +57,22 @@ impl UserRepository {
         Ok(user)
     }
 
+    pub async fn find_by_id(&self, user_id: i64) -> Result<Option<User>, AppError> {
+        let user = sqlx::query_as::<_, User>(
+            "SELECT id, provider, provider_id, username, email, avatar_url, created_at, last_login, username_edited_at, disabled_at, disabled_reason 
+             FROM users 
+             WHERE id = ?"
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
     pub async fn create_user(&self, user: CreateUser) -> Result<User, AppError> {
         let now = chrono::Utc::now();
+        let mut tx = self.pool.begin().await?;
         
         let result = sqlx::query(
             "INSERT INTO users (provider, provider_id, username, email, avatar_url, created_at, last_login)
@@ -70,21 +85,36 @@ impl UserRepository {
         .bind(&user.avatar_url)
         .bind(now.to_rfc3339())
         .bind(now.to_rfc3339())
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
 
         let user_id = result.last_insert_rowid();
+
+        // The identity the user signed up with
+        sqlx::query(
+            "INSERT INTO identities (user_id, provider, provider_id, email, linked_at)
+             VALUES (?, ?, ?, ?, ?)"
+        )
+        .bind(user_id)
+        .bind(&user.provider)
+        .bind(&user.provider_id)
+        .bind(&user.email)
+        .bind(now.to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
         
         // Fetch the created user
         let created_user = sqlx::query_as::<_, User>(
-            "SELECT id, provider, provider_id, username, email, avatar_url, created_at, last_login 
+            "SELECT id, provider, provider_id, username, email, avatar_url, created_at, last_login, username_edited_at, disabled_at, disabled_reason 
              FROM users 
              WHERE id = ?"
         )
         .bind(user_id)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
+        tx.commit().await?;
+
         Ok(created_user)
     }
 
@@ -101,21 +131,421 @@ impl UserRepository {
 
         Ok(())
     }
+
+    /// Record a login, applying any profile fields refreshed from the provider.
+    pub async fn record_login(&self, user_id: i64, update: &ProfileUpdate) -> Result<User, AppError> {
+        let user = sqlx::query_as::<_, User>(
+            "UPDATE users SET
+                last_login = ?,
+                username = CASE WHEN ? THEN ? ELSE username END,
+                email = CASE WHEN ? THEN ? ELSE email END,
+                avatar_url = CASE WHEN ? THEN ? ELSE avatar_url END
+             WHERE id = ?
+             RETURNING id, provider, provider_id, username, email, avatar_url, created_at, last_login, username_edited_at, disabled_at, disabled_reason"
+        )
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(update.username.is_some())
+        .bind(&update.username)
+        .bind(update.email.is_some())
+        .bind(update.email.clone().flatten())
+        .bind(update.avatar_url.is_some())
+        .bind(update.avatar_url.clone().flatten())
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Rename a user on their own behalf, returning `None` if the user is gone.
+    ///
+    /// The edit is remembered, so provider sync no longer overwrites it.
+    pub async fn update_username(&self, user_id: i64, username: &str) -> Result<Option<User>, AppError> {
+        let user = sqlx::query_as::<_, User>(
+            "UPDATE users SET username = ?, username_edited_at = ?
+             WHERE id = ?
+             RETURNING id, provider, provider_id, username, email, avatar_url, created_at, last_login, username_edited_at, disabled_at, disabled_reason"
+        )
+        .bind(username)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Link a provider identity to an existing user.
+    ///
+    /// Linking an identity the user already owns is a no-op; an identity that
+    /// belongs to someone else fails with `IdentityInUse`.
+    pub async fn link_identity(
+        &self,
+        user_id: i64,
+        provider: &str,
+        provider_id: &str,
+        email: Option<&str>,
+    ) -> Result<Identity, AppError> {
+        let result = sqlx::query(
+            "INSERT INTO identities (user_id, provider, provider_id, email, linked_at)
+             VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT(provider, provider_id) DO NOTHING"
+        )
+        .bind(user_id)
+        .bind(provider)
+        .bind(provider_id)
+        .bind(email)
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        let identity = sqlx::query_as::<_, Identity>(
+            "SELECT id, user_id, provider, provider_id, email, linked_at
+             FROM identities
+             WHERE provider = ? AND provider_id = ?"
+        )
+        .bind(provider)
+        .bind(provider_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        if identity.user_id != user_id {
+            return Err(AuthError::IdentityInUse.into());
+        }
+
+        if result.rows_affected() == 1 {
+            tracing::info!("Linked {} identity to user ID: {}", provider, user_id);
+        }
+
+        Ok(identity)
+    }
+
+    pub async fn list_identities(&self, user_id: i64) -> Result<Vec<Identity>, AppError> {
+        let identities = sqlx::query_as::<_, Identity>(
+            "SELECT id, user_id, provider, provider_id, email, linked_at
+             FROM identities
+             WHERE user_id = ?
+             ORDER BY linked_at, id"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(identities)
+    }
+
+    /// Remove one of a user's identities, refusing to remove the last one, and return it.
+    pub async fn unlink_identity(&self, user_id: i64, identity_id: i64) -> Result<Identity, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        let identities = sqlx::query_as::<_, Identity>(
+            "SELECT id, user_id, provider, provider_id, email, linked_at
+             FROM identities
+             WHERE user_id = ?
+             ORDER BY linked_at, id"
+        )
+        .bind(user_id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let Some(identity) = identities.iter().find(|identity| identity.id == identity_id).cloned() else {
+            return Err(AuthError::IdentityNotFound.into());
+        };
+
+        let Some(remaining) = identities.iter().find(|identity| identity.id != identity_id) else {
+            return Err(AuthError::LastIdentity.into());
+        };
+
+        sqlx::query("DELETE FROM identities WHERE id = ?")
+            .bind(identity_id)
+            .execute(&mut *tx)
+            .await?;
+
+        // Keep the user's primary identity pointing at one that still exists
+        sqlx::query(
+            "UPDATE users SET provider = ?, provider_id = ?
+             WHERE id = ? AND provider = ? AND provider_id = ?"
+        )
+        .bind(&remaining.provider)
+        .bind(&remaining.provider_id)
+        .bind(user_id)
+        .bind(&identity.provider)
+        .bind(&identity.provider_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        tracing::info!("Unlinked {} identity from user ID: {}", identity.provider, user_id);
+        Ok(identity)
+    }
+
+    /// One page of users, optionally filtered by a username or email substring.
+    pub async fn list_users(&self, query: &UserListQuery) -> Result<Vec<User>, AppError> {
+        let order = match query.sort {
+            UserSort::LastLoginDesc => "last_login DESC, id DESC",
+            UserSort::LastLoginAsc => "last_login ASC, id ASC",
+        };
+        let pattern = query.search.as_deref().map(like_pattern);
+
+        let users = sqlx::query_as::<_, User>(&format!(
+            "SELECT id, provider, provider_id, username, email, avatar_url, created_at, last_login, username_edited_at, disabled_at, disabled_reason
+             FROM users
+             WHERE {}
+             ORDER BY {}
+             LIMIT ?3 OFFSET ?4",
+            USER_LIST_FILTER, order
+        ))
+        .bind(pattern)
+        .bind(query.disabled)
+        .bind(query.per_page)
+        .bind((query.page.max(1) - 1) * query.per_page)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(users)
+    }
+
+    /// Number of users `list_users` pages through for the same filters.
+    pub async fn count_users(&self, query: &UserListQuery) -> Result<i64, AppError> {
+        let count = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM users WHERE {}", USER_LIST_FILTER))
+            .bind(query.search.as_deref().map(like_pattern))
+            .bind(query.disabled)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Disable a user, returning whether the user exists.
+    ///
+    /// Disabling an already disabled user only updates the reason.
+    pub async fn disable_user(&self, user_id: i64, reason: Option<&str>) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            "UPDATE users SET disabled_at = COALESCE(disabled_at, ?), disabled_reason = ? WHERE id = ?"
+        )
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(reason)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Re-enable a disabled user, returning whether the user exists.
+    pub async fn enable_user(&self, user_id: i64) -> Result<bool, AppError> {
+        let result = sqlx::query("UPDATE users SET disabled_at = NULL, disabled_reason = NULL WHERE id = ?")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delete a user together with their identities and roles.
+    pub async fn delete_user(&self, user_id: i64) -> Result<bool, AppError> {
+        let result = sqlx::query("DELETE FROM users WHERE id = ?")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Start tracking a new sign-in of a user.
+    pub async fn create_session(
+        &self,
+        user_id: i64,
+        ip_address: Option<&str>,
+        user_agent: Option<&str>,
+    ) -> Result<UserSession, AppError> {
+        let now = chrono::Utc::now();
+        let session = sqlx::query_as::<_, UserSession>(
+            "INSERT INTO user_sessions (id, user_id, created_at, last_seen, ip_address, user_agent)
+             VALUES (?, ?, ?, ?, ?, ?)
+             RETURNING id, user_id, created_at, last_seen, ip_address, user_agent"
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(user_id)
+        .bind(now.to_rfc3339())
+        .bind(now.to_rfc3339())
+        .bind(ip_address)
+        .bind(user_agent)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(session)
+    }
+
+    /// Whether a tracked session of the user still exists, i.e. was not revoked.
+    pub async fn session_exists(&self, user_id: i64, session_id: &str) -> Result<bool, AppError> {
+        let exists = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM user_sessions WHERE id = ? AND user_id = ?)"
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(exists)
+    }
+
+    pub async fn touch_session(&self, session_id: &str, last_seen: chrono::DateTime<chrono::Utc>) -> Result<(), AppError> {
+        sqlx::query("UPDATE user_sessions SET last_seen = ? WHERE id = ?")
+            .bind(last_seen.to_rfc3339())
+            .bind(session_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_sessions(&self, user_id: i64) -> Result<Vec<UserSession>, AppError> {
+        let sessions = sqlx::query_as::<_, UserSession>(
+            "SELECT id, user_id, created_at, last_seen, ip_address, user_agent
+             FROM user_sessions
+             WHERE user_id = ?
+             ORDER BY last_seen DESC, created_at DESC"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    /// Revoke one of a user's sessions, returning whether it existed.
+    pub async fn revoke_session(&self, user_id: i64, session_id: &str) -> Result<bool, AppError> {
+        let result = sqlx::query("DELETE FROM user_sessions WHERE id = ? AND user_id = ?")
+            .bind(session_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revoke every session of a user, returning how many there were.
+    pub async fn revoke_all_sessions(&self, user_id: i64) -> Result<u64, AppError> {
+        let result = sqlx::query("DELETE FROM user_sessions WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Forget sessions idle since `idle_before` or started before `issued_before`.
+    pub async fn delete_stale_sessions(
+        &self,
+        idle_before: chrono::DateTime<chrono::Utc>,
+        issued_before: chrono::DateTime<chrono::Utc>,
+    ) -> Result<u64, AppError> {
+        let result = sqlx::query("DELETE FROM user_sessions WHERE last_seen < ? OR created_at < ?")
+            .bind(idle_before.to_rfc3339())
+            .bind(issued_before.to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn list_roles(&self, user_id: i64) -> Result<Vec<Role>, AppError> {
+        let names: Vec<String> =
+            sqlx::query_scalar("SELECT role FROM user_roles WHERE user_id = ? ORDER BY role")
+                .bind(user_id)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(names
+            .iter()
+            .filter_map(|name| {
+                let role = Role::parse(name);
+                if role.is_none() {
+                    tracing::warn!("Ignoring unknown role '{}' of user ID: {}", name, user_id);
+                }
+                role
+            })
+            .collect())
+    }
+
+    /// Grant `role` to a user, returning whether they did not already hold it.
+    pub async fn grant_role(&self, user_id: i64, role: Role) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            "INSERT INTO user_roles (user_id, role, granted_at) VALUES (?, ?, ?)
+             ON CONFLICT (user_id, role) DO NOTHING"
+        )
+        .bind(user_id)
+        .bind(role.as_str())
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Revoke `role` from a user, returning whether they held it.
+    pub async fn revoke_role(&self, user_id: i64, role: Role) -> Result<bool, AppError> {
+        let result = sqlx::query("DELETE FROM user_roles WHERE user_id = ? AND role = ?")
+            .bind(user_id)
+            .bind(role.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Make the oldest user an admin while nobody holds the role yet.
+    ///
+    /// The check and the grant are one statement, so two first logins racing
+    /// each other cannot both become admin.
+    pub async fn grant_initial_admin(&self, user_id: i64) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            "INSERT INTO user_roles (user_id, role, granted_at)
+             SELECT ?, ?, ?
+             WHERE NOT EXISTS (SELECT 1 FROM user_roles WHERE role = ?)
+               AND (SELECT MIN(id) FROM users) = ?"
+        )
+        .bind(user_id)
+        .bind(Role::Admin.as_str())
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(Role::Admin.as_str())
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
 }
+
+// Search (?1) and disabled (?2) filters shared by listing and counting users
+const USER_LIST_FILTER: &str = "(?1 IS NULL OR username LIKE ?1 ESCAPE '\\' OR email LIKE ?1 ESCAPE '\\')
+     AND (?2 IS NULL OR (disabled_at IS NOT NULL) = ?2)";
+
+// LIKE pattern matching `search` anywhere, with its wildcards taken literally
+fn like_pattern(search: &str) -> String {
+    let escaped = search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use tempfile::NamedTempFile;
 
-    async fn setup_test_db() -> Database {
+    async fn setup_test_db() -> (Database, NamedTempFile) {
         let temp_file = NamedTempFile::new().unwrap();
         let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
-        Database::new(&database_url).await.unwrap()
+        (Database::new(&database_url).await.unwrap(), temp_file)
     }
 
     #[tokio::test]
     async fn test_create_and_find_user() {
-        let db = setup_test_db().await;
+        let (db, _db_file) = setup_test_db().await;
         let repo = UserRepository::new(db.pool().clone());
 
         let create_user = CreateUser {
@@ -146,9 +576,32 @@ mod tests {
         assert_eq!(found_user.email, Some("test@example.com".to_string()));
     }
 
+    #[tokio::test]
+    async fn test_find_by_id() {
+        let (db, _db_file) = setup_test_db().await;
+        let repo = UserRepository::new(db.pool().clone());
+
+        let created_user = repo
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "12345".to_string(),
+                username: "testuser".to_string(),
+                email: Some("test@example.com".to_string()),
+                avatar_url: Some("https://example.com/avatar.jpg".to_string()),
+            })
+            .await
+            .unwrap();
+
+        let found_user = repo.find_by_id(created_user.id).await.unwrap().unwrap();
+        assert_eq!(found_user.username, "testuser");
+        assert_eq!(found_user.avatar_url, Some("https://example.com/avatar.jpg".to_string()));
+
+        assert!(repo.find_by_id(created_user.id + 1).await.unwrap().is_none());
+    }
+
     #[tokio::test]
     async fn test_find_nonexistent_user() {
-        let db = setup_test_db().await;
+        let (db, _db_file) = setup_test_db().await;
         let repo = UserRepository::new(db.pool().clone());
 
         let result = repo
@@ -161,7 +614,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_update_last_login() {
-        let db = setup_test_db().await;
+        let (db, _db_file) = setup_test_db().await;
         let repo = UserRepository::new(db.pool().clone());
 
         let create_user = CreateUser {
@@ -191,9 +644,42 @@ mod tests {
         assert!(updated_user.last_login > original_login);
     }
 
+    #[tokio::test]
+    async fn test_record_login_applies_profile_update() {
+        let (db, _db_file) = setup_test_db().await;
+        let repo = UserRepository::new(db.pool().clone());
+
+        let created_user = repo
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "12345".to_string(),
+                username: "octocat".to_string(),
+                email: Some("old@example.com".to_string()),
+                avatar_url: Some("https://example.com/old.png".to_string()),
+            })
+            .await
+            .unwrap();
+
+        // Nothing but last_login changes for an empty update
+        let user = repo.record_login(created_user.id, &ProfileUpdate::default()).await.unwrap();
+        assert_eq!(user.username, "octocat");
+        assert_eq!(user.email, Some("old@example.com".to_string()));
+        assert!(user.last_login >= created_user.last_login);
+
+        let update = ProfileUpdate {
+            username: Some("octocat-renamed".to_string()),
+            email: None,
+            avatar_url: Some(None),
+        };
+        let user = repo.record_login(created_user.id, &update).await.unwrap();
+        assert_eq!(user.username, "octocat-renamed");
+        assert_eq!(user.email, Some("old@example.com".to_string()));
+        assert_eq!(user.avatar_url, None);
+    }
+
     #[tokio::test]
     async fn test_unique_constraint() {
-        let db = setup_test_db().await;
+        let (db, _db_file) = setup_test_db().await;
         let repo = UserRepository::new(db.pool().clone());
 
         let create_user1 = CreateUser {
@@ -222,7 +708,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_different_providers_same_id() {
-        let db = setup_test_db().await;
+        let (db, _db_file) = setup_test_db().await;
         let repo = UserRepository::new(db.pool().clone());
 
         let github_user = CreateUser {
@@ -249,4 +735,301 @@ mod tests {
         assert_eq!(github_created.provider, "github");
         assert_eq!(microsoft_created.provider, "microsoft");
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_link_list_and_unlink_identities() {
+        let (db, _db_file) = setup_test_db().await;
+        let repo = UserRepository::new(db.pool().clone());
+
+        let user = repo
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "gh-1".to_string(),
+                username: "linker".to_string(),
+                email: Some("linker@example.com".to_string()),
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+
+        // Creating a user records its first identity
+        let identities = repo.list_identities(user.id).await.unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].provider, "github");
+
+        // A linked identity signs in as the same user; linking twice is a no-op
+        let linked = repo
+            .link_identity(user.id, "microsoft", "ms-1", Some("linker@contoso.com"))
+            .await
+            .unwrap();
+        repo.link_identity(user.id, "microsoft", "ms-1", None).await.unwrap();
+        let found = repo.find_by_provider_id("microsoft", "ms-1").await.unwrap().unwrap();
+        assert_eq!(found.id, user.id);
+        assert_eq!(repo.list_identities(user.id).await.unwrap().len(), 2);
+
+        // Unlinking the original identity moves the primary identity over
+        repo.unlink_identity(user.id, identities[0].id).await.unwrap();
+        assert!(repo.find_by_provider_id("github", "gh-1").await.unwrap().is_none());
+        let found = repo.find_by_provider_id("microsoft", "ms-1").await.unwrap().unwrap();
+        assert_eq!(found.provider, "microsoft");
+        assert_eq!(found.provider_id, "ms-1");
+
+        // The last identity cannot be removed
+        let result = repo.unlink_identity(user.id, linked.id).await;
+        assert!(matches!(result, Err(AppError::Auth(AuthError::LastIdentity))));
+
+        // The unlinked account can sign up again as a new user
+        let result = repo
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "gh-1".to_string(),
+                username: "linker".to_string(),
+                email: None,
+                avatar_url: None,
+            })
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_grant_and_revoke_roles() {
+        let (db, _db_file) = setup_test_db().await;
+        let repo = UserRepository::new(db.pool().clone());
+
+        let create = |provider_id: &str| CreateUser {
+            provider: "github".to_string(),
+            provider_id: provider_id.to_string(),
+            username: provider_id.to_string(),
+            email: None,
+            avatar_url: None,
+        };
+        let first = repo.create_user(create("gh-1")).await.unwrap();
+        let second = repo.create_user(create("gh-2")).await.unwrap();
+
+        // Only the oldest user is bootstrapped, and only while there is no admin
+        assert!(!repo.grant_initial_admin(second.id).await.unwrap());
+        assert!(repo.grant_initial_admin(first.id).await.unwrap());
+        assert!(!repo.grant_initial_admin(first.id).await.unwrap());
+        assert_eq!(repo.list_roles(first.id).await.unwrap(), vec![Role::Admin]);
+
+        assert!(repo.grant_role(second.id, Role::Admin).await.unwrap());
+        assert!(!repo.grant_role(second.id, Role::Admin).await.unwrap());
+        assert!(repo.revoke_role(second.id, Role::Admin).await.unwrap());
+        assert!(repo.list_roles(second.id).await.unwrap().is_empty());
+
+        // With every admin gone, the bootstrap applies again
+        repo.revoke_role(first.id, Role::Admin).await.unwrap();
+        assert!(repo.grant_initial_admin(first.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_list_search_and_page_users() {
+        let (db, _db_file) = setup_test_db().await;
+        let repo = UserRepository::new(db.pool().clone());
+
+        let mut user_ids = Vec::new();
+        for (provider_id, username, email) in [
+            ("gh-1", "alice", Some("alice@example.com")),
+            ("gh-2", "bob", Some("bob@corp.example")),
+            ("gh-3", "carol_50%", None),
+        ] {
+            let user = repo
+                .create_user(CreateUser {
+                    provider: "github".to_string(),
+                    provider_id: provider_id.to_string(),
+                    username: username.to_string(),
+                    email: email.map(str::to_string),
+                    avatar_url: None,
+                })
+                .await
+                .unwrap();
+            repo.update_last_login(user.id).await.unwrap();
+            user_ids.push(user.id);
+        }
+
+        let usernames = |users: Vec<User>| users.into_iter().map(|user| user.username).collect::<Vec<_>>();
+        let query = UserListQuery {
+            page: 1,
+            per_page: 2,
+            ..UserListQuery::default()
+        };
+
+        // Most recent login first, two per page
+        assert_eq!(usernames(repo.list_users(&query).await.unwrap()), ["carol_50%", "bob"]);
+        let page_two = UserListQuery { page: 2, ..query.clone() };
+        assert_eq!(usernames(repo.list_users(&page_two).await.unwrap()), ["alice"]);
+        let oldest_first = UserListQuery { sort: UserSort::LastLoginAsc, ..query.clone() };
+        assert_eq!(usernames(repo.list_users(&oldest_first).await.unwrap()), ["alice", "bob"]);
+        assert_eq!(repo.count_users(&query).await.unwrap(), 3);
+
+        // Search matches usernames and emails case-insensitively, with wildcards taken literally
+        let search = UserListQuery { search: Some("CORP".to_string()), ..query.clone() };
+        assert_eq!(usernames(repo.list_users(&search).await.unwrap()), ["bob"]);
+        let searching = |search: &str| UserListQuery { search: Some(search.to_string()), ..query.clone() };
+        assert_eq!(repo.count_users(&searching("%")).await.unwrap(), 1);
+        assert_eq!(repo.count_users(&searching("_")).await.unwrap(), 1);
+        assert_eq!(repo.count_users(&searching("example")).await.unwrap(), 2);
+
+        // Disabled users can be listed on their own or left out
+        repo.disable_user(user_ids[1], None).await.unwrap();
+        let disabled = UserListQuery { disabled: Some(true), ..query.clone() };
+        assert_eq!(usernames(repo.list_users(&disabled).await.unwrap()), ["bob"]);
+        let enabled = UserListQuery { disabled: Some(false), ..searching("example") };
+        assert_eq!(usernames(repo.list_users(&enabled).await.unwrap()), ["alice"]);
+        assert_eq!(repo.count_users(&enabled).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disable_enable_and_delete_user() {
+        let (db, _db_file) = setup_test_db().await;
+        let repo = UserRepository::new(db.pool().clone());
+
+        let user = repo
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "12345".to_string(),
+                username: "testuser".to_string(),
+                email: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        repo.grant_role(user.id, Role::Admin).await.unwrap();
+
+        assert!(repo.disable_user(user.id, None).await.unwrap());
+        let disabled = repo.find_by_id(user.id).await.unwrap().unwrap();
+        assert!(disabled.is_disabled());
+        assert!(disabled.disabled_reason.is_none());
+
+        // Disabling again keeps the original timestamp and updates the reason
+        repo.disable_user(user.id, Some("Left the company")).await.unwrap();
+        let redisabled = repo.find_by_id(user.id).await.unwrap().unwrap();
+        assert_eq!(redisabled.disabled_at, disabled.disabled_at);
+        assert_eq!(redisabled.disabled_reason.as_deref(), Some("Left the company"));
+
+        assert!(repo.enable_user(user.id).await.unwrap());
+        let enabled = repo.find_by_id(user.id).await.unwrap().unwrap();
+        assert!(!enabled.is_disabled());
+        assert!(enabled.disabled_reason.is_none());
+
+        // Deleting removes the user's identities and roles with it
+        assert!(repo.delete_user(user.id).await.unwrap());
+        assert!(!repo.delete_user(user.id).await.unwrap());
+        assert!(!repo.disable_user(user.id, None).await.unwrap());
+        assert!(repo.find_by_provider_id("github", "12345").await.unwrap().is_none());
+        assert!(repo.list_identities(user.id).await.unwrap().is_empty());
+        assert!(repo.list_roles(user.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_track_and_revoke_sessions() {
+        let (db, _db_file) = setup_test_db().await;
+        let repo = UserRepository::new(db.pool().clone());
+
+        let user = repo
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "12345".to_string(),
+                username: "testuser".to_string(),
+                email: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+
+        let laptop = repo.create_session(user.id, Some("192.0.2.1"), Some("Firefox")).await.unwrap();
+        let phone = repo.create_session(user.id, None, None).await.unwrap();
+        assert_ne!(laptop.id, phone.id);
+        assert_eq!(laptop.ip_address.as_deref(), Some("192.0.2.1"));
+
+        // Most recently active first
+        let later = chrono::Utc::now() + chrono::Duration::minutes(5);
+        repo.touch_session(&laptop.id, later).await.unwrap();
+        let sessions = repo.list_sessions(user.id).await.unwrap();
+        assert_eq!(sessions.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(), [laptop.id.as_str(), phone.id.as_str()]);
+
+        // Sessions can only be revoked by their owner
+        assert!(!repo.revoke_session(user.id + 1, &phone.id).await.unwrap());
+        assert!(repo.revoke_session(user.id, &phone.id).await.unwrap());
+        assert!(!repo.session_exists(user.id, &phone.id).await.unwrap());
+        assert!(repo.session_exists(user.id, &laptop.id).await.unwrap());
+
+        // Only sessions idle since before the cutoff are stale
+        let stale = repo.create_session(user.id, None, None).await.unwrap();
+        let cutoff = chrono::Utc::now() + chrono::Duration::minutes(1);
+        assert_eq!(repo.delete_stale_sessions(cutoff, chrono::Utc::now() - chrono::Duration::hours(1)).await.unwrap(), 1);
+        assert!(!repo.session_exists(user.id, &stale.id).await.unwrap());
+
+        assert_eq!(repo.revoke_all_sessions(user.id).await.unwrap(), 1);
+        assert!(repo.list_sessions(user.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_link_identity_owned_by_another_user() {
+        let (db, _db_file) = setup_test_db().await;
+        let repo = UserRepository::new(db.pool().clone());
+
+        let first = repo
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "gh-1".to_string(),
+                username: "first".to_string(),
+                email: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        let second = repo
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "gh-2".to_string(),
+                username: "second".to_string(),
+                email: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+
+        let result = repo.link_identity(second.id, "github", "gh-1", None).await;
+        assert!(matches!(result, Err(AppError::Auth(AuthError::IdentityInUse))));
+
+        // Someone else's identity cannot be unlinked either
+        let first_identity = &repo.list_identities(first.id).await.unwrap()[0];
+        let result = repo.unlink_identity(second.id, first_identity.id).await;
+        assert!(matches!(result, Err(AppError::Auth(AuthError::IdentityNotFound))));
+    }
+
+    #[tokio::test]
+    async fn test_identities_migration_moves_existing_users() {
+        use sqlx::Executor;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
+        let pool = SqlitePool::connect(&database_url).await.unwrap();
+
+        // A database as it looked before identities existed
+        pool.execute(include_str!("../migrations/001_create_users_table.sql")).await.unwrap();
+        pool.execute(include_str!("../migrations/002_create_login_states_table.sql")).await.unwrap();
+        sqlx::query(
+            "INSERT INTO users (provider, provider_id, username, email, created_at, last_login)
+             VALUES ('github', 'legacy', 'olduser', 'old@example.com', ?, ?)"
+        )
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(chrono::Utc::now().to_rfc3339())
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        pool.execute(include_str!("../migrations/004_create_identities_table.sql")).await.unwrap();
+
+        let identities = sqlx::query_as::<_, Identity>(
+            "SELECT id, user_id, provider, provider_id, email, linked_at FROM identities"
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].provider_id, "legacy");
+        assert_eq!(identities[0].email, Some("old@example.com".to_string()));
+    }
+}
@@ -1,8 +1,11 @@
 use axum::{
-    http::StatusCode,
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
     response::{IntoResponse, Response, Redirect},
     Json,
 };
+use serde::Serialize;
 use serde_json::json;
 
 #[derive(Debug, thiserror::Error)]
@@ -24,6 +27,15 @@ pub enum AppError {
     
     #[error("Migration error: {0}")]
     Migration(#[from] sqlx::migrate::MigrateError),
+    
+    #[error("OAuth2 provider error: {0}")]
+    OAuth(#[from] OAuthError),
+    
+    #[error("Invalid request: {0}")]
+    BadRequest(String),
+    
+    #[error("{0} not found")]
+    NotFound(&'static str),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -51,94 +63,469 @@ pub enum AuthError {
     
     #[error("Invalid session")]
     InvalidSession,
+    
+    #[error("Identity is already linked to another user")]
+    IdentityInUse,
+    
+    #[error("Identity not found")]
+    IdentityNotFound,
+    
+    #[error("Cannot unlink the last identity of a user")]
+    LastIdentity,
+    
+    #[error("User account no longer exists")]
+    AccountNotFound,
+    
+    #[error("OpenID Connect discovery failed: {0}")]
+    Discovery(String),
+    
+    #[error("Invalid ID token: {0}")]
+    InvalidIdToken(String),
+    
+    #[error("Microsoft tenant not allowed: {0}")]
+    TenantNotAllowed(String),
+    
+    #[error("Access denied: {0}")]
+    AccessDenied(String),
+    
+    #[error("Missing required role")]
+    Forbidden,
+    
+    #[error("User account is disabled")]
+    AccountDisabled(Option<String>),
+    
+    #[error("Session was revoked")]
+    SessionRevoked,
+    
+    #[error("Session not found")]
+    UnknownSession,
+    
+    #[error("Invalid or expired API token")]
+    InvalidApiToken,
+    
+    #[error("API token lacks the {0} scope")]
+    InsufficientScope(&'static str),
+    
+    #[error("API token not found")]
+    UnknownApiToken,
+    
+    #[error("{provider} returned an error: {error}")]
+    ProviderError { provider: String, error: String },
 }
 
-impl IntoResponse for AppError {
+/// Errors answered by this app's own OpenID provider endpoints, in the
+/// shape RFC 6749 prescribes.
+#[derive(Debug, thiserror::Error)]
+pub enum OAuthError {
+    // The client or redirect URI cannot be trusted, so the user is told instead of redirected
+    #[error("Unknown client or unregistered redirect URI")]
+    UnknownClient,
+    
+    // Sent back to the client's redirect URI
+    #[error("Authorization refused: {error}: {description}")]
+    AuthorizationRefused {
+        redirect_uri: String,
+        state: Option<String>,
+        error: &'static str,
+        description: String,
+    },
+    
+    #[error("Invalid token request: {0}")]
+    InvalidRequest(String),
+    
+    #[error("Client authentication failed")]
+    InvalidClient,
+    
+    #[error("Invalid grant: {0}")]
+    InvalidGrant(String),
+    
+    #[error("Unsupported grant type: {0}")]
+    UnsupportedGrantType(String),
+    
+    #[error("Invalid access token")]
+    InvalidToken,
+    
+    #[error("Authorization server error: {0}")]
+    ServerError(String),
+}
+
+impl OAuthError {
+    /// The RFC 6749 error code sent to the client.
+    pub fn code(&self) -> &'static str {
+        match self {
+            OAuthError::UnknownClient => "invalid_request",
+            OAuthError::AuthorizationRefused { error, .. } => error,
+            OAuthError::InvalidRequest(_) => "invalid_request",
+            OAuthError::InvalidClient => "invalid_client",
+            OAuthError::InvalidGrant(_) => "invalid_grant",
+            OAuthError::UnsupportedGrantType(_) => "unsupported_grant_type",
+            OAuthError::InvalidToken => "invalid_token",
+            OAuthError::ServerError(_) => "server_error",
+        }
+    }
+    
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OAuthError::UnknownClient => "oauth_unknown_client",
+            OAuthError::AuthorizationRefused { .. } => "oauth_authorization_refused",
+            OAuthError::InvalidRequest(_) => "oauth_invalid_request",
+            OAuthError::InvalidClient => "oauth_invalid_client",
+            OAuthError::InvalidGrant(_) => "oauth_invalid_grant",
+            OAuthError::UnsupportedGrantType(_) => "oauth_unsupported_grant_type",
+            OAuthError::InvalidToken => "oauth_invalid_token",
+            OAuthError::ServerError(_) => "oauth_server_error",
+        }
+    }
+}
+
+impl IntoResponse for OAuthError {
     fn into_response(self) -> Response {
+        let status = match self {
+            OAuthError::AuthorizationRefused { ref redirect_uri, ref state, error, ref description } => {
+                tracing::info!("Authorization refused: {}: {}", error, description);
+                let url = crate::oauth_server::redirect_url(
+                    redirect_uri,
+                    &[("error", error), ("error_description", description)],
+                    state.as_deref(),
+                );
+                return Redirect::to(&url).into_response();
+            }
+            OAuthError::InvalidClient | OAuthError::InvalidToken => StatusCode::UNAUTHORIZED,
+            OAuthError::ServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        
+        if status == StatusCode::INTERNAL_SERVER_ERROR {
+            tracing::error!("OAuth2 provider error: {}", self);
+        } else {
+            tracing::warn!("OAuth2 request rejected: {}", self);
+        }
+        
+        let body = Json(json!({
+            "error": self.code(),
+            // Server errors keep their details in the log
+            "error_description": match self {
+                OAuthError::ServerError(_) => "The authorization server failed to process the request.".to_string(),
+                _ => self.to_string(),
+            },
+        }));
+        match self {
+            // Tell the client how to authenticate next time
+            OAuthError::InvalidClient => {
+                (status, [(header::WWW_AUTHENTICATE, "Basic realm=\"oauth\"")], body).into_response()
+            }
+            OAuthError::InvalidToken => {
+                (status, [(header::WWW_AUTHENTICATE, "Bearer error=\"invalid_token\"")], body).into_response()
+            }
+            _ => (status, [(header::CACHE_CONTROL, "no-store")], body).into_response(),
+        }
+    }
+}
+
+impl AppError {
+    /// Short, stable name of the error, as recorded in the audit log.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::Database(_) => "database",
+            AppError::Auth(auth_error) => auth_error.kind(),
+            AppError::Template(_) => "template",
+            AppError::Http(_) => "http",
+            AppError::Config(_) => "config",
+            AppError::Migration(_) => "migration",
+            AppError::OAuth(oauth_error) => oauth_error.kind(),
+            AppError::BadRequest(_) => "bad_request",
+            AppError::NotFound(_) => "not_found",
+        }
+    }
+}
+
+impl AuthError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AuthError::StateMismatch => "state_mismatch",
+            AuthError::TokenExchange(_) => "token_exchange",
+            AuthError::ProfileFetch(_) => "profile_fetch",
+            AuthError::NotAuthenticated => "not_authenticated",
+            AuthError::InvalidProvider(_) => "invalid_provider",
+            AuthError::MissingAuthCode => "missing_auth_code",
+            AuthError::SessionExpired => "session_expired",
+            AuthError::InvalidSession => "invalid_session",
+            AuthError::IdentityInUse => "identity_in_use",
+            AuthError::IdentityNotFound => "identity_not_found",
+            AuthError::LastIdentity => "last_identity",
+            AuthError::AccountNotFound => "account_not_found",
+            AuthError::Discovery(_) => "discovery",
+            AuthError::InvalidIdToken(_) => "invalid_id_token",
+            AuthError::TenantNotAllowed(_) => "tenant_not_allowed",
+            AuthError::AccessDenied(_) => "access_denied",
+            AuthError::Forbidden => "forbidden",
+            AuthError::AccountDisabled(_) => "account_disabled",
+            AuthError::SessionRevoked => "session_revoked",
+            AuthError::UnknownSession => "unknown_session",
+            AuthError::InvalidApiToken => "invalid_api_token",
+            AuthError::InsufficientScope(_) => "insufficient_scope",
+            AuthError::UnknownApiToken => "unknown_api_token",
+            AuthError::ProviderError { .. } => "provider_error",
+        }
+    }
+}
+
+impl AuthError {
+    /// What the user is told, on a page or in a JSON error.
+    pub fn user_message(&self) -> String {
+        let message = match self {
+            AuthError::NotAuthenticated | AuthError::InvalidSession => "Please sign in.",
+            AuthError::SessionExpired => "Your session has expired. Please sign in again.",
+            AuthError::IdentityInUse => "That account is already linked to another user.",
+            AuthError::IdentityNotFound => "That linked account could not be found.",
+            AuthError::LastIdentity => "You cannot unlink your only sign-in method.",
+            AuthError::UnknownSession => "That session has already ended.",
+            AuthError::UnknownApiToken => "That token has already been revoked.",
+            AuthError::InvalidApiToken => "The API token is invalid, expired or revoked.",
+            AuthError::InsufficientScope(scope) => {
+                return format!("The API token does not have the {} scope.", scope);
+            }
+            AuthError::Forbidden => "You do not have permission to access this page.",
+            AuthError::AccountDisabled(Some(reason)) => {
+                return format!(
+                    "Your account has been disabled: {}. Contact an administrator if you think this is a mistake.",
+                    reason
+                );
+            }
+            AuthError::AccountDisabled(None) => {
+                "Your account has been disabled. Contact an administrator if you think this is a mistake."
+            }
+            AuthError::ProviderError { provider, .. } => {
+                return format!("{} authentication failed. Please try again.", provider);
+            }
+            AuthError::StateMismatch => "Security error during login. Please try again.",
+            AuthError::TokenExchange(_) => "Failed to complete login. Please try again.",
+            AuthError::ProfileFetch(_) => "Failed to retrieve your profile. Please try again.",
+            AuthError::InvalidProvider(_) => "Invalid login provider selected.",
+            AuthError::MissingAuthCode => "Login was incomplete. Please try again.",
+            AuthError::AccountNotFound => "Your account no longer exists. Please sign in again.",
+            AuthError::SessionRevoked => "You were signed out of this session. Please sign in again.",
+            AuthError::InvalidIdToken(_) => "We could not verify your sign-in. Please try again.",
+            AuthError::AccessDenied(_) => "Your account isn't authorized to use this application. Contact an administrator if you need access.",
+            AuthError::TenantNotAllowed(_) => "Your Microsoft organization is not allowed to sign in to this application. Please use an account from an approved organization.",
+            AuthError::Discovery(_) => "Authentication failed. Please try again.",
+        };
+        message.to_string()
+    }
+}
+
+/// An error as structured JSON, for API clients and requests that accept JSON.
+///
+/// `error` is the stable [`AppError::kind`], `message` is safe to show.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonError {
+    #[serde(skip)]
+    pub status: StatusCode,
+    pub error: &'static str,
+    pub message: String,
+}
+
+impl IntoResponse for JsonError {
+    fn into_response(self) -> Response {
+        (self.status, [(header::CACHE_CONTROL, "no-store")], Json(self)).into_response()
+    }
+}
+
+impl AppError {
+    /// The JSON form of this error; server errors keep their details in the log.
+    pub fn json_error(&self) -> JsonError {
+        let (status, message) = match self {
+            AppError::Database(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "A database error occurred. Please try again later.".to_string(),
+            ),
+            AppError::Template(_) => (StatusCode::INTERNAL_SERVER_ERROR, "A page rendering error occurred.".to_string()),
+            AppError::Http(_) => (
+                StatusCode::BAD_GATEWAY,
+                "Failed to communicate with external service. Please try again later.".to_string(),
+            ),
+            AppError::Config(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Server configuration error.".to_string()),
+            AppError::Migration(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Database initialization failed.".to_string()),
+            AppError::OAuth(oauth_error) => (StatusCode::BAD_REQUEST, oauth_error.to_string()),
+            AppError::BadRequest(message) => (StatusCode::BAD_REQUEST, message.clone()),
+            AppError::NotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::Auth(auth_error) => {
+                let status = match auth_error {
+                    AuthError::NotAuthenticated
+                    | AuthError::InvalidSession
+                    | AuthError::SessionExpired
+                    | AuthError::SessionRevoked
+                    | AuthError::AccountNotFound
+                    | AuthError::InvalidApiToken
+                    | AuthError::InvalidIdToken(_) => StatusCode::UNAUTHORIZED,
+                    AuthError::Forbidden
+                    | AuthError::InsufficientScope(_)
+                    | AuthError::AccountDisabled(_)
+                    | AuthError::AccessDenied(_)
+                    | AuthError::TenantNotAllowed(_) => StatusCode::FORBIDDEN,
+                    AuthError::IdentityNotFound | AuthError::UnknownSession | AuthError::UnknownApiToken => {
+                        StatusCode::NOT_FOUND
+                    }
+                    AuthError::IdentityInUse | AuthError::LastIdentity => StatusCode::CONFLICT,
+                    AuthError::StateMismatch | AuthError::InvalidProvider(_) | AuthError::MissingAuthCode => {
+                        StatusCode::BAD_REQUEST
+                    }
+                    AuthError::TokenExchange(_)
+                    | AuthError::ProfileFetch(_)
+                    | AuthError::Discovery(_)
+                    | AuthError::ProviderError { .. } => StatusCode::BAD_GATEWAY,
+                };
+                (status, auth_error.user_message())
+            }
+        };
+
+        JsonError {
+            status,
+            error: self.kind(),
+            message,
+        }
+    }
+
+    // The response for browsers: login redirects and dashboard messages
+    fn page_response(self) -> Response {
         match self {
             // Authentication errors that should redirect to login
             AppError::Auth(AuthError::NotAuthenticated) 
-            | AppError::Auth(AuthError::SessionExpired) 
             | AppError::Auth(AuthError::InvalidSession) => {
                 tracing::warn!("Authentication required, redirecting to login: {}", self);
                 Redirect::to("/login").into_response()
             }
             
-            // OAuth2 errors that should redirect to login with error message
-            AppError::Auth(auth_error) => {
-                tracing::error!("Authentication error: {}", auth_error);
-                let error_msg = match auth_error {
-                    AuthError::StateMismatch => "Security error during login. Please try again.",
-                    AuthError::TokenExchange(_) => "Failed to complete login. Please try again.",
-                    AuthError::ProfileFetch(_) => "Failed to retrieve your profile. Please try again.",
-                    AuthError::InvalidProvider(_) => "Invalid login provider selected.",
-                    AuthError::MissingAuthCode => "Login was incomplete. Please try again.",
-                    _ => "Authentication failed. Please try again.",
-                };
-                
-                let redirect_url = format!("/login?error={}", urlencoding::encode(error_msg));
+            // Expired sessions go back to login with an explanation
+            AppError::Auth(ref auth_error @ AuthError::SessionExpired) => {
+                tracing::info!("Session expired, redirecting to login");
+                let redirect_url = format!("/login?error={}", urlencoding::encode(&auth_error.user_message()));
                 Redirect::to(&redirect_url).into_response()
             }
             
-            // Server errors
-            AppError::Database(ref db_error) => {
-                tracing::error!("Database error: {}", db_error);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(json!({
-                        "error": "Database error",
-                        "message": "A database error occurred. Please try again later."
-                    }))
-                ).into_response()
+            // Account linking and session errors happen while signed in, so report them on the dashboard
+            AppError::Auth(
+                ref auth_error @ (AuthError::IdentityInUse
+                | AuthError::IdentityNotFound
+                | AuthError::LastIdentity
+                | AuthError::UnknownSession
+                | AuthError::UnknownApiToken),
+            ) => {
+                tracing::warn!("Account management error: {}", auth_error);
+                let redirect_url = format!("/dashboard?error={}", urlencoding::encode(&auth_error.user_message()));
+                Redirect::to(&redirect_url).into_response()
+            }
+            
+            // Disabled accounts are told so, with the admin's reason when one was given
+            AppError::Auth(ref auth_error @ AuthError::AccountDisabled(_)) => {
+                tracing::warn!("Refusing disabled account");
+                let redirect_url = format!("/login?error={}", urlencoding::encode(&auth_error.user_message()));
+                Redirect::to(&redirect_url).into_response()
+            }
+            
+            // The provider refused or failed the sign-in before redirecting back
+            AppError::Auth(ref auth_error @ AuthError::ProviderError { ref provider, ref error }) => {
+                tracing::error!("{} OAuth2 error: {}", provider, error);
+                let redirect_url = format!("/login?error={}", urlencoding::encode(&auth_error.user_message()));
+                Redirect::to(&redirect_url).into_response()
+            }
+            
+            // API tokens are answered as RFC 6750 prescribes, never with the login page
+            AppError::Auth(AuthError::InvalidApiToken) => {
+                tracing::warn!("Rejected API token");
+                let challenge = "Bearer error=\"invalid_token\"".to_string();
+                ([(header::WWW_AUTHENTICATE, challenge)], self.json_error()).into_response()
             }
             
-            AppError::Template(ref template_error) => {
-                tracing::error!("Template error: {}", template_error);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(json!({
-                        "error": "Template error",
-                        "message": "A page rendering error occurred."
-                    }))
-                ).into_response()
+            AppError::Auth(AuthError::InsufficientScope(scope)) => {
+                tracing::warn!("API token lacks the {} scope", scope);
+                let challenge = format!("Bearer error=\"insufficient_scope\", scope=\"{}\"", scope);
+                ([(header::WWW_AUTHENTICATE, challenge)], self.json_error()).into_response()
             }
             
-            AppError::Http(ref http_error) => {
-                tracing::error!("HTTP client error: {}", http_error);
-                (
-                    StatusCode::BAD_GATEWAY,
-                    Json(json!({
-                        "error": "External service error",
-                        "message": "Failed to communicate with external service. Please try again later."
-                    }))
-                ).into_response()
+            // Signed in, but without the role the page requires
+            AppError::Auth(AuthError::Forbidden) => self.json_error().into_response(),
+            
+            // OAuth2 errors that should redirect to login with error message
+            AppError::Auth(auth_error) => {
+                tracing::error!("Authentication error: {}", auth_error);
+                let redirect_url = format!("/login?error={}", urlencoding::encode(&auth_error.user_message()));
+                Redirect::to(&redirect_url).into_response()
             }
             
-            AppError::Config(ref config_error) => {
-                tracing::error!("Configuration error: {}", config_error);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(json!({
-                        "error": "Configuration error",
-                        "message": "Server configuration error."
-                    }))
-                ).into_response()
+            // Only API handlers fail with these, and answer with JSON anyway
+            AppError::BadRequest(_) | AppError::NotFound(_) => {
+                tracing::warn!("API request rejected: {}", self);
+                self.json_error().into_response()
             }
             
-            AppError::Migration(ref migration_error) => {
-                tracing::error!("Migration error: {}", migration_error);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(json!({
-                        "error": "Database migration error",
-                        "message": "Database initialization failed."
-                    }))
-                ).into_response()
+            // Provider endpoint errors follow the OAuth2 spec rather than the app's pages
+            AppError::OAuth(oauth_error) => oauth_error.into_response(),
+            
+            // Server errors
+            AppError::Database(_)
+            | AppError::Template(_)
+            | AppError::Http(_)
+            | AppError::Config(_)
+            | AppError::Migration(_) => {
+                tracing::error!("{}", self);
+                self.json_error().into_response()
             }
         }
     }
 }
 
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        // OAuth2 errors already have the shape their clients expect
+        let json_error = (!matches!(self, AppError::OAuth(_))).then(|| self.json_error());
+
+        let mut response = self.page_response();
+        if let Some(json_error) = json_error {
+            response.extensions_mut().insert(json_error);
+        }
+        response
+    }
+}
+
+/// Answer errors with [`JsonError`] bodies when the request accepts JSON.
+///
+/// Browsers keep getting login redirects and dashboard messages; a request
+/// whose `Accept` header asks for `application/json` gets the status and
+/// JSON form of the same error instead.
+pub async fn json_errors_middleware(request: Request, next: Next) -> Response {
+    let accepts_json = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+
+    let response = next.run(request).await;
+    if accepts_json {
+        return into_json_error(response);
+    }
+    response
+}
+
+/// Always answer errors with [`JsonError`] bodies, for routers that only serve JSON.
+pub async fn always_json_errors_middleware(request: Request, next: Next) -> Response {
+    into_json_error(next.run(request).await)
+}
+
+// Swap an error response for its JSON form, keeping any authentication challenge
+fn into_json_error(mut response: Response) -> Response {
+    let Some(json_error) = response.extensions_mut().remove::<JsonError>() else {
+        return response;
+    };
+
+    let challenge = response.headers().get(header::WWW_AUTHENTICATE).cloned();
+    let mut json_response = json_error.into_response();
+    if let Some(challenge) = challenge {
+        json_response.headers_mut().insert(header::WWW_AUTHENTICATE, challenge);
+    }
+    json_response
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -162,8 +549,9 @@ mod tests {
         
         assert_eq!(response.status(), StatusCode::SEE_OTHER);
         
-        let location = response.headers().get("location").unwrap();
-        assert_eq!(location, "/login");
+        let location = response.headers().get("location").unwrap().to_str().unwrap();
+        assert!(location.starts_with("/login?error="));
+        assert!(location.contains("session%20has%20expired"));
     }
 
     #[tokio::test]
@@ -202,6 +590,57 @@ mod tests {
         assert!(location.contains("Invalid%20login%20provider"));
     }
 
+    #[tokio::test]
+    async fn test_tenant_not_allowed_redirects_with_error() {
+        let error = AppError::Auth(AuthError::TenantNotAllowed("other-tenant".to_string()));
+        let response = error.into_response();
+
+        let location = response.headers().get("location").unwrap().to_str().unwrap();
+        assert!(location.starts_with("/login?error="));
+        assert!(location.contains("Microsoft%20organization%20is%20not%20allowed"));
+    }
+
+    #[tokio::test]
+    async fn test_access_denied_redirects_with_distinct_message() {
+        let error = AppError::Auth(AuthError::AccessDenied("not in an allowed organization".to_string()));
+        let response = error.into_response();
+
+        let location = response.headers().get("location").unwrap().to_str().unwrap();
+        assert!(location.starts_with("/login?error="));
+        assert!(location.contains("isn%27t%20authorized"));
+    }
+
+    #[tokio::test]
+    async fn test_account_disabled_redirects_with_reason() {
+        let error = AppError::Auth(AuthError::AccountDisabled(Some("Left the company".to_string())));
+        let response = error.into_response();
+
+        let location = response.headers().get("location").unwrap().to_str().unwrap();
+        assert!(location.starts_with("/login?error="));
+        assert!(location.contains("account%20has%20been%20disabled%3A%20Left%20the%20company"));
+    }
+
+    #[tokio::test]
+    async fn test_forbidden_returns_403() {
+        let error = AppError::Auth(AuthError::Forbidden);
+        let response = error.into_response();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.headers().get("location").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_account_linking_errors_redirect_to_dashboard() {
+        let error = AppError::Auth(AuthError::LastIdentity);
+        let response = error.into_response();
+
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        let location = response.headers().get("location").unwrap().to_str().unwrap();
+        assert!(location.starts_with("/dashboard?error="));
+        assert!(location.contains("only%20sign-in%20method"));
+    }
+
     #[tokio::test]
     async fn test_database_error_returns_500() {
         let db_error = sqlx::Error::RowNotFound;
@@ -244,6 +683,95 @@ mod tests {
         assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
     }
 
+    #[tokio::test]
+    async fn test_oauth_refusal_redirects_to_client() {
+        let error = AppError::OAuth(OAuthError::AuthorizationRefused {
+            redirect_uri: "https://app.example.com/cb".to_string(),
+            state: Some("xyz".to_string()),
+            error: "access_denied",
+            description: "The user declined".to_string(),
+        });
+        let response = error.into_response();
+
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let location = response.headers().get("location").unwrap().to_str().unwrap();
+        assert_eq!(
+            location,
+            "https://app.example.com/cb?error=access_denied&error_description=The+user+declined&state=xyz"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oauth_invalid_client_returns_401_with_challenge() {
+        let response = AppError::OAuth(OAuthError::InvalidClient).into_response();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get("www-authenticate").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_oauth_unknown_client_is_not_redirected() {
+        let response = AppError::OAuth(OAuthError::UnknownClient).into_response();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.headers().get("location").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_insufficient_scope_names_the_scope() {
+        let response = AppError::Auth(AuthError::InsufficientScope("write")).into_response();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let challenge = response.headers().get("www-authenticate").unwrap().to_str().unwrap();
+        assert!(challenge.contains("scope=\"write\""));
+        assert!(response.headers().get("location").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_json_errors_replace_login_redirects() {
+        let app = axum::Router::new()
+            .route("/", axum::routing::get(|| async { Err::<(), _>(AppError::Auth(AuthError::NotAuthenticated)) }))
+            .layer(axum::middleware::from_fn(json_errors_middleware));
+        let request = |accept: &str| {
+            Request::builder()
+                .uri("/")
+                .header(header::ACCEPT, accept)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = tower::ServiceExt::oneshot(app.clone(), request("text/html")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        let response = tower::ServiceExt::oneshot(app, request("application/json")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get("location").is_none());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "not_authenticated");
+        assert_eq!(body["message"], "Please sign in.");
+    }
+
+    #[test]
+    fn test_json_error_statuses() {
+        let cases = [
+            (AppError::Auth(AuthError::SessionExpired), StatusCode::UNAUTHORIZED),
+            (AppError::Auth(AuthError::AccountDisabled(None)), StatusCode::FORBIDDEN),
+            (AppError::Auth(AuthError::LastIdentity), StatusCode::CONFLICT),
+            (AppError::BadRequest("username is required".to_string()), StatusCode::BAD_REQUEST),
+            (AppError::NotFound("User"), StatusCode::NOT_FOUND),
+        ];
+        for (error, status) in cases {
+            let json_error = error.json_error();
+            assert_eq!(json_error.status, status);
+            assert_eq!(json_error.error, error.kind());
+        }
+
+        // Server errors never leak their details
+        let json_error = AppError::Database(sqlx::Error::Protocol("secret table".to_string())).json_error();
+        assert!(!json_error.message.contains("secret"));
+    }
+
     #[test]
     fn test_auth_error_display() {
         let error = AuthError::StateMismatch;
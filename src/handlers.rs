@@ -1,23 +1,48 @@
 use axum::{
-    extract::{Query, State},
-    response::{Html, IntoResponse, Redirect},
+    extract::{FromRef, Path, Query, State},
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
 };
 use askama::Template;
-use oauth2::CsrfToken;
 use serde::Deserialize;
 use tower_sessions::Session;
 
+pub mod admin;
+pub mod api;
+pub mod oauth;
+
+pub use admin::{
+    admin_delete_user_handler, admin_disable_user_handler, admin_enable_user_handler,
+    admin_audit_export_handler, admin_audit_handler, admin_revoke_sessions_handler, admin_users_handler,
+    admin_clients_handler, admin_delete_client_handler, admin_register_client_handler,
+};
+pub use api::router as api_router;
+pub use oauth::{
+    authorize_decision_handler, authorize_handler, discovery_handler, jwks_handler, token_handler,
+    userinfo_handler,
+};
+
 use crate::{
-    auth::AuthService,
+    api_tokens::{ApiScope, ApiTokenStore, CreatedApiToken, MAX_TOKEN_NAME_LEN, TOKEN_LIFETIME_DAYS},
+    audit::{AuditEventType, AuditLog, NewAuditEvent},
+    auth::{sanitize_return_to, AuthService, CallbackOutcome},
+    database::UserRepository,
     error::{AppError, AuthError},
-    session::{AuthenticatedUser, SessionExt},
-    templates::{DashboardTemplate, LoginTemplate},
+    models::{Role, SessionData},
+    oauth_server::OAuthServer,
+    session::{AuthenticatedUser, ClientInfo, SessionExt, SessionPolicy},
+    templates::{DashboardTemplate, LoginTemplate, ProviderOption},
 };
 
 // Application state
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, FromRef)]
 pub struct AppState {
     pub auth_service: AuthService,
+    pub user_repository: UserRepository,
+    pub session_policy: SessionPolicy,
+    pub audit_log: AuditLog,
+    pub oauth_server: OAuthServer,
+    pub api_tokens: ApiTokenStore,
 }
 
 // Query parameters for OAuth2 callbacks
@@ -32,159 +57,453 @@ pub struct AuthCallbackQuery {
 #[derive(Debug, Deserialize)]
 pub struct LoginQuery {
     pub error: Option<String>,
+    // Local path to continue to after signing in
+    pub return_to: Option<String>,
+}
+
+// Query parameters for dashboard
+#[derive(Debug, Deserialize)]
+pub struct DashboardQuery {
+    pub error: Option<String>,
+}
+
+// New personal access token; each scope is a checkbox named after it
+#[derive(Debug, Deserialize)]
+pub struct CreateApiTokenForm {
+    pub name: String,
+    pub lifetime_days: i64,
+    pub read: Option<String>,
+    pub write: Option<String>,
+    pub admin: Option<String>,
 }
 
 // Authentication route handlers
 pub async fn login_handler(
+    State(state): State<AppState>,
     Query(query): Query<LoginQuery>,
+    session: Session,
 ) -> Result<impl IntoResponse, AppError> {
-    let template = LoginTemplate::new(query.error);
+    // A link to the login page may name its own destination; anything off-site is ignored
+    if let Some(path) = query.return_to.as_deref().and_then(sanitize_return_to) {
+        session.set_return_to(path).await?;
+    }
+
+    let template = LoginTemplate::new(query.error).with_providers(provider_options(&state));
     let html = template.render()?;
     Ok(Html(html))
 }
 
-pub async fn microsoft_auth_handler(
+pub async fn auth_handler(
     State(state): State<AppState>,
+    Path(provider): Path<String>,
     session: Session,
 ) -> Result<impl IntoResponse, AppError> {
-    let (auth_url, csrf_token) = state
-        .auth_service
-        .initiate_microsoft_auth()
-        .map_err(AppError::Auth)?;
+    // Kept in the session until sign-in succeeds, so a failed attempt can be retried
+    let return_url = session.get_return_to().await?;
+    let request = state.auth_service.initiate_auth(&provider, return_url).await?;
 
-    // Store CSRF token in session
-    session.set_csrf_token(csrf_token.secret().clone()).await?;
+    // Bind the login state to this browser session
+    session.set_csrf_token(request.csrf_token.secret().clone()).await?;
 
-    Ok(Redirect::to(&auth_url))
+    Ok(Redirect::to(&request.url))
 }
 
-pub async fn github_auth_handler(
+pub async fn auth_callback_handler(
     State(state): State<AppState>,
+    Path(provider): Path<String>,
+    Query(query): Query<AuthCallbackQuery>,
+    client: ClientInfo,
     session: Session,
 ) -> Result<impl IntoResponse, AppError> {
-    let (auth_url, csrf_token) = state
-        .auth_service
-        .initiate_github_auth()
-        .map_err(AppError::Auth)?;
+    let previous_session = session.get_user_session().await?;
+    let attempt = NewAuditEvent::success(AuditEventType::Login)
+        .with_user(previous_session.as_ref().map(|session_data| session_data.user_id))
+        .with_provider(&provider)
+        .with_client(&client);
 
-    // Store CSRF token in session
-    session.set_csrf_token(csrf_token.secret().clone()).await?;
-
-    Ok(Redirect::to(&auth_url))
+    // Every attempt is audited, whichever step it fails at
+    match complete_callback(&state, &provider, query, &client, &session, previous_session).await {
+        Ok(outcome) => {
+            let (event, destination) = match outcome {
+                CallbackOutcome::SignedIn { user, return_url, .. } => (
+                    attempt.with_user(Some(user.id)),
+                    // Checked again, as the stored value outlives the code that wrote it
+                    return_url.as_deref().and_then(sanitize_return_to),
+                ),
+                CallbackOutcome::Linked(identity) => (
+                    NewAuditEvent {
+                        event_type: AuditEventType::Link,
+                        ..attempt.with_user(Some(identity.user_id))
+                    },
+                    None,
+                ),
+            };
+            state.audit_log.record(event).await;
+            Ok(Redirect::to(destination.as_deref().unwrap_or("/dashboard")))
+        }
+        Err(e) => {
+            state.audit_log.record(attempt.failed(&e)).await;
+            Err(e)
+        }
+    }
 }
 
-pub async fn microsoft_callback_handler(
-    State(state): State<AppState>,
-    Query(query): Query<AuthCallbackQuery>,
-    session: Session,
-) -> Result<impl IntoResponse, AppError> {
+// Finish a login or link started by `auth_handler` or `link_handler`
+async fn complete_callback(
+    state: &AppState,
+    provider: &str,
+    query: AuthCallbackQuery,
+    client: &ClientInfo,
+    session: &Session,
+    previous_session: Option<SessionData>,
+) -> Result<CallbackOutcome, AppError> {
+    let identity_provider = state.auth_service.provider(provider)?;
+
+    // The session's copy of the state is single-use as well
+    let session_state = session.get_csrf_token().await?;
+    session.clear_csrf_token().await?;
+
     // Check for OAuth2 error
     if let Some(error) = query.error {
-        tracing::error!("Microsoft OAuth2 error: {}", error);
-        return Ok(Redirect::to(&format!("/login?error={}", urlencoding::encode("Microsoft authentication failed. Please try again."))));
+        if let Some(state_param) = query.state {
+            state
+                .auth_service
+                .abandon_auth(provider, &state_param, session_state.as_deref())
+                .await;
+        }
+        return Err(AuthError::ProviderError {
+            provider: identity_provider.display_name().to_string(),
+            error,
+        }
+        .into());
     }
 
     // Get authorization code
     let code = query.code.ok_or(AuthError::MissingAuthCode)?;
     let state_param = query.state.ok_or(AuthError::StateMismatch)?;
 
-    // Get stored CSRF token
-    let stored_csrf_token = session.get_csrf_token().await?;
-    
-    // For development: Use the state parameter as the CSRF token if session token is not available
-    // In production with HTTPS, the session cookie should persist properly
-    let csrf_token = if let Some(token) = stored_csrf_token {
-        CsrfToken::new(token)
-    } else {
-        // Fallback for development - use the state parameter
-        tracing::debug!("Using state parameter as CSRF token for development");
-        CsrfToken::new(state_param.clone())
-    };
-
     // Handle OAuth2 callback
-    let user = state
+    let session_user_id = previous_session.as_ref().map(|session_data| session_data.user_id);
+    let outcome = state
         .auth_service
-        .handle_microsoft_callback(code, state_param, csrf_token)
+        .handle_callback(provider, code, state_param, session_state, session_user_id)
         .await?;
 
-    // Create user session
-    session.set_user_session(&user).await?;
-    session.clear_csrf_token().await?;
+    match &outcome {
+        CallbackOutcome::SignedIn { user, profile_changes, .. } => {
+            // Signing in again replaces any session this browser already had
+            if let Some(SessionData { user_id, session_id: Some(session_id), .. }) = previous_session {
+                state.oauth_server.notify_logout(user_id, Some(&session_id)).await?;
+                state.user_repository.revoke_session(user_id, &session_id).await?;
+            }
 
-    tracing::info!("User {} successfully authenticated via Microsoft", user.username);
-    Ok(Redirect::to("/dashboard"))
+            // Create user session under a new session ID, carrying the user's roles
+            let roles = state.user_repository.list_roles(user.id).await?;
+            let tracked = state
+                .user_repository
+                .create_session(user.id, client.ip_address.as_deref(), client.user_agent.as_deref())
+                .await?;
+            session
+                .set_user_session(user, identity_provider.slug(), roles, tracked.id)
+                .await?;
+            session.clear_return_to().await?;
+
+            tracing::info!(
+                "User {} successfully authenticated via {}",
+                user.username,
+                identity_provider.display_name()
+            );
+
+            if !profile_changes.is_empty() {
+                state
+                    .audit_log
+                    .record(
+                        NewAuditEvent::success(AuditEventType::ProfileUpdated)
+                            .with_user(Some(user.id))
+                            .with_provider(identity_provider.slug())
+                            .with_client(client)
+                            .with_details(format!("refreshed from provider: {}", profile_changes.join(", "))),
+                    )
+                    .await;
+            }
+        }
+        CallbackOutcome::Linked(identity) => {
+            tracing::info!(
+                "User ID {} linked a {} account",
+                identity.user_id,
+                identity_provider.display_name()
+            );
+        }
+    }
+
+    Ok(outcome)
 }
 
-pub async fn github_callback_handler(
+// Account linking handlers
+pub async fn link_handler(
     State(state): State<AppState>,
-    Query(query): Query<AuthCallbackQuery>,
+    Path(provider): Path<String>,
+    authenticated_user: AuthenticatedUser,
     session: Session,
 ) -> Result<impl IntoResponse, AppError> {
-    // Check for OAuth2 error
-    if let Some(error) = query.error {
-        tracing::error!("GitHub OAuth2 error: {}", error);
-        return Ok(Redirect::to(&format!("/login?error={}", urlencoding::encode("GitHub authentication failed. Please try again."))));
+    let request = state
+        .auth_service
+        .initiate_link(&provider, authenticated_user.session_data.user_id)
+        .await?;
+
+    // Bind the login state to this browser session
+    session.set_csrf_token(request.csrf_token.secret().clone()).await?;
+
+    Ok(Redirect::to(&request.url))
+}
+
+pub async fn unlink_handler(
+    State(state): State<AppState>,
+    Path(identity_id): Path<i64>,
+    authenticated_user: AuthenticatedUser,
+    client: ClientInfo,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = authenticated_user.session_data.user_id;
+    let event = NewAuditEvent::success(AuditEventType::Unlink)
+        .with_user(Some(user_id))
+        .with_client(&client);
+
+    match state.user_repository.unlink_identity(user_id, identity_id).await {
+        Ok(identity) => {
+            state.audit_log.record(event.with_provider(&identity.provider)).await;
+            Ok(Redirect::to("/dashboard"))
+        }
+        Err(e) => {
+            state.audit_log.record(event.failed(&e)).await;
+            Err(e)
+        }
     }
+}
 
-    // Get authorization code
-    let code = query.code.ok_or(AuthError::MissingAuthCode)?;
-    let state_param = query.state.ok_or(AuthError::StateMismatch)?;
+// Protected route handlers
+pub async fn dashboard_handler(
+    State(state): State<AppState>,
+    Query(query): Query<DashboardQuery>,
+    authenticated_user: AuthenticatedUser,
+    session: Session,
+) -> Result<impl IntoResponse, AppError> {
+    render_dashboard(&state, &authenticated_user.session_data, &session, query.error, None).await
+}
 
-    // Get stored CSRF token
-    let stored_csrf_token = session.get_csrf_token().await?;
-    
-    // For development: Use the state parameter as the CSRF token if session token is not available
-    // In production with HTTPS, the session cookie should persist properly
-    let csrf_token = if let Some(token) = stored_csrf_token {
-        CsrfToken::new(token)
-    } else {
-        // Fallback for development - use the state parameter
-        tracing::debug!("Using state parameter as CSRF token for development");
-        CsrfToken::new(state_param.clone())
+async fn render_dashboard(
+    state: &AppState,
+    session_data: &SessionData,
+    session: &Session,
+    error: Option<String>,
+    created_token: Option<CreatedApiToken>,
+) -> Result<Html<String>, AppError> {
+    // Load the current profile; the account may have been deleted since sign-in
+    let Some(user) = state.user_repository.find_by_id(session_data.user_id).await? else {
+        tracing::warn!("User ID {} no longer exists, ending session", session_data.user_id);
+        session.destroy_session().await?;
+        return Err(AuthError::AccountNotFound.into());
     };
 
-    // Handle OAuth2 callback
-    let user = state
+    let identities = state.user_repository.list_identities(user.id).await?;
+    let now = chrono::Utc::now();
+    let sessions = state
+        .user_repository
+        .list_sessions(user.id)
+        .await?
+        .into_iter()
+        .filter(|tracked| state.session_policy.is_live(tracked.created_at, tracked.last_seen, now))
+        .collect();
+    let api_tokens = state.api_tokens.list(user.id).await?;
+    let template = DashboardTemplate::new(user)
+        .with_identities(identities, provider_options(state))
+        .with_sessions(sessions, session_data.session_id.clone())
+        .with_api_tokens(api_tokens, created_token)
+        .with_error(error)
+        .with_admin(session_data.has_role(Role::Admin));
+
+    let html = template.render()?;
+    Ok(Html(html))
+}
+
+// Every registered provider as a sign-in or link button
+fn provider_options(state: &AppState) -> Vec<ProviderOption> {
+    state
         .auth_service
-        .handle_github_callback(code, state_param, csrf_token)
-        .await?;
+        .providers()
+        .iter()
+        .map(|provider| ProviderOption {
+            slug: provider.slug().to_string(),
+            display_name: provider.display_name().to_string(),
+        })
+        .collect()
+}
 
-    // Create user session
-    session.set_user_session(&user).await?;
-    session.clear_csrf_token().await?;
+// Session management handlers
+pub async fn revoke_session_handler(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+    authenticated_user: AuthenticatedUser,
+    client: ClientInfo,
+    session: Session,
+) -> Result<impl IntoResponse, AppError> {
+    let session_data = &authenticated_user.session_data;
+    // Relying parties are told first, as revoking forgets which ones were signed in
+    state.oauth_server.notify_logout(session_data.user_id, Some(&session_id)).await?;
+    if !state.user_repository.revoke_session(session_data.user_id, &session_id).await? {
+        return Err(AuthError::UnknownSession.into());
+    }
+
+    state
+        .audit_log
+        .record(
+            NewAuditEvent::success(AuditEventType::SessionRevoked)
+                .with_user(Some(session_data.user_id))
+                .with_client(&client),
+        )
+        .await;
+
+    // Revoking the current session is signing out
+    if session_data.session_id.as_deref() == Some(session_id.as_str()) {
+        session.destroy_session().await?;
+        return Ok(Redirect::to("/login"));
+    }
 
-    tracing::info!("User {} successfully authenticated via GitHub", user.username);
     Ok(Redirect::to("/dashboard"))
 }
 
-// Protected route handlers
-pub async fn dashboard_handler(
-    authenticated_user: AuthenticatedUser,
+// Rendered rather than redirected, as this response is the only place the token appears
+pub async fn create_api_token_handler(
     State(state): State<AppState>,
-) -> Result<impl IntoResponse, AppError> {
-    // We already have the user data in the session, so we can use it directly
-    // In a real application, you might want to fetch fresh data from the database
+    authenticated_user: AuthenticatedUser,
+    client: ClientInfo,
+    session: Session,
+    Form(form): Form<CreateApiTokenForm>,
+) -> Result<Response, AppError> {
     let session_data = &authenticated_user.session_data;
+    let name = form.name.trim();
+    let scopes: Vec<ApiScope> = [(ApiScope::Read, &form.read), (ApiScope::Write, &form.write), (ApiScope::Admin, &form.admin)]
+        .into_iter()
+        .filter(|(_, checked)| checked.is_some())
+        .map(|(scope, _)| scope)
+        .collect();
 
-    let template = DashboardTemplate::new(
-        session_data.username.clone(),
-        None, // Email is not stored in session data, could be fetched from DB if needed
-        session_data.provider.clone(),
-    );
+    let problem = if name.is_empty() {
+        Some("Token name is required.")
+    } else if name.chars().count() > MAX_TOKEN_NAME_LEN {
+        Some("Token name is too long.")
+    } else if scopes.is_empty() {
+        Some("Choose at least one scope for the token.")
+    } else if scopes.contains(&ApiScope::Admin) && !session_data.has_role(Role::Admin) {
+        Some("Only admins can create tokens with the admin scope.")
+    } else if !TOKEN_LIFETIME_DAYS.contains(&form.lifetime_days) {
+        Some("Choose one of the offered token lifetimes.")
+    } else {
+        None
+    };
+    if let Some(problem) = problem {
+        let redirect_url = format!("/dashboard?error={}", urlencoding::encode(problem));
+        return Ok(Redirect::to(&redirect_url).into_response());
+    }
 
-    let html = template.render()?;
-    Ok(Html(html))
+    let created = state
+        .api_tokens
+        .create(session_data.user_id, name, &scopes, form.lifetime_days)
+        .await?;
+    state
+        .audit_log
+        .record(
+            NewAuditEvent::success(AuditEventType::ApiTokenCreated)
+                .with_user(Some(session_data.user_id))
+                .with_client(&client)
+                .with_details(format!("{} ({})", created.token.name, created.token.scope_list())),
+        )
+        .await;
+
+    Ok(render_dashboard(&state, session_data, &session, None, Some(created)).await?.into_response())
 }
 
-pub async fn logout_handler(session: Session) -> Result<impl IntoResponse, AppError> {
-    // Clear user session
-    session.clear_user_session().await?;
-    session.clear_csrf_token().await?;
+pub async fn revoke_api_token_handler(
+    State(state): State<AppState>,
+    Path(token_id): Path<String>,
+    authenticated_user: AuthenticatedUser,
+    client: ClientInfo,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = authenticated_user.session_data.user_id;
+    if !state.api_tokens.revoke(user_id, &token_id).await? {
+        return Err(AuthError::UnknownApiToken.into());
+    }
+
+    state
+        .audit_log
+        .record(
+            NewAuditEvent::success(AuditEventType::ApiTokenRevoked)
+                .with_user(Some(user_id))
+                .with_client(&client),
+        )
+        .await;
+    Ok(Redirect::to("/dashboard"))
+}
 
-    tracing::info!("User logged out successfully");
+pub async fn sign_out_everywhere_handler(
+    State(state): State<AppState>,
+    authenticated_user: AuthenticatedUser,
+    client: ClientInfo,
+    session: Session,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = authenticated_user.session_data.user_id;
+    state.oauth_server.notify_logout(user_id, None).await?;
+    let revoked = state.user_repository.revoke_all_sessions(user_id).await?;
+    session.destroy_session().await?;
+
+    state
+        .audit_log
+        .record(
+            NewAuditEvent::success(AuditEventType::SignedOutEverywhere)
+                .with_user(Some(user_id))
+                .with_client(&client)
+                .with_details(format!("Ended {} sessions", revoked)),
+        )
+        .await;
     Ok(Redirect::to("/login"))
 }
 
+pub async fn logout_handler(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    session: Session,
+) -> Result<impl IntoResponse, AppError> {
+    let session_data = session.get_user_session().await?;
+
+    // Sign out of relying parties, then stop listing this session as active
+    if let Some(SessionData { user_id, session_id: Some(session_id), .. }) = &session_data {
+        state.oauth_server.notify_logout(*user_id, Some(session_id)).await?;
+        state.user_repository.revoke_session(*user_id, session_id).await?;
+    }
+
+    // Delete the whole session from the store so its cookie can never be reused
+    session.destroy_session().await?;
+
+    // Only a signed-in browser has anything to sign out of
+    let Some(session_data) = session_data else {
+        return Ok(Redirect::to("/login"));
+    };
+    state
+        .audit_log
+        .record(
+            NewAuditEvent::success(AuditEventType::Logout)
+                .with_user(Some(session_data.user_id))
+                .with_provider(&session_data.provider)
+                .with_client(&client),
+        )
+        .await;
+
+    // End the session at the identity provider too, which sends the browser back to /login
+    match state.auth_service.end_session_url(&session_data.provider) {
+        Some(end_session_url) => Ok(Redirect::to(&end_session_url)),
+        None => Ok(Redirect::to("/login")),
+    }
+}
+
 // Root route handler
 pub async fn root_handler(session: Session) -> Result<impl IntoResponse, AppError> {
     // Check if user is authenticated
@@ -0,0 +1,380 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::{Html, IntoResponse, Redirect, Response},
+    Form, Json,
+};
+use askama::Template;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Deserialize;
+
+use super::AppState;
+use crate::{
+    audit::{AuditEventType, AuditFilter, NewAuditEvent},
+    error::AppError,
+    models::{UserListQuery, UserSort},
+    oauth_server::{parse_backchannel_logout_uri, parse_redirect_uris, OAuthClient},
+    session::{ClientInfo, RequireAdmin},
+    templates::{AdminAuditTemplate, AdminClientsTemplate, AdminUsersTemplate},
+};
+
+// Users shown per page of the admin list
+const USERS_PER_PAGE: i64 = 25;
+
+// Audit events shown per page of the admin audit log
+const EVENTS_PER_PAGE: i64 = 50;
+
+// Most audit events returned by one JSON export
+const EXPORT_LIMIT: i64 = 10_000;
+
+// Query parameters for the admin user list
+#[derive(Debug, Deserialize)]
+pub struct AdminUsersQuery {
+    pub q: Option<String>,
+    #[serde(default)]
+    pub sort: UserSort,
+    pub page: Option<i64>,
+    pub error: Option<String>,
+}
+
+// Filters for the audit log page and export; dates are YYYY-MM-DD, both inclusive
+#[derive(Debug, Default, Deserialize)]
+pub struct AdminAuditQuery {
+    pub user_id: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub page: Option<i64>,
+    pub error: Option<String>,
+}
+
+impl AdminAuditQuery {
+    // Blank fields are left out of the filter
+    fn filter(&self) -> Result<AuditFilter, &'static str> {
+        let user_id = match non_empty(&self.user_id) {
+            Some(user_id) => Some(user_id.parse::<i64>().map_err(|_| "User ID must be a number.")?),
+            None => None,
+        };
+        let since = non_empty(&self.from).map(parse_date).transpose()?;
+        // The end date is inclusive, so stop at the start of the next day
+        let until = non_empty(&self.to)
+            .map(parse_date)
+            .transpose()?
+            .map(|to| to + Duration::days(1));
+
+        Ok(AuditFilter { user_id, since, until })
+    }
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|value| !value.is_empty())
+}
+
+fn parse_date(value: &str) -> Result<DateTime<Utc>, &'static str> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| "Dates must be in YYYY-MM-DD format.")
+}
+
+// Form fields for disabling a user
+#[derive(Debug, Deserialize)]
+pub struct DisableUserForm {
+    pub reason: Option<String>,
+}
+
+// Query parameters for the registered applications page
+#[derive(Debug, Deserialize)]
+pub struct AdminClientsQuery {
+    pub error: Option<String>,
+}
+
+// Form fields for registering an application
+#[derive(Debug, Deserialize)]
+pub struct RegisterClientForm {
+    pub name: String,
+    // One per line
+    pub redirect_uris: String,
+    // Checkbox; public clients get no secret
+    pub public: Option<String>,
+    // Optional; where logout tokens are posted when the user signs out here
+    pub backchannel_logout_uri: Option<String>,
+}
+
+pub async fn admin_users_handler(
+    State(state): State<AppState>,
+    Query(query): Query<AdminUsersQuery>,
+    admin: RequireAdmin,
+) -> Result<impl IntoResponse, AppError> {
+    let search = query.q.map(|q| q.trim().to_string()).filter(|q| !q.is_empty());
+
+    let mut list_query = UserListQuery {
+        search: search.clone(),
+        sort: query.sort,
+        per_page: USERS_PER_PAGE,
+        ..UserListQuery::default()
+    };
+    let total_users = state.user_repository.count_users(&list_query).await?;
+    let total_pages = ((total_users + USERS_PER_PAGE - 1) / USERS_PER_PAGE).max(1);
+    let page = query.page.unwrap_or(1).clamp(1, total_pages);
+    list_query.page = page;
+
+    let users = state.user_repository.list_users(&list_query).await?;
+
+    let template = AdminUsersTemplate {
+        users,
+        search: search.unwrap_or_default(),
+        sort: query.sort,
+        page,
+        total_pages,
+        total_users,
+        current_user_id: admin.user.session_data.user_id,
+        error: query.error,
+    };
+
+    let html = template.render()?;
+    Ok(Html(html))
+}
+
+pub async fn admin_disable_user_handler(
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+    admin: RequireAdmin,
+    client: ClientInfo,
+    Form(form): Form<DisableUserForm>,
+) -> Result<impl IntoResponse, AppError> {
+    if user_id == admin.user.session_data.user_id {
+        return Ok(list_with_error("You cannot disable your own account."));
+    }
+
+    let reason = form.reason.map(|reason| reason.trim().to_string()).filter(|reason| !reason.is_empty());
+    // Disabling ends every session, so relying parties are signed out as well
+    state.oauth_server.notify_logout(user_id, None).await?;
+    if !state.user_repository.disable_user(user_id, reason.as_deref()).await? {
+        return Ok(list_with_error("That user could not be found."));
+    }
+
+    let mut event = admin_event(AuditEventType::UserDisabled, user_id, &admin, &client);
+    if let Some(reason) = reason {
+        event = event.with_details(reason);
+    }
+    state.audit_log.record(event).await;
+    Ok(Redirect::to("/admin/users"))
+}
+
+pub async fn admin_enable_user_handler(
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+    admin: RequireAdmin,
+    client: ClientInfo,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.user_repository.enable_user(user_id).await? {
+        return Ok(list_with_error("That user could not be found."));
+    }
+
+    state
+        .audit_log
+        .record(admin_event(AuditEventType::UserEnabled, user_id, &admin, &client))
+        .await;
+    Ok(Redirect::to("/admin/users"))
+}
+
+pub async fn admin_delete_user_handler(
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+    admin: RequireAdmin,
+    client: ClientInfo,
+) -> Result<impl IntoResponse, AppError> {
+    if user_id == admin.user.session_data.user_id {
+        return Ok(list_with_error("You cannot delete your own account."));
+    }
+    state.oauth_server.notify_logout(user_id, None).await?;
+    if !state.user_repository.delete_user(user_id).await? {
+        return Ok(list_with_error("That user could not be found."));
+    }
+
+    state
+        .audit_log
+        .record(admin_event(AuditEventType::UserDeleted, user_id, &admin, &client))
+        .await;
+    Ok(Redirect::to("/admin/users"))
+}
+
+pub async fn admin_revoke_sessions_handler(
+    State(state): State<AppState>,
+    Path(user_id): Path<i64>,
+    admin: RequireAdmin,
+    client: ClientInfo,
+) -> Result<impl IntoResponse, AppError> {
+    if state.user_repository.find_by_id(user_id).await?.is_none() {
+        return Ok(list_with_error("That user could not be found."));
+    }
+
+    state.oauth_server.notify_logout(user_id, None).await?;
+    let revoked = state.user_repository.revoke_all_sessions(user_id).await?;
+
+    state
+        .audit_log
+        .record(
+            admin_event(AuditEventType::UserSessionsRevoked, user_id, &admin, &client)
+                .with_details(format!("Ended {} sessions", revoked)),
+        )
+        .await;
+    Ok(Redirect::to("/admin/users"))
+}
+
+pub async fn admin_audit_handler(
+    State(state): State<AppState>,
+    Query(query): Query<AdminAuditQuery>,
+    _admin: RequireAdmin,
+) -> Result<impl IntoResponse, AppError> {
+    let mut template = AdminAuditTemplate {
+        events: Vec::new(),
+        user_id: non_empty(&query.user_id).unwrap_or_default().to_string(),
+        from: non_empty(&query.from).unwrap_or_default().to_string(),
+        to: non_empty(&query.to).unwrap_or_default().to_string(),
+        page: 1,
+        total_pages: 1,
+        total_events: 0,
+        error: query.error.clone(),
+    };
+
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(message) => {
+            template.error = Some(message.to_string());
+            return Ok(Html(template.render()?));
+        }
+    };
+
+    template.total_events = state.audit_log.count(&filter).await?;
+    template.total_pages = ((template.total_events + EVENTS_PER_PAGE - 1) / EVENTS_PER_PAGE).max(1);
+    template.page = query.page.unwrap_or(1).clamp(1, template.total_pages);
+    template.events = state
+        .audit_log
+        .list(&filter, EVENTS_PER_PAGE, (template.page - 1) * EVENTS_PER_PAGE)
+        .await?;
+
+    Ok(Html(template.render()?))
+}
+
+// The filtered events as JSON, newest first
+pub async fn admin_audit_export_handler(
+    State(state): State<AppState>,
+    Query(query): Query<AdminAuditQuery>,
+    _admin: RequireAdmin,
+) -> Result<Response, AppError> {
+    let filter = match query.filter() {
+        Ok(filter) => filter,
+        Err(message) => {
+            let url = format!("/admin/audit?error={}", urlencoding::encode(message));
+            return Ok(Redirect::to(&url).into_response());
+        }
+    };
+
+    let events = state.audit_log.list(&filter, EXPORT_LIMIT, 0).await?;
+    Ok(Json(events).into_response())
+}
+
+pub async fn admin_clients_handler(
+    State(state): State<AppState>,
+    Query(query): Query<AdminClientsQuery>,
+    _admin: RequireAdmin,
+) -> Result<impl IntoResponse, AppError> {
+    let template = AdminClientsTemplate {
+        clients: state.oauth_server.list_clients().await?,
+        registered: None,
+        issuer: state.oauth_server.issuer().to_string(),
+        name: String::new(),
+        redirect_uris: String::new(),
+        backchannel_logout_uri: String::new(),
+        error: query.error,
+    };
+
+    Ok(Html(template.render()?))
+}
+
+// Rendered rather than redirected, as this response is the only place the secret appears
+pub async fn admin_register_client_handler(
+    State(state): State<AppState>,
+    admin: RequireAdmin,
+    client: ClientInfo,
+    Form(form): Form<RegisterClientForm>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut template = AdminClientsTemplate {
+        clients: Vec::new(),
+        registered: None,
+        issuer: state.oauth_server.issuer().to_string(),
+        name: form.name.trim().to_string(),
+        redirect_uris: form.redirect_uris.clone(),
+        backchannel_logout_uri: form.backchannel_logout_uri.unwrap_or_default().trim().to_string(),
+        error: None,
+    };
+
+    let uris = match parse_redirect_uris(&form.redirect_uris) {
+        Ok(_) if template.name.is_empty() => Err("Name is required."),
+        result => result.and_then(|redirect_uris| {
+            Ok((redirect_uris, parse_backchannel_logout_uri(&template.backchannel_logout_uri)?))
+        }),
+    };
+    match uris {
+        Ok((redirect_uris, backchannel_logout_uri)) => {
+            let registered = state
+                .oauth_server
+                .register_client(&template.name, redirect_uris, backchannel_logout_uri, form.public.is_none())
+                .await?;
+            state
+                .audit_log
+                .record(client_event(AuditEventType::ClientRegistered, &registered.client, &admin, &client))
+                .await;
+
+            template.name.clear();
+            template.redirect_uris.clear();
+            template.backchannel_logout_uri.clear();
+            template.registered = Some(registered);
+        }
+        Err(message) => template.error = Some(message.to_string()),
+    }
+
+    template.clients = state.oauth_server.list_clients().await?;
+    Ok(Html(template.render()?))
+}
+
+pub async fn admin_delete_client_handler(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+    admin: RequireAdmin,
+    client: ClientInfo,
+) -> Result<impl IntoResponse, AppError> {
+    let Some(oauth_client) = state.oauth_server.find_client(&client_id).await? else {
+        return Ok(clients_with_error("That application could not be found."));
+    };
+    state.oauth_server.delete_client(&client_id).await?;
+
+    state
+        .audit_log
+        .record(client_event(AuditEventType::ClientDeleted, &oauth_client, &admin, &client))
+        .await;
+    Ok(Redirect::to("/admin/clients"))
+}
+
+// An admin action on `user_id`, attributed to the signed-in admin
+fn admin_event(event_type: AuditEventType, user_id: i64, admin: &RequireAdmin, client: &ClientInfo) -> NewAuditEvent {
+    NewAuditEvent::success(event_type)
+        .with_user(Some(user_id))
+        .with_actor(admin.user.session_data.user_id)
+        .with_client(client)
+}
+
+fn list_with_error(message: &str) -> Redirect {
+    Redirect::to(&format!("/admin/users?error={}", urlencoding::encode(message)))
+}
+
+// An admin action on a registered application
+fn client_event(event_type: AuditEventType, oauth_client: &OAuthClient, admin: &RequireAdmin, client: &ClientInfo) -> NewAuditEvent {
+    NewAuditEvent::success(event_type)
+        .with_actor(admin.user.session_data.user_id)
+        .with_client(client)
+        .with_details(format!("{} ({})", oauth_client.name, oauth_client.client_id))
+}
+
+fn clients_with_error(message: &str) -> Redirect {
+    Redirect::to(&format!("/admin/clients?error={}", urlencoding::encode(message)))
+}
@@ -0,0 +1,196 @@
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        Path, Query, State,
+    },
+    middleware,
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use super::AppState;
+use crate::{
+    api_tokens::{ApiScope, ApiUser},
+    audit::{AuditEventType, NewAuditEvent},
+    error::{always_json_errors_middleware, AppError},
+    models::{User, UserListQuery, UserSort},
+    session::ClientInfo,
+};
+
+// Users returned per page unless the client asks otherwise
+const DEFAULT_PER_PAGE: i64 = 50;
+
+// Most users one page may hold
+const MAX_PER_PAGE: i64 = 100;
+
+// Longest username a user may choose
+pub const MAX_USERNAME_LEN: usize = 64;
+
+// Editable fields of the current user; anything else is refused rather than ignored
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateMeRequest {
+    pub username: Option<String>,
+}
+
+// Query parameters for the user directory
+#[derive(Debug, Deserialize)]
+pub struct ApiUsersQuery {
+    pub q: Option<String>,
+    pub disabled: Option<bool>,
+    #[serde(default)]
+    pub sort: UserSort,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+/// One page of the user directory.
+#[derive(Debug, Serialize)]
+pub struct UserPage {
+    pub users: Vec<User>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    pub total_pages: i64,
+}
+
+/// The `/api/v1` routes; every error is answered with JSON.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/me", get(get_me_handler).patch(update_me_handler))
+        .route("/users", get(list_users_handler))
+        .route("/users/:id", get(get_user_handler))
+        .layer(middleware::from_fn(always_json_errors_middleware))
+}
+
+pub async fn get_me_handler(State(state): State<AppState>, api_user: ApiUser) -> Result<Json<User>, AppError> {
+    api_user.require_scope(ApiScope::Read)?;
+
+    let user = state
+        .user_repository
+        .find_by_id(api_user.user.session_data.user_id)
+        .await?
+        .ok_or(AppError::NotFound("User"))?;
+    Ok(Json(user))
+}
+
+pub async fn update_me_handler(
+    State(state): State<AppState>,
+    api_user: ApiUser,
+    client: ClientInfo,
+    request: Result<Json<UpdateMeRequest>, JsonRejection>,
+) -> Result<Json<User>, AppError> {
+    api_user.require_scope(ApiScope::Write)?;
+    let Json(request) = request.map_err(|e| AppError::BadRequest(e.body_text()))?;
+    let user_id = api_user.user.session_data.user_id;
+
+    let Some(username) = request.username else {
+        // Nothing to change
+        let user = state.user_repository.find_by_id(user_id).await?;
+        return user.map(Json).ok_or(AppError::NotFound("User"));
+    };
+    let username = parse_username(&username)?;
+
+    let user = state
+        .user_repository
+        .update_username(user_id, &username)
+        .await?
+        .ok_or(AppError::NotFound("User"))?;
+
+    state
+        .audit_log
+        .record(
+            NewAuditEvent::success(AuditEventType::ProfileUpdated)
+                .with_user(Some(user_id))
+                .with_client(&client)
+                .with_details(format!("username: {}", user.username)),
+        )
+        .await;
+    Ok(Json(user))
+}
+
+pub async fn list_users_handler(
+    State(state): State<AppState>,
+    api_user: ApiUser,
+    query: Result<Query<ApiUsersQuery>, QueryRejection>,
+) -> Result<Json<UserPage>, AppError> {
+    api_user.require_admin()?;
+    let Query(query) = query.map_err(|e| AppError::BadRequest(e.body_text()))?;
+
+    let page = query.page.unwrap_or(1);
+    if page < 1 {
+        return Err(AppError::BadRequest("page must be at least 1".to_string()));
+    }
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    if !(1..=MAX_PER_PAGE).contains(&per_page) {
+        return Err(AppError::BadRequest(format!("per_page must be between 1 and {}", MAX_PER_PAGE)));
+    }
+
+    let list_query = UserListQuery {
+        search: query.q.map(|q| q.trim().to_string()).filter(|q| !q.is_empty()),
+        disabled: query.disabled,
+        sort: query.sort,
+        page,
+        per_page,
+    };
+    let total = state.user_repository.count_users(&list_query).await?;
+    let users = state.user_repository.list_users(&list_query).await?;
+
+    Ok(Json(UserPage {
+        users,
+        page,
+        per_page,
+        total,
+        total_pages: (total + per_page - 1) / per_page,
+    }))
+}
+
+pub async fn get_user_handler(
+    State(state): State<AppState>,
+    api_user: ApiUser,
+    user_id: Result<Path<i64>, PathRejection>,
+) -> Result<Json<User>, AppError> {
+    api_user.require_admin()?;
+    let Path(user_id) = user_id.map_err(|e| AppError::BadRequest(e.body_text()))?;
+
+    let user = state
+        .user_repository
+        .find_by_id(user_id)
+        .await?
+        .ok_or(AppError::NotFound("User"))?;
+    Ok(Json(user))
+}
+
+// A username as entered: trimmed, non-empty, short and printable
+fn parse_username(username: &str) -> Result<String, AppError> {
+    let username = username.trim();
+    if username.is_empty() {
+        return Err(AppError::BadRequest("username cannot be empty".to_string()));
+    }
+    if username.chars().count() > MAX_USERNAME_LEN {
+        return Err(AppError::BadRequest(format!(
+            "username cannot be longer than {} characters",
+            MAX_USERNAME_LEN
+        )));
+    }
+    if username.chars().any(char::is_control) {
+        return Err(AppError::BadRequest("username cannot contain control characters".to_string()));
+    }
+
+    Ok(username.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_username() {
+        assert_eq!(parse_username("  octocat ").unwrap(), "octocat");
+        assert!(parse_username("   ").is_err());
+        assert!(parse_username("tab\there").is_err());
+        assert!(parse_username(&"x".repeat(MAX_USERNAME_LEN + 1)).is_err());
+        assert!(parse_username(&"é".repeat(MAX_USERNAME_LEN)).is_ok());
+    }
+}
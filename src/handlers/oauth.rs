@@ -0,0 +1,213 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap},
+    response::{Html, IntoResponse, Redirect, Response},
+    Form, Json,
+};
+use askama::Template;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
+
+use super::AppState;
+use crate::{
+    audit::{AuditEventType, NewAuditEvent},
+    error::{AppError, OAuthError},
+    oauth_server::{AuthorizationRequest, ValidatedAuthorization},
+    session::{AuthenticatedUser, ClientInfo},
+    templates::ConsentTemplate,
+};
+
+// The consent form: the original authorization request plus the user's answer
+#[derive(Debug, Deserialize)]
+pub struct ConsentForm {
+    #[serde(flatten)]
+    pub request: AuthorizationRequest,
+    // "allow" or "deny"
+    pub decision: String,
+}
+
+// Token endpoint parameters; client credentials may come here or in a Basic header
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: Option<String>,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+pub async fn discovery_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.oauth_server.discovery_document())
+}
+
+pub async fn jwks_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.oauth_server.jwks().clone())
+}
+
+// Start of a client's sign-in: ask for consent once, then send a code back
+pub async fn authorize_handler(
+    State(state): State<AppState>,
+    Query(request): Query<AuthorizationRequest>,
+    authenticated_user: Result<AuthenticatedUser, AppError>,
+) -> Result<Response, AppError> {
+    // Check the client first, so a bad request never goes through sign-in
+    let authorization = state.oauth_server.validate_authorization(&request).await?;
+
+    let authenticated_user = match authenticated_user {
+        Ok(authenticated_user) => authenticated_user,
+        Err(_) if authorization.prompt_none => {
+            return Err(authorization.refuse("login_required", "The user is not signed in.").into());
+        }
+        // Signing in returns here, as the extractor remembered this URL
+        Err(e) => return Err(e),
+    };
+    let user_id = authenticated_user.session_data.user_id;
+
+    let consented = state
+        .oauth_server
+        .has_consent(user_id, &authorization.client.client_id, &authorization.scopes)
+        .await?;
+    if consented {
+        return issue_code(&state, &authorization, &authenticated_user).await;
+    }
+    if authorization.prompt_none {
+        return Err(authorization.refuse("consent_required", "The user has not authorized this client.").into());
+    }
+
+    let template = ConsentTemplate {
+        client_name: authorization.client.name.clone(),
+        username: authenticated_user.session_data.username.clone(),
+        scopes: authorization.scopes.clone(),
+        request,
+    };
+    Ok(Html(template.render()?).into_response())
+}
+
+pub async fn authorize_decision_handler(
+    State(state): State<AppState>,
+    authenticated_user: AuthenticatedUser,
+    client: ClientInfo,
+    Form(form): Form<ConsentForm>,
+) -> Result<Response, AppError> {
+    let authorization = state.oauth_server.validate_authorization(&form.request).await?;
+
+    if form.decision != "allow" {
+        return Err(authorization.refuse("access_denied", "The user denied the request.").into());
+    }
+
+    let user_id = authenticated_user.session_data.user_id;
+    state
+        .oauth_server
+        .grant_consent(user_id, &authorization.client.client_id, &authorization.scopes)
+        .await?;
+    state
+        .audit_log
+        .record(
+            NewAuditEvent::success(AuditEventType::ClientAuthorized)
+                .with_user(Some(user_id))
+                .with_client(&client)
+                .with_details(format!("{} ({})", authorization.client.name, authorization.scope())),
+        )
+        .await;
+
+    issue_code(&state, &authorization, &authenticated_user).await
+}
+
+async fn issue_code(
+    state: &AppState,
+    authorization: &ValidatedAuthorization,
+    authenticated_user: &AuthenticatedUser,
+) -> Result<Response, AppError> {
+    let session_data = &authenticated_user.session_data;
+    let code = state
+        .oauth_server
+        .issue_code(authorization, session_data)
+        .await?;
+
+    tracing::info!(
+        "Issued authorization code for user ID {} to client {}",
+        session_data.user_id,
+        authorization.client.name
+    );
+    Ok(Redirect::to(&authorization.redirect_with(&[("code", &code)])).into_response())
+}
+
+pub async fn token_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(form): Form<TokenRequest>,
+) -> Result<Response, AppError> {
+    match form.grant_type.as_deref() {
+        Some("authorization_code") => {}
+        Some(other) => return Err(OAuthError::UnsupportedGrantType(other.to_string()).into()),
+        None => return Err(OAuthError::InvalidRequest("grant_type is required".to_string()).into()),
+    }
+
+    let (client_id, client_secret) = match basic_credentials(&headers)? {
+        Some(credentials) => (credentials.0, Some(credentials.1)),
+        None => (
+            form.client_id.ok_or(OAuthError::InvalidClient)?,
+            form.client_secret,
+        ),
+    };
+    let client = state
+        .oauth_server
+        .authenticate_client(&client_id, client_secret.as_deref())
+        .await?;
+
+    let code = form
+        .code
+        .ok_or_else(|| OAuthError::InvalidRequest("code is required".to_string()))?;
+    let tokens = state
+        .oauth_server
+        .exchange_code(&client, &code, form.redirect_uri.as_deref(), form.code_verifier.as_deref())
+        .await?;
+
+    // Tokens must never be cached along the way
+    Ok((
+        [(header::CACHE_CONTROL, "no-store"), (header::PRAGMA, "no-cache")],
+        Json(tokens),
+    )
+        .into_response())
+}
+
+pub async fn userinfo_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let access_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(OAuthError::InvalidToken)?;
+
+    let claims = state.oauth_server.userinfo(access_token.trim()).await?;
+    Ok(Json(claims))
+}
+
+// Client ID and secret from an `Authorization: Basic` header, form-urlencoded
+// inside as RFC 6749 section 2.3.1 requires
+fn basic_credentials(headers: &HeaderMap) -> Result<Option<(String, String)>, OAuthError> {
+    let Some(encoded) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+    else {
+        return Ok(None);
+    };
+
+    let decoded = STANDARD
+        .decode(encoded.trim())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or(OAuthError::InvalidClient)?;
+    let (client_id, client_secret) = decoded.split_once(':').ok_or(OAuthError::InvalidClient)?;
+    let decode = |value: &str| {
+        urlencoding::decode(&value.replace('+', " "))
+            .map(|value| value.into_owned())
+            .map_err(|_| OAuthError::InvalidClient)
+    };
+
+    Ok(Some((decode(client_id)?, decode(client_secret)?)))
+}
@@ -1,20 +1,30 @@
+pub mod api_tokens;
+pub mod audit;
 pub mod auth;
 pub mod config;
 pub mod database;
 pub mod error;
 pub mod handlers;
 pub mod models;
+pub mod oauth_server;
 pub mod session;
 pub mod templates;
 
-pub use config::Config;
-pub use error::{AppError, AuthError};
+pub use config::{Config, ConfigError, FieldSync, LoginStateMode, MicrosoftTenant, OidcConfig, SessionStoreKind};
+pub use api_tokens::{ApiScope, ApiToken, ApiTokenStore, ApiUser, CreatedApiToken};
+pub use audit::{AuditEvent, AuditEventType, AuditFilter, AuditLog, AuditOutcome, NewAuditEvent};
+pub use error::{AppError, AuthError, JsonError, OAuthError, always_json_errors_middleware, json_errors_middleware};
 pub use database::{Database, UserRepository};
-pub use auth::{OAuth2Config, AuthService};
-pub use templates::{LoginTemplate, DashboardTemplate};
-pub use session::{SessionManager, SessionExt, AuthenticatedUser, auth_middleware, optional_auth_middleware};
+pub use auth::{AccessPolicy, AdminBootstrap, OAuth2Config, AuthService, CallbackOutcome, IdentityProvider, LoginStateStore, OidcProvider, ProfileSyncPolicy, ProviderProfile};
+pub use models::{Role, UserSession, UserSort};
+pub use oauth_server::{OAuthClient, OAuthServer, RegisteredClient, SigningKeys, UserClaims};
+pub use templates::{LoginTemplate, DashboardTemplate, AdminUsersTemplate, AdminClientsTemplate, ConsentTemplate};
+pub use session::{SessionManager, SessionExt, SessionKeys, SessionPolicy, AppSessionStore, SqliteSessionStore, AuthenticatedUser, ClientInfo, AdminRole, RequireRole, RequireAdmin, auth_middleware, optional_auth_middleware, require_admin_middleware};
 pub use handlers::{
-    AppState, dashboard_handler, github_auth_handler, github_callback_handler,
-    login_handler, logout_handler, microsoft_auth_handler, microsoft_callback_handler,
-    root_handler,
+    AppState, api_router, admin_delete_user_handler, admin_disable_user_handler, admin_enable_user_handler,
+    admin_audit_export_handler, admin_audit_handler, admin_revoke_sessions_handler, admin_users_handler, revoke_session_handler,
+    create_api_token_handler, revoke_api_token_handler,
+    admin_clients_handler, admin_delete_client_handler, admin_register_client_handler,
+    authorize_decision_handler, authorize_handler, discovery_handler, jwks_handler, token_handler, userinfo_handler, sign_out_everywhere_handler, auth_callback_handler, auth_handler, dashboard_handler, link_handler,
+    login_handler, logout_handler, root_handler, unlink_handler,
 };
\ No newline at end of file
@@ -1,4 +1,5 @@
 use axum::{
+    middleware,
     routing::{get, post},
     Router,
 };
@@ -7,12 +8,20 @@ use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use sso_web_app::{
-    AppState, AuthService, Config, Database, OAuth2Config, SessionManager, UserRepository,
-    dashboard_handler, github_auth_handler, github_callback_handler,
-    login_handler, logout_handler, microsoft_auth_handler, microsoft_callback_handler,
-    root_handler,
+    AccessPolicy, AdminBootstrap, ApiTokenStore, AppState, AuditLog, AuthService, Config, Database, LoginStateStore, OAuth2Config, OAuthServer, OidcProvider, ProfileSyncPolicy,
+    SessionManager, UserRepository, auth_callback_handler, auth_handler, dashboard_handler,
+    link_handler, login_handler, logout_handler, root_handler, unlink_handler,
+    admin_delete_user_handler, admin_disable_user_handler, admin_enable_user_handler,
+    admin_audit_export_handler, admin_audit_handler, admin_revoke_sessions_handler, admin_users_handler,
+    admin_clients_handler, admin_delete_client_handler, admin_register_client_handler,
+    authorize_decision_handler, authorize_handler, discovery_handler, jwks_handler, token_handler, userinfo_handler,
+    revoke_session_handler, create_api_token_handler, revoke_api_token_handler,
+    sign_out_everywhere_handler, api_router, json_errors_middleware,
 };
 
+// How often expired sessions are purged from the database
+const SESSION_CLEANUP_INTERVAL_SECS: u64 = 300;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize tracing
@@ -43,19 +52,45 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let user_repository = UserRepository::new(database.pool().clone());
 
     // Initialize OAuth2 clients
-    let oauth2_config = OAuth2Config::new(&config)?;
+    let mut oauth2_config = OAuth2Config::new(&config)?;
+    if let Some(oidc) = &config.oidc {
+        let provider =
+            OidcProvider::discover(oidc, &config.base_url, oauth2_config.http_client.clone())
+                .await?;
+        tracing::info!("OpenID Connect provider '{}' discovered at {}", oidc.slug, oidc.issuer_url);
+        oauth2_config.register(provider);
+    }
     tracing::info!("OAuth2 clients configured");
 
     // Create authentication service
-    let auth_service = AuthService::new(oauth2_config, user_repository);
+    let login_states = LoginStateStore::new(database.pool().clone(), config.login_state_mode);
+    let auth_service = AuthService::new(oauth2_config, user_repository.clone(), login_states)
+        .with_profile_sync(ProfileSyncPolicy::from_config(&config))
+        .with_access_policy(AccessPolicy::from_config(&config))
+        .with_admin_bootstrap(AdminBootstrap::from_config(&config))
+        .with_upstream_logout(config.upstream_logout);
 
     // Set up session management
-    let session_manager = SessionManager::new();
-    let session_layer = session_manager.layer();
+    let session_manager = SessionManager::new(&config, database.pool().clone())?;
+    session_manager.spawn_expiry_cleanup(
+        user_repository.clone(),
+        std::time::Duration::from_secs(SESSION_CLEANUP_INTERVAL_SECS),
+    );
     tracing::info!("Session management configured");
 
+    // Our own OpenID provider for internal apps, issuing tokens under the public base URL
+    let oauth_server = OAuthServer::new(database.pool().clone(), &config.base_url).await?;
+    tracing::info!("OpenID provider ready with issuer {}", oauth_server.issuer());
+
     // Create application state
-    let app_state = AppState { auth_service };
+    let app_state = AppState {
+        auth_service,
+        user_repository,
+        session_policy: session_manager.policy(),
+        audit_log: AuditLog::new(database.pool().clone()),
+        oauth_server,
+        api_tokens: ApiTokenStore::new(database.pool().clone()),
+    };
 
     // Build our application with routes
     let app = Router::new()
@@ -64,18 +99,45 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         
         // Authentication routes (public)
         .route("/login", get(login_handler))
-        .route("/auth/microsoft", get(microsoft_auth_handler))
-        .route("/auth/github", get(github_auth_handler))
-        .route("/auth/callback/microsoft", get(microsoft_callback_handler))
-        .route("/auth/callback/github", get(github_callback_handler))
+        .route("/auth/:provider", get(auth_handler))
+        .route("/auth/callback/:provider", get(auth_callback_handler))
         
         // Protected routes (require authentication)
         .route("/dashboard", get(dashboard_handler))
+        .route("/account/link/:provider", get(link_handler))
+        .route("/account/identities/:id/unlink", post(unlink_handler))
+        .route("/account/sessions/:id/revoke", post(revoke_session_handler))
+        .route("/account/sessions/revoke-all", post(sign_out_everywhere_handler))
+        .route("/account/tokens", post(create_api_token_handler))
+        .route("/account/tokens/:id/revoke", post(revoke_api_token_handler))
         .route("/logout", post(logout_handler))
         
+        // OpenID provider routes for internal apps
+        .route("/.well-known/openid-configuration", get(discovery_handler))
+        .route("/oauth/jwks", get(jwks_handler))
+        .route("/oauth/authorize", get(authorize_handler).post(authorize_decision_handler))
+        .route("/oauth/token", post(token_handler))
+        .route("/oauth/userinfo", get(userinfo_handler).post(userinfo_handler))
+        
+        // Admin routes (require the admin role)
+        .route("/admin/users", get(admin_users_handler))
+        .route("/admin/users/:id/disable", post(admin_disable_user_handler))
+        .route("/admin/users/:id/enable", post(admin_enable_user_handler))
+        .route("/admin/users/:id/delete", post(admin_delete_user_handler))
+        .route("/admin/users/:id/sessions/revoke", post(admin_revoke_sessions_handler))
+        .route("/admin/audit", get(admin_audit_handler))
+        .route("/admin/audit/export", get(admin_audit_export_handler))
+        .route("/admin/clients", get(admin_clients_handler).post(admin_register_client_handler))
+        .route("/admin/clients/:id/delete", post(admin_delete_client_handler))
+        
+        // JSON API for scripts and internal apps
+        .nest("/api/v1", api_router())
+        
         // Add application state and middleware
-        .with_state(app_state)
-        .layer(session_layer)
+        .layer(middleware::from_fn(json_errors_middleware))
+        .with_state(app_state);
+    let app = session_manager
+        .apply(app)
         .layer(TraceLayer::new_for_http());
 
     // Run the server
@@ -85,8 +147,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     let listener = tokio::net::TcpListener::bind(addr).await?;
     
-    // Graceful shutdown handling
-    let app_with_graceful_shutdown = app.into_make_service();
+    // Graceful shutdown handling; connect info gives sessions their client IP
+    let app_with_graceful_shutdown = app.into_make_service_with_connect_info::<SocketAddr>();
     
     tracing::info!("✅ Server is ready to accept connections");
     axum::serve(listener, app_with_graceful_shutdown)
@@ -11,6 +11,17 @@ pub struct User {
     pub avatar_url: Option<String>,
     pub created_at: DateTime<Utc>,
     pub last_login: DateTime<Utc>,
+    // Set once the user edits their display name in this app
+    pub username_edited_at: Option<DateTime<Utc>>,
+    // Set while an admin has disabled the account
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub disabled_reason: Option<String>,
+}
+
+impl User {
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
 }
 
 impl sqlx::FromRow<'_, sqlx::sqlite::SqliteRow> for User {
@@ -33,6 +44,28 @@ impl sqlx::FromRow<'_, sqlx::sqlite::SqliteRow> for User {
                 source: Box::new(e),
             })?
             .with_timezone(&Utc);
+
+        let username_edited_at = row
+            .try_get::<Option<String>, _>("username_edited_at")?
+            .map(|value| {
+                DateTime::parse_from_rfc3339(&value).map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "username_edited_at".to_string(),
+                    source: Box::new(e),
+                })
+            })
+            .transpose()?
+            .map(|value| value.with_timezone(&Utc));
+
+        let disabled_at = row
+            .try_get::<Option<String>, _>("disabled_at")?
+            .map(|value| {
+                DateTime::parse_from_rfc3339(&value).map_err(|e| sqlx::Error::ColumnDecode {
+                    index: "disabled_at".to_string(),
+                    source: Box::new(e),
+                })
+            })
+            .transpose()?
+            .map(|value| value.with_timezone(&Utc));
         
         Ok(User {
             id: row.try_get("id")?,
@@ -43,6 +76,9 @@ impl sqlx::FromRow<'_, sqlx::sqlite::SqliteRow> for User {
             avatar_url: row.try_get("avatar_url")?,
             created_at,
             last_login,
+            username_edited_at,
+            disabled_at,
+            disabled_reason: row.try_get("disabled_reason")?,
         })
     }
 }
@@ -56,9 +92,183 @@ pub struct CreateUser {
     pub avatar_url: Option<String>,
 }
 
+// Profile fields to overwrite on login; `None` leaves a field unchanged
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileUpdate {
+    pub username: Option<String>,
+    pub email: Option<Option<String>>,
+    pub avatar_url: Option<Option<String>>,
+}
+
+impl ProfileUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.changed_fields().is_empty()
+    }
+
+    pub fn changed_fields(&self) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.username.is_some() {
+            fields.push("username");
+        }
+        if self.email.is_some() {
+            fields.push("email");
+        }
+        if self.avatar_url.is_some() {
+            fields.push("avatar_url");
+        }
+        fields
+    }
+}
+
+// Order of the admin user list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserSort {
+    #[default]
+    LastLoginDesc,
+    LastLoginAsc,
+}
+
+impl UserSort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserSort::LastLoginDesc => "last_login_desc",
+            UserSort::LastLoginAsc => "last_login_asc",
+        }
+    }
+
+    pub fn reversed(&self) -> Self {
+        match self {
+            UserSort::LastLoginDesc => UserSort::LastLoginAsc,
+            UserSort::LastLoginAsc => UserSort::LastLoginDesc,
+        }
+    }
+}
+
+// One page of the admin user list; `page` counts from 1
+#[derive(Debug, Clone, Default)]
+pub struct UserListQuery {
+    pub search: Option<String>,
+    // Only disabled users, only enabled ones, or both when unset
+    pub disabled: Option<bool>,
+    pub sort: UserSort,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+// Permission granted to a user on top of being signed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionData {
     pub user_id: i64,
     pub username: String,
     pub provider: String,
-}
\ No newline at end of file
+    // When the user signed in; sessions written before this field existed count as expired
+    #[serde(default)]
+    pub issued_at: DateTime<Utc>,
+    // Last authenticated request, used for the idle timeout
+    #[serde(default)]
+    pub last_seen: DateTime<Utc>,
+    // Roles held at sign-in
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    // Row in `user_sessions` tracking this sign-in; revoking it ends the session
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+impl SessionData {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+}
+
+// A provider account that can be used to sign in as a user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identity {
+    pub id: i64,
+    pub user_id: i64,
+    pub provider: String,
+    pub provider_id: String,
+    pub email: Option<String>,
+    pub linked_at: DateTime<Utc>,
+}
+
+impl sqlx::FromRow<'_, sqlx::sqlite::SqliteRow> for Identity {
+    fn from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        use sqlx::Row;
+
+        let linked_at_str: String = row.try_get("linked_at")?;
+        let linked_at = DateTime::parse_from_rfc3339(&linked_at_str)
+            .map_err(|e| sqlx::Error::ColumnDecode {
+                index: "linked_at".to_string(),
+                source: Box::new(e),
+            })?
+            .with_timezone(&Utc);
+
+        Ok(Identity {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            provider: row.try_get("provider")?,
+            provider_id: row.try_get("provider_id")?,
+            email: row.try_get("email")?,
+            linked_at,
+        })
+    }
+}
+
+// A signed-in browser session, tracked so it can be listed and revoked
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserSession {
+    pub id: String,
+    pub user_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl sqlx::FromRow<'_, sqlx::sqlite::SqliteRow> for UserSession {
+    fn from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        use sqlx::Row;
+
+        let parse = |column: &str| -> Result<DateTime<Utc>, sqlx::Error> {
+            let value: String = row.try_get(column)?;
+            DateTime::parse_from_rfc3339(&value)
+                .map(|value| value.with_timezone(&Utc))
+                .map_err(|e| sqlx::Error::ColumnDecode {
+                    index: column.to_string(),
+                    source: Box::new(e),
+                })
+        };
+
+        Ok(UserSession {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            created_at: parse("created_at")?,
+            last_seen: parse("last_seen")?,
+            ip_address: row.try_get("ip_address")?,
+            user_agent: row.try_get("user_agent")?,
+        })
+    }
+}
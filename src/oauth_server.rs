@@ -0,0 +1,915 @@
+//! OpenID Connect provider for internal applications.
+//!
+//! Registered clients sign their users in through this app with the
+//! authorization code flow and PKCE, and receive ID tokens whose `sub` is the
+//! local user ID, whichever upstream provider the user signed in with.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use oauth2::url::Url;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::SqlitePool;
+
+use crate::{
+    database::UserRepository,
+    error::{AppError, OAuthError},
+    models::{SessionData, User},
+};
+
+pub mod keys;
+pub mod logout;
+
+pub use keys::SigningKeys;
+pub use logout::LogoutNotice;
+
+// Scopes clients may request; anything else is ignored
+pub const SUPPORTED_SCOPES: &[&str] = &["openid", "profile", "email"];
+
+// How long an authorization code may wait to be exchanged
+const CODE_TTL_SECONDS: i64 = 300;
+
+// Lifetime of access tokens and ID tokens
+const TOKEN_TTL_SECONDS: i64 = 3600;
+
+// An application registered to sign users in through this app
+#[derive(Debug, Clone)]
+pub struct OAuthClient {
+    pub client_id: String,
+    pub name: String,
+    // SHA-256 of the client secret; public clients have none
+    pub secret_hash: Option<String>,
+    pub redirect_uris: Vec<String>,
+    // Where OpenID back-channel logout tokens are posted, if the client accepts them
+    pub backchannel_logout_uri: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OAuthClient {
+    pub fn is_public(&self) -> bool {
+        self.secret_hash.is_none()
+    }
+
+    // Redirect URIs must match a registered one exactly
+    pub fn allows_redirect(&self, redirect_uri: &str) -> bool {
+        self.redirect_uris.iter().any(|allowed| allowed == redirect_uri)
+    }
+}
+
+impl sqlx::FromRow<'_, sqlx::sqlite::SqliteRow> for OAuthClient {
+    fn from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        use sqlx::Row;
+
+        let created_at_str: String = row.try_get("created_at")?;
+        let created_at = DateTime::parse_from_rfc3339(&created_at_str)
+            .map_err(|e| sqlx::Error::ColumnDecode {
+                index: "created_at".to_string(),
+                source: Box::new(e),
+            })?
+            .with_timezone(&Utc);
+
+        let redirect_uris: String = row.try_get("redirect_uris")?;
+
+        Ok(OAuthClient {
+            client_id: row.try_get("client_id")?,
+            name: row.try_get("name")?,
+            secret_hash: row.try_get("secret_hash")?,
+            redirect_uris: redirect_uris.lines().map(str::to_string).collect(),
+            backchannel_logout_uri: row.try_get("backchannel_logout_uri")?,
+            created_at,
+        })
+    }
+}
+
+/// A newly registered client, with its secret in the clear.
+///
+/// The secret is only stored hashed, so this is the one chance to show it.
+#[derive(Debug, Clone)]
+pub struct RegisteredClient {
+    pub client: OAuthClient,
+    pub client_secret: Option<String>,
+}
+
+// Parameters of an authorization request, as sent by the client
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthorizationRequest {
+    pub response_type: Option<String>,
+    pub client_id: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    // `none` asks for an answer without showing any page
+    pub prompt: Option<String>,
+}
+
+/// An authorization request from a known client to one of its registered
+/// redirect URIs, so errors from here on can be sent back to the client.
+#[derive(Debug, Clone)]
+pub struct ValidatedAuthorization {
+    pub client: OAuthClient,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: String,
+    pub prompt_none: bool,
+}
+
+impl ValidatedAuthorization {
+    pub fn scope(&self) -> String {
+        self.scopes.join(" ")
+    }
+
+    // The client's redirect URI with the given parameters and the request's state
+    pub fn redirect_with(&self, params: &[(&str, &str)]) -> String {
+        redirect_url(&self.redirect_uri, params, self.state.as_deref())
+    }
+
+    pub fn refuse(&self, error: &'static str, description: &str) -> OAuthError {
+        OAuthError::AuthorizationRefused {
+            redirect_uri: self.redirect_uri.clone(),
+            state: self.state.clone(),
+            error,
+            description: description.to_string(),
+        }
+    }
+}
+
+/// Successful token endpoint response.
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    pub id_token: String,
+    pub scope: String,
+}
+
+/// Claims about a user released for the granted scopes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserClaims {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+impl UserClaims {
+    pub fn for_scopes(user: &User, scopes: &[String]) -> Self {
+        let granted = |scope: &str| scopes.iter().any(|granted| granted == scope);
+        let profile = granted("profile");
+
+        Self {
+            sub: user.id.to_string(),
+            name: profile.then(|| user.username.clone()),
+            preferred_username: profile.then(|| user.username.clone()),
+            picture: user.avatar_url.clone().filter(|_| profile),
+            email: user.email.clone().filter(|_| granted("email")),
+        }
+    }
+}
+
+// Claims of the ID tokens this app signs
+#[derive(Debug, Serialize)]
+struct IdTokenClaims {
+    iss: String,
+    aud: String,
+    exp: i64,
+    iat: i64,
+    auth_time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    // The sign-in the token belongs to, as named in back-channel logout tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sid: Option<String>,
+    #[serde(flatten)]
+    user: UserClaims,
+}
+
+// An authorization code as read back for its one exchange
+struct StoredCode {
+    client_id: String,
+    user_id: i64,
+    redirect_uri: String,
+    scope: String,
+    nonce: Option<String>,
+    code_challenge: String,
+    session_id: Option<String>,
+    auth_time: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl sqlx::FromRow<'_, sqlx::sqlite::SqliteRow> for StoredCode {
+    fn from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        use sqlx::Row;
+
+        let parse = |column: &str| -> Result<DateTime<Utc>, sqlx::Error> {
+            let value: String = row.try_get(column)?;
+            DateTime::parse_from_rfc3339(&value)
+                .map(|value| value.with_timezone(&Utc))
+                .map_err(|e| sqlx::Error::ColumnDecode {
+                    index: column.to_string(),
+                    source: Box::new(e),
+                })
+        };
+
+        Ok(StoredCode {
+            client_id: row.try_get("client_id")?,
+            user_id: row.try_get("user_id")?,
+            redirect_uri: row.try_get("redirect_uri")?,
+            scope: row.try_get("scope")?,
+            nonce: row.try_get("nonce")?,
+            code_challenge: row.try_get("code_challenge")?,
+            session_id: row.try_get("session_id")?,
+            auth_time: parse("auth_time")?,
+            expires_at: parse("expires_at")?,
+        })
+    }
+}
+
+/// The authorization server: clients, codes, tokens, consent and signing keys.
+#[derive(Debug, Clone)]
+pub struct OAuthServer {
+    pool: SqlitePool,
+    user_repository: UserRepository,
+    issuer: String,
+    keys: SigningKeys,
+    // Delivers back-channel logout tokens
+    http_client: reqwest::Client,
+}
+
+impl OAuthServer {
+    /// `issuer` is the app's public base URL, which all endpoints hang off.
+    pub async fn new(pool: SqlitePool, issuer: &str) -> Result<Self, AppError> {
+        let keys = SigningKeys::load_or_generate(&pool).await?;
+
+        Ok(Self {
+            user_repository: UserRepository::new(pool.clone()),
+            pool,
+            issuer: issuer.trim_end_matches('/').to_string(),
+            keys,
+            http_client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(logout::LOGOUT_TIMEOUT_SECS))
+                .redirect(reqwest::redirect::Policy::none())
+                .build()?,
+        })
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn jwks(&self) -> &Value {
+        self.keys.jwks()
+    }
+
+    /// Provider metadata served at `/.well-known/openid-configuration`.
+    pub fn discovery_document(&self) -> Value {
+        json!({
+            "issuer": self.issuer,
+            "authorization_endpoint": format!("{}/oauth/authorize", self.issuer),
+            "token_endpoint": format!("{}/oauth/token", self.issuer),
+            "userinfo_endpoint": format!("{}/oauth/userinfo", self.issuer),
+            "jwks_uri": format!("{}/oauth/jwks", self.issuer),
+            "scopes_supported": SUPPORTED_SCOPES,
+            "response_types_supported": ["code"],
+            "grant_types_supported": ["authorization_code"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["ES256"],
+            "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post", "none"],
+            "code_challenge_methods_supported": ["S256"],
+            "claims_supported": ["sub", "iss", "aud", "exp", "iat", "auth_time", "nonce", "sid", "name", "preferred_username", "picture", "email"],
+            "backchannel_logout_supported": true,
+            "backchannel_logout_session_supported": true,
+        })
+    }
+
+    // Client registration
+
+    /// Register a client; confidential clients get a generated secret.
+    pub async fn register_client(
+        &self,
+        name: &str,
+        redirect_uris: Vec<String>,
+        backchannel_logout_uri: Option<String>,
+        confidential: bool,
+    ) -> Result<RegisteredClient, AppError> {
+        let client_secret = confidential.then(random_token);
+        let client = OAuthClient {
+            client_id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            secret_hash: client_secret.as_deref().map(hash_token),
+            redirect_uris,
+            backchannel_logout_uri,
+            created_at: Utc::now(),
+        };
+
+        sqlx::query(
+            "INSERT INTO oauth_clients (client_id, name, secret_hash, redirect_uris, backchannel_logout_uri, created_at)
+             VALUES (?, ?, ?, ?, ?, ?)"
+        )
+        .bind(&client.client_id)
+        .bind(&client.name)
+        .bind(&client.secret_hash)
+        .bind(client.redirect_uris.join("\n"))
+        .bind(&client.backchannel_logout_uri)
+        .bind(client.created_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(RegisteredClient { client, client_secret })
+    }
+
+    pub async fn find_client(&self, client_id: &str) -> Result<Option<OAuthClient>, AppError> {
+        let client = sqlx::query_as::<_, OAuthClient>(
+            "SELECT client_id, name, secret_hash, redirect_uris, backchannel_logout_uri, created_at FROM oauth_clients WHERE client_id = ?"
+        )
+        .bind(client_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(client)
+    }
+
+    pub async fn list_clients(&self) -> Result<Vec<OAuthClient>, AppError> {
+        let clients = sqlx::query_as::<_, OAuthClient>(
+            "SELECT client_id, name, secret_hash, redirect_uris, backchannel_logout_uri, created_at FROM oauth_clients ORDER BY name COLLATE NOCASE"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(clients)
+    }
+
+    /// Delete a client with its codes, tokens and consents; false if unknown.
+    pub async fn delete_client(&self, client_id: &str) -> Result<bool, AppError> {
+        let result = sqlx::query("DELETE FROM oauth_clients WHERE client_id = ?")
+            .bind(client_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Check the client's credentials at the token endpoint.
+    pub async fn authenticate_client(
+        &self,
+        client_id: &str,
+        client_secret: Option<&str>,
+    ) -> Result<OAuthClient, AppError> {
+        let client = self
+            .find_client(client_id)
+            .await?
+            .ok_or(OAuthError::InvalidClient)?;
+
+        if let Some(secret_hash) = &client.secret_hash {
+            let presented = client_secret.map(hash_token);
+            if presented.as_deref() != Some(secret_hash.as_str()) {
+                return Err(OAuthError::InvalidClient.into());
+            }
+        }
+
+        Ok(client)
+    }
+
+    // Authorization
+
+    /// Check an authorization request before any user interaction.
+    ///
+    /// An unknown client or redirect URI is reported to the user, never
+    /// redirected; other problems go back to the client's redirect URI.
+    pub async fn validate_authorization(
+        &self,
+        request: &AuthorizationRequest,
+    ) -> Result<ValidatedAuthorization, AppError> {
+        let client_id = request.client_id.as_deref().ok_or(OAuthError::UnknownClient)?;
+        let client = self
+            .find_client(client_id)
+            .await?
+            .ok_or(OAuthError::UnknownClient)?;
+        let redirect_uri = request
+            .redirect_uri
+            .clone()
+            .filter(|redirect_uri| client.allows_redirect(redirect_uri))
+            .ok_or(OAuthError::UnknownClient)?;
+
+        let scopes = normalize_scopes(request.scope.as_deref().unwrap_or_default());
+        let validated = ValidatedAuthorization {
+            client,
+            redirect_uri,
+            scopes,
+            state: request.state.clone(),
+            nonce: request.nonce.clone(),
+            code_challenge: request.code_challenge.clone().unwrap_or_default(),
+            prompt_none: request.prompt.as_deref() == Some("none"),
+        };
+
+        if request.response_type.as_deref() != Some("code") {
+            return Err(validated
+                .refuse("unsupported_response_type", "Only the authorization code flow is supported.")
+                .into());
+        }
+        if !validated.scopes.iter().any(|scope| scope == "openid") {
+            return Err(validated.refuse("invalid_scope", "The openid scope is required.").into());
+        }
+        if validated.code_challenge.is_empty() {
+            return Err(validated.refuse("invalid_request", "PKCE code_challenge is required.").into());
+        }
+        if request.code_challenge_method.as_deref() != Some("S256") {
+            return Err(validated
+                .refuse("invalid_request", "code_challenge_method must be S256.")
+                .into());
+        }
+
+        Ok(validated)
+    }
+
+    /// Whether the user already agreed to share all of `scopes` with the client.
+    pub async fn has_consent(&self, user_id: i64, client_id: &str, scopes: &[String]) -> Result<bool, AppError> {
+        let granted: Option<(String,)> =
+            sqlx::query_as("SELECT scope FROM oauth_consents WHERE user_id = ? AND client_id = ?")
+                .bind(user_id)
+                .bind(client_id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(granted.is_some_and(|(granted,)| {
+            let granted: Vec<&str> = granted.split_whitespace().collect();
+            scopes.iter().all(|scope| granted.contains(&scope.as_str()))
+        }))
+    }
+
+    /// Remember consent, adding `scopes` to anything granted before.
+    pub async fn grant_consent(&self, user_id: i64, client_id: &str, scopes: &[String]) -> Result<(), AppError> {
+        let previous: Option<(String,)> =
+            sqlx::query_as("SELECT scope FROM oauth_consents WHERE user_id = ? AND client_id = ?")
+                .bind(user_id)
+                .bind(client_id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        let mut granted = scopes.to_vec();
+        if let Some((previous,)) = previous {
+            granted.extend(previous.split_whitespace().map(str::to_string));
+        }
+
+        sqlx::query(
+            "INSERT INTO oauth_consents (user_id, client_id, scope, granted_at) VALUES (?, ?, ?, ?)
+             ON CONFLICT (user_id, client_id) DO UPDATE SET scope = excluded.scope, granted_at = excluded.granted_at"
+        )
+        .bind(user_id)
+        .bind(client_id)
+        .bind(normalize_scopes(&granted.join(" ")).join(" "))
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Issue a one-time code for the signed-in user, tied to their session.
+    pub async fn issue_code(
+        &self,
+        authorization: &ValidatedAuthorization,
+        session_data: &SessionData,
+    ) -> Result<String, AppError> {
+        // Opportunistically drop codes that were never exchanged
+        sqlx::query("DELETE FROM oauth_authorization_codes WHERE expires_at < ?")
+            .bind(Utc::now().to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+
+        let code = random_token();
+        sqlx::query(
+            "INSERT INTO oauth_authorization_codes
+             (code_hash, client_id, user_id, redirect_uri, scope, nonce, code_challenge, session_id, auth_time, expires_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(hash_token(&code))
+        .bind(&authorization.client.client_id)
+        .bind(session_data.user_id)
+        .bind(&authorization.redirect_uri)
+        .bind(authorization.scope())
+        .bind(&authorization.nonce)
+        .bind(&authorization.code_challenge)
+        .bind(&session_data.session_id)
+        .bind(session_data.issued_at.to_rfc3339())
+        .bind((Utc::now() + Duration::seconds(CODE_TTL_SECONDS)).to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(code)
+    }
+
+    // Tokens
+
+    /// Redeem an authorization code for an access token and ID token.
+    ///
+    /// The code is deleted before anything is checked, so it never works twice.
+    /// Codes of a session that has since ended are already gone.
+    pub async fn exchange_code(
+        &self,
+        client: &OAuthClient,
+        code: &str,
+        redirect_uri: Option<&str>,
+        code_verifier: Option<&str>,
+    ) -> Result<TokenResponse, AppError> {
+        let stored = sqlx::query_as::<_, StoredCode>(
+            "DELETE FROM oauth_authorization_codes WHERE code_hash = ?
+             RETURNING client_id, user_id, redirect_uri, scope, nonce, code_challenge, session_id, auth_time, expires_at"
+        )
+        .bind(hash_token(code))
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| OAuthError::InvalidGrant("unknown or already used authorization code".to_string()))?;
+
+        if stored.expires_at < Utc::now() {
+            return Err(OAuthError::InvalidGrant("authorization code expired".to_string()).into());
+        }
+        if stored.client_id != client.client_id {
+            return Err(OAuthError::InvalidGrant("authorization code was issued to another client".to_string()).into());
+        }
+        if redirect_uri != Some(stored.redirect_uri.as_str()) {
+            return Err(OAuthError::InvalidGrant("redirect_uri does not match the authorization request".to_string()).into());
+        }
+        let verifier = code_verifier.ok_or_else(|| OAuthError::InvalidGrant("code_verifier is required".to_string()))?;
+        if pkce_challenge(verifier) != stored.code_challenge {
+            return Err(OAuthError::InvalidGrant("code_verifier does not match the code challenge".to_string()).into());
+        }
+
+        let user = self.active_user(stored.user_id).await?.ok_or_else(|| {
+            OAuthError::InvalidGrant("user account is no longer active".to_string())
+        })?;
+        let scopes = normalize_scopes(&stored.scope);
+
+        let now = Utc::now();
+        let access_token = random_token();
+        sqlx::query("DELETE FROM oauth_access_tokens WHERE expires_at < ?")
+            .bind(now.to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+        sqlx::query(
+            "INSERT INTO oauth_access_tokens (token_hash, client_id, user_id, scope, session_id, expires_at)
+             VALUES (?, ?, ?, ?, ?, ?)"
+        )
+        .bind(hash_token(&access_token))
+        .bind(&client.client_id)
+        .bind(user.id)
+        .bind(&stored.scope)
+        .bind(&stored.session_id)
+        .bind((now + Duration::seconds(TOKEN_TTL_SECONDS)).to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        // Remember the client signed in under this session, to tell it when the session ends
+        if let Some(session_id) = &stored.session_id {
+            sqlx::query(
+                "INSERT INTO oauth_client_sessions (session_id, client_id, user_id, created_at) VALUES (?, ?, ?, ?)
+                 ON CONFLICT (session_id, client_id) DO NOTHING"
+            )
+            .bind(session_id)
+            .bind(&client.client_id)
+            .bind(user.id)
+            .bind(now.to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+        }
+
+        let id_token = self.keys.sign("JWT", &IdTokenClaims {
+            iss: self.issuer.clone(),
+            aud: client.client_id.clone(),
+            exp: (now + Duration::seconds(TOKEN_TTL_SECONDS)).timestamp(),
+            iat: now.timestamp(),
+            auth_time: stored.auth_time.timestamp(),
+            nonce: stored.nonce,
+            sid: stored.session_id,
+            user: UserClaims::for_scopes(&user, &scopes),
+        })?;
+
+        Ok(TokenResponse {
+            access_token,
+            token_type: "Bearer",
+            expires_in: TOKEN_TTL_SECONDS,
+            id_token,
+            scope: scopes.join(" "),
+        })
+    }
+
+    /// Claims for the user an access token was issued to.
+    pub async fn userinfo(&self, access_token: &str) -> Result<UserClaims, AppError> {
+        let row: Option<(i64, String, String)> = sqlx::query_as(
+            "SELECT user_id, scope, expires_at FROM oauth_access_tokens WHERE token_hash = ?"
+        )
+        .bind(hash_token(access_token))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let (user_id, scope, expires_at) = row.ok_or(OAuthError::InvalidToken)?;
+        if parse_timestamp(&expires_at)? < Utc::now() {
+            return Err(OAuthError::InvalidToken.into());
+        }
+        let user = self.active_user(user_id).await?.ok_or(OAuthError::InvalidToken)?;
+
+        Ok(UserClaims::for_scopes(&user, &normalize_scopes(&scope)))
+    }
+
+    // Disabled and deleted users get nothing more from the provider
+    async fn active_user(&self, user_id: i64) -> Result<Option<User>, AppError> {
+        Ok(self
+            .user_repository
+            .find_by_id(user_id)
+            .await?
+            .filter(|user| !user.is_disabled()))
+    }
+}
+
+/// Parse the redirect URIs an admin entered, one per line.
+///
+/// Each must be an absolute http(s) URL without a fragment; plain http is
+/// only accepted for localhost.
+pub fn parse_redirect_uris(value: &str) -> Result<Vec<String>, &'static str> {
+    let mut redirect_uris: Vec<String> = Vec::new();
+    for line in value.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        check_client_uri(line).map_err(|problem| match problem {
+            UriProblem::NotAbsolute => "Redirect URIs must be absolute URLs.",
+            UriProblem::Insecure => "Redirect URIs must use https, except on localhost.",
+            UriProblem::Fragment => "Redirect URIs must not contain a fragment.",
+        })?;
+        if !redirect_uris.iter().any(|existing| existing == line) {
+            redirect_uris.push(line.to_string());
+        }
+    }
+
+    if redirect_uris.is_empty() {
+        return Err("At least one redirect URI is required.");
+    }
+    Ok(redirect_uris)
+}
+
+/// Parse the optional back-channel logout URI an admin entered.
+pub fn parse_backchannel_logout_uri(value: &str) -> Result<Option<String>, &'static str> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    check_client_uri(value).map_err(|problem| match problem {
+        UriProblem::NotAbsolute => "The back-channel logout URI must be an absolute URL.",
+        UriProblem::Insecure => "The back-channel logout URI must use https, except on localhost.",
+        UriProblem::Fragment => "The back-channel logout URI must not contain a fragment.",
+    })?;
+    Ok(Some(value.to_string()))
+}
+
+enum UriProblem {
+    NotAbsolute,
+    Insecure,
+    Fragment,
+}
+
+// Client URIs are absolute https URLs without a fragment; plain http is only for localhost
+fn check_client_uri(value: &str) -> Result<(), UriProblem> {
+    let url = Url::parse(value).map_err(|_| UriProblem::NotAbsolute)?;
+    let local = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+    if url.scheme() != "https" && !(url.scheme() == "http" && local) {
+        return Err(UriProblem::Insecure);
+    }
+    if url.fragment().is_some() {
+        return Err(UriProblem::Fragment);
+    }
+    Ok(())
+}
+
+// Supported scopes in a stable order, without duplicates
+fn normalize_scopes(scope: &str) -> Vec<String> {
+    let requested: Vec<&str> = scope.split_whitespace().collect();
+    SUPPORTED_SCOPES
+        .iter()
+        .filter(|supported| requested.contains(supported))
+        .map(|scope| scope.to_string())
+        .collect()
+}
+
+// `redirect_uri` with `params` and `state` added to any query it already has
+pub(crate) fn redirect_url(redirect_uri: &str, params: &[(&str, &str)], state: Option<&str>) -> String {
+    let Ok(mut url) = Url::parse(redirect_uri) else {
+        return redirect_uri.to_string();
+    };
+    {
+        let mut query = url.query_pairs_mut();
+        query.extend_pairs(params);
+        if let Some(state) = state {
+            query.append_pair("state", state);
+        }
+    }
+    url.to_string()
+}
+
+// 256 random bits, URL-safe; used for codes, tokens and client secrets
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// Codes, tokens and secrets are only stored as their SHA-256
+pub(crate) fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// RFC 7636 S256: BASE64URL(SHA256(verifier))
+fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, OAuthError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|value| value.with_timezone(&Utc))
+        .map_err(|e| OAuthError::ServerError(format!("invalid stored timestamp: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::Database, models::CreateUser};
+    use tempfile::NamedTempFile;
+
+    const REDIRECT_URI: &str = "https://app.example.com/callback";
+
+    async fn setup() -> (OAuthServer, User, NamedTempFile) {
+        let (server, user, _, temp_file) = setup_with_session().await;
+        (server, user, temp_file)
+    }
+
+    // A server with one signed-in user
+    async fn setup_with_session() -> (OAuthServer, User, SessionData, NamedTempFile) {
+        let temp_file = NamedTempFile::new().unwrap();
+        let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
+        let db = Database::new(&database_url).await.unwrap();
+
+        let users = UserRepository::new(db.pool().clone());
+        let user = users
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "12345".to_string(),
+                username: "octocat".to_string(),
+                email: Some("octocat@example.com".to_string()),
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        let tracked = users.create_session(user.id, None, None).await.unwrap();
+        let session_data = SessionData {
+            user_id: user.id,
+            username: user.username.clone(),
+            provider: user.provider.clone(),
+            issued_at: tracked.created_at,
+            last_seen: tracked.last_seen,
+            roles: Vec::new(),
+            session_id: Some(tracked.id),
+        };
+        let server = OAuthServer::new(db.pool().clone(), "https://sso.example.com/").await.unwrap();
+        (server, user, session_data, temp_file)
+    }
+
+    fn request(client_id: &str, verifier: &str) -> AuthorizationRequest {
+        AuthorizationRequest {
+            response_type: Some("code".to_string()),
+            client_id: Some(client_id.to_string()),
+            redirect_uri: Some(REDIRECT_URI.to_string()),
+            scope: Some("openid email unknown".to_string()),
+            state: Some("xyz".to_string()),
+            nonce: Some("n-0S6".to_string()),
+            code_challenge: Some(pkce_challenge(verifier)),
+            code_challenge_method: Some("S256".to_string()),
+            prompt: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_redirect_uris() {
+        assert_eq!(
+            parse_redirect_uris("https://a.example.com/cb\n\n http://localhost:8080/cb \nhttps://a.example.com/cb").unwrap(),
+            vec!["https://a.example.com/cb", "http://localhost:8080/cb"]
+        );
+        assert!(parse_redirect_uris("").is_err());
+        assert!(parse_redirect_uris("/callback").is_err());
+        assert!(parse_redirect_uris("http://app.example.com/cb").is_err());
+        assert!(parse_redirect_uris("https://app.example.com/cb#frag").is_err());
+    }
+
+    #[test]
+    fn test_normalize_scopes_drops_unknown_and_duplicates() {
+        assert_eq!(normalize_scopes("email openid foo openid"), vec!["openid", "email"]);
+    }
+
+    #[test]
+    fn test_redirect_url_keeps_existing_query() {
+        assert_eq!(
+            redirect_url("https://app.example.com/cb?tenant=a", &[("code", "abc")], Some("x y")),
+            "https://app.example.com/cb?tenant=a&code=abc&state=x+y"
+        );
+    }
+
+    #[test]
+    fn test_pkce_challenge_matches_rfc_example() {
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_secret_is_checked_against_hash() {
+        let (server, _user, _temp_file) = setup().await;
+        let registered = server
+            .register_client("Wiki", vec![REDIRECT_URI.to_string()], None, true)
+            .await
+            .unwrap();
+        let secret = registered.client_secret.unwrap();
+        assert_ne!(registered.client.secret_hash.as_deref(), Some(secret.as_str()));
+
+        let client_id = &registered.client.client_id;
+        assert!(server.authenticate_client(client_id, Some(&secret)).await.is_ok());
+        assert!(server.authenticate_client(client_id, Some("wrong")).await.is_err());
+        assert!(server.authenticate_client(client_id, None).await.is_err());
+        assert!(server.authenticate_client("unknown", Some(&secret)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_redirect_is_not_redirected() {
+        let (server, _user, _temp_file) = setup().await;
+        let client = server.register_client("Wiki", vec![REDIRECT_URI.to_string()], None, false).await.unwrap().client;
+
+        let mut bad_redirect = request(&client.client_id, "verifier");
+        bad_redirect.redirect_uri = Some("https://evil.example.com/callback".to_string());
+        let error = server.validate_authorization(&bad_redirect).await.unwrap_err();
+        assert!(matches!(error, AppError::OAuth(OAuthError::UnknownClient)));
+
+        let mut no_pkce = request(&client.client_id, "verifier");
+        no_pkce.code_challenge = None;
+        let error = server.validate_authorization(&no_pkce).await.unwrap_err();
+        assert!(matches!(
+            error,
+            AppError::OAuth(OAuthError::AuthorizationRefused { error: "invalid_request", .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_code_exchange_and_userinfo() {
+        let (server, user, session_data, _temp_file) = setup_with_session().await;
+        let client = server.register_client("Wiki", vec![REDIRECT_URI.to_string()], None, false).await.unwrap().client;
+        let verifier = "a-sufficiently-long-code-verifier-for-the-test";
+
+        let authorization = server.validate_authorization(&request(&client.client_id, verifier)).await.unwrap();
+        assert_eq!(authorization.scopes, vec!["openid", "email"]);
+        let code = server.issue_code(&authorization, &session_data).await.unwrap();
+
+        // A wrong verifier burns the code
+        let error = server.exchange_code(&client, &code, Some(REDIRECT_URI), Some("wrong")).await.unwrap_err();
+        assert!(matches!(error, AppError::OAuth(OAuthError::InvalidGrant(_))));
+        assert!(server.exchange_code(&client, &code, Some(REDIRECT_URI), Some(verifier)).await.is_err());
+
+        let code = server.issue_code(&authorization, &session_data).await.unwrap();
+        let tokens = server.exchange_code(&client, &code, Some(REDIRECT_URI), Some(verifier)).await.unwrap();
+        assert_eq!(tokens.scope, "openid email");
+
+        let claims = server.userinfo(&tokens.access_token).await.unwrap();
+        assert_eq!(claims.sub, user.id.to_string());
+        assert_eq!(claims.email.as_deref(), Some("octocat@example.com"));
+        assert!(claims.name.is_none());
+        assert!(server.userinfo("not-a-token").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_consent_accumulates_scopes() {
+        let (server, user, _temp_file) = setup().await;
+        let client = server.register_client("Wiki", vec![REDIRECT_URI.to_string()], None, false).await.unwrap().client;
+        let scopes = |list: &[&str]| list.iter().map(|scope| scope.to_string()).collect::<Vec<_>>();
+
+        assert!(!server.has_consent(user.id, &client.client_id, &scopes(&["openid"])).await.unwrap());
+        server.grant_consent(user.id, &client.client_id, &scopes(&["openid", "email"])).await.unwrap();
+        server.grant_consent(user.id, &client.client_id, &scopes(&["openid", "profile"])).await.unwrap();
+        assert!(server
+            .has_consent(user.id, &client.client_id, &scopes(&["openid", "profile", "email"]))
+            .await
+            .unwrap());
+
+        // Deleting the client forgets the consent with it
+        assert!(server.delete_client(&client.client_id).await.unwrap());
+        assert!(!server.has_consent(user.id, &client.client_id, &scopes(&["openid"])).await.unwrap());
+    }
+}
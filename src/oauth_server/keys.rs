@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use p256::pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding};
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePool;
+
+use crate::error::{AppError, OAuthError};
+
+// A private key with the public JWK it is published as
+struct LoadedKey {
+    kid: String,
+    encoding_key: EncodingKey,
+    jwk: Value,
+}
+
+/// ES256 keys that sign the ID tokens this app issues.
+///
+/// Keys live in `oauth_signing_keys`; the newest one signs and every stored
+/// key is published in the JWKS, so tokens signed before a rotation still
+/// verify until the old key is deleted.
+#[derive(Clone)]
+pub struct SigningKeys {
+    current: Arc<LoadedKey>,
+    jwks: Arc<Value>,
+}
+
+impl std::fmt::Debug for SigningKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigningKeys")
+            .field("kid", &self.current.kid)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SigningKeys {
+    /// Load the stored keys, generating the first one on a fresh database.
+    pub async fn load_or_generate(pool: &SqlitePool) -> Result<Self, AppError> {
+        if let Some(keys) = Self::load(pool).await? {
+            return Ok(keys);
+        }
+
+        Self::rotate(pool).await
+    }
+
+    /// Generate a new signing key; it signs from now on.
+    pub async fn rotate(pool: &SqlitePool) -> Result<Self, AppError> {
+        let secret = p256::SecretKey::random(&mut rand_core::OsRng);
+        let pem = secret
+            .to_pkcs8_pem(LineEnding::LF)
+            .map_err(|e| OAuthError::ServerError(format!("cannot encode signing key: {}", e)))?;
+        let kid = uuid::Uuid::new_v4().to_string();
+
+        sqlx::query("INSERT INTO oauth_signing_keys (kid, private_key_pem, created_at) VALUES (?, ?, ?)")
+            .bind(&kid)
+            .bind(pem.as_str())
+            .bind(Utc::now().to_rfc3339())
+            .execute(pool)
+            .await?;
+        tracing::info!("Generated ID token signing key {}", kid);
+
+        Self::load(pool)
+            .await?
+            .ok_or_else(|| OAuthError::ServerError("signing key was not stored".to_string()).into())
+    }
+
+    async fn load(pool: &SqlitePool) -> Result<Option<Self>, AppError> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT kid, private_key_pem FROM oauth_signing_keys ORDER BY created_at DESC, rowid DESC"
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let keys = rows
+            .iter()
+            .map(|(kid, pem)| load_key(kid, pem))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let jwks = json!({ "keys": keys.iter().map(|key| key.jwk.clone()).collect::<Vec<_>>() });
+        Ok(keys.into_iter().next().map(|current| Self {
+            current: Arc::new(current),
+            jwks: Arc::new(jwks),
+        }))
+    }
+
+    /// Key id of the key that signs new tokens.
+    pub fn kid(&self) -> &str {
+        &self.current.kid
+    }
+
+    /// The public keys, as served at the JWKS endpoint.
+    pub fn jwks(&self) -> &Value {
+        &self.jwks
+    }
+
+    /// Sign `claims` with the current key; `typ` tells token kinds apart.
+    pub fn sign<T: Serialize>(&self, typ: &str, claims: &T) -> Result<String, OAuthError> {
+        let mut header = Header::new(Algorithm::ES256);
+        header.typ = Some(typ.to_string());
+        header.kid = Some(self.current.kid.clone());
+        encode(&header, claims, &self.current.encoding_key)
+            .map_err(|e| OAuthError::ServerError(format!("cannot sign token: {}", e)))
+    }
+}
+
+fn load_key(kid: &str, pem: &str) -> Result<LoadedKey, OAuthError> {
+    let invalid = |e: &dyn std::fmt::Display| OAuthError::ServerError(format!("signing key {} is invalid: {}", kid, e));
+
+    let secret = p256::SecretKey::from_pkcs8_pem(pem).map_err(|e| invalid(&e))?;
+    let encoding_key = EncodingKey::from_ec_pem(pem.as_bytes()).map_err(|e| invalid(&e))?;
+
+    let mut jwk = serde_json::to_value(secret.public_key().to_jwk()).map_err(|e| invalid(&e))?;
+    jwk["kid"] = json!(kid);
+    jwk["alg"] = json!("ES256");
+    jwk["use"] = json!("sig");
+
+    Ok(LoadedKey {
+        kid: kid.to_string(),
+        encoding_key,
+        jwk,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use jsonwebtoken::{decode, decode_header, jwk::JwkSet, DecodingKey, Validation};
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn test_keys_persist_and_rotate() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
+        let db = Database::new(&database_url).await.unwrap();
+
+        let keys = SigningKeys::load_or_generate(db.pool()).await.unwrap();
+        let reloaded = SigningKeys::load_or_generate(db.pool()).await.unwrap();
+        assert_eq!(keys.kid(), reloaded.kid());
+
+        // A token signed before rotation still verifies against the published keys
+        let token = keys.sign("JWT", &json!({ "sub": "1", "exp": Utc::now().timestamp() + 60 })).unwrap();
+        let rotated = SigningKeys::rotate(db.pool()).await.unwrap();
+        assert_ne!(rotated.kid(), keys.kid());
+
+        let jwks: JwkSet = serde_json::from_value(rotated.jwks().clone()).unwrap();
+        assert_eq!(jwks.keys.len(), 2);
+        let kid = decode_header(&token).unwrap().kid.unwrap();
+        let key = DecodingKey::from_jwk(jwks.find(&kid).unwrap()).unwrap();
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        assert!(decode::<Value>(&token, &key, &validation).is_ok());
+    }
+}
@@ -0,0 +1,235 @@
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use super::OAuthServer;
+use crate::error::AppError;
+
+// How long a relying party gets to acknowledge a logout token
+pub(super) const LOGOUT_TIMEOUT_SECS: u64 = 5;
+
+// Logout tokens are only meant to be used on arrival
+const LOGOUT_TOKEN_TTL_SECONDS: i64 = 120;
+
+// Event that marks a JWT as an OpenID back-channel logout token
+const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
+/// A signed logout token on its way to one relying party.
+#[derive(Debug, Clone)]
+pub struct LogoutNotice {
+    pub client_name: String,
+    pub backchannel_logout_uri: String,
+    pub logout_token: String,
+}
+
+// Claims of an OpenID Connect back-channel logout token; never carries a nonce
+#[derive(Debug, Serialize)]
+struct LogoutTokenClaims {
+    iss: String,
+    aud: String,
+    iat: i64,
+    exp: i64,
+    jti: String,
+    sub: String,
+    sid: String,
+    events: Value,
+}
+
+impl OAuthServer {
+    /// Logout tokens for every client signed in under the ending session(s).
+    ///
+    /// With `session_id` only that sign-in ends; without it, all of the
+    /// user's do. The clients are forgotten, so each is told once.
+    pub async fn logout_notices(
+        &self,
+        user_id: i64,
+        session_id: Option<&str>,
+    ) -> Result<Vec<LogoutNotice>, AppError> {
+        let rows: Vec<(String, String, String, Option<String>)> = sqlx::query_as(
+            "SELECT s.session_id, c.client_id, c.name, c.backchannel_logout_uri
+             FROM oauth_client_sessions s JOIN oauth_clients c ON c.client_id = s.client_id
+             WHERE s.user_id = ? AND (? IS NULL OR s.session_id = ?)"
+        )
+        .bind(user_id)
+        .bind(session_id)
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await?;
+        sqlx::query("DELETE FROM oauth_client_sessions WHERE user_id = ? AND (? IS NULL OR session_id = ?)")
+            .bind(user_id)
+            .bind(session_id)
+            .bind(session_id)
+            .execute(&self.pool)
+            .await?;
+
+        let now = Utc::now();
+        let mut notices = Vec::new();
+        for (sid, client_id, client_name, backchannel_logout_uri) in rows {
+            // Clients without a logout endpoint only lose their tokens
+            let Some(backchannel_logout_uri) = backchannel_logout_uri else {
+                continue;
+            };
+
+            let logout_token = self.keys.sign(
+                "logout+jwt",
+                &LogoutTokenClaims {
+                    iss: self.issuer.clone(),
+                    aud: client_id,
+                    iat: now.timestamp(),
+                    exp: (now + Duration::seconds(LOGOUT_TOKEN_TTL_SECONDS)).timestamp(),
+                    jti: uuid::Uuid::new_v4().to_string(),
+                    sub: user_id.to_string(),
+                    sid,
+                    events: json!({ BACKCHANNEL_LOGOUT_EVENT: {} }),
+                },
+            )?;
+            notices.push(LogoutNotice {
+                client_name,
+                backchannel_logout_uri,
+                logout_token,
+            });
+        }
+
+        Ok(notices)
+    }
+
+    /// Tell relying parties that sessions ended, without waiting for them.
+    ///
+    /// Call this before the sessions are revoked, as revoking forgets which
+    /// clients they were used for.
+    pub async fn notify_logout(&self, user_id: i64, session_id: Option<&str>) -> Result<(), AppError> {
+        let notices = self.logout_notices(user_id, session_id).await?;
+        if notices.is_empty() {
+            return Ok(());
+        }
+
+        let http_client = self.http_client.clone();
+        tokio::spawn(async move {
+            for notice in notices {
+                deliver(&http_client, &notice).await;
+            }
+        });
+        Ok(())
+    }
+}
+
+// Post one logout token; failures are logged, as the user is signed out here regardless
+async fn deliver(http_client: &reqwest::Client, notice: &LogoutNotice) {
+    let result = http_client
+        .post(&notice.backchannel_logout_uri)
+        .header(reqwest::header::CACHE_CONTROL, "no-store")
+        .form(&[("logout_token", notice.logout_token.as_str())])
+        .send()
+        .await;
+
+    match result {
+        Ok(response) if response.status().is_success() => {
+            tracing::info!("Sent back-channel logout to {}", notice.client_name);
+        }
+        Ok(response) => {
+            tracing::warn!(
+                "{} answered back-channel logout with HTTP {}",
+                notice.client_name,
+                response.status()
+            );
+        }
+        Err(e) => tracing::warn!("Back-channel logout to {} failed: {}", notice.client_name, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{Database, UserRepository},
+        models::{CreateUser, SessionData},
+        oauth_server::{pkce_challenge, AuthorizationRequest},
+    };
+    use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn test_logout_notices_name_the_ended_session() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
+        let db = Database::new(&database_url).await.unwrap();
+        let users = UserRepository::new(db.pool().clone());
+        let user = users
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "12345".to_string(),
+                username: "octocat".to_string(),
+                email: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        let server = OAuthServer::new(db.pool().clone(), "https://sso.example.com").await.unwrap();
+
+        let redirect_uri = "https://app.example.com/callback".to_string();
+        let with_logout = server
+            .register_client("Wiki", vec![redirect_uri.clone()], Some("https://app.example.com/logout".to_string()), false)
+            .await
+            .unwrap()
+            .client;
+        let without_logout = server
+            .register_client("Chat", vec![redirect_uri.clone()], None, false)
+            .await
+            .unwrap()
+            .client;
+
+        // Sign in to both clients under two sessions
+        let verifier = "a-sufficiently-long-code-verifier-for-the-test";
+        let mut session_ids = Vec::new();
+        for _ in 0..2 {
+            let tracked = users.create_session(user.id, None, None).await.unwrap();
+            let session_data = SessionData {
+                user_id: user.id,
+                username: user.username.clone(),
+                provider: user.provider.clone(),
+                issued_at: tracked.created_at,
+                last_seen: tracked.last_seen,
+                roles: Vec::new(),
+                session_id: Some(tracked.id.clone()),
+            };
+            for client in [&with_logout, &without_logout] {
+                let request = AuthorizationRequest {
+                    response_type: Some("code".to_string()),
+                    client_id: Some(client.client_id.clone()),
+                    redirect_uri: Some(redirect_uri.clone()),
+                    scope: Some("openid".to_string()),
+                    code_challenge: Some(pkce_challenge(verifier)),
+                    code_challenge_method: Some("S256".to_string()),
+                    ..AuthorizationRequest::default()
+                };
+                let authorization = server.validate_authorization(&request).await.unwrap();
+                let code = server.issue_code(&authorization, &session_data).await.unwrap();
+                server.exchange_code(client, &code, Some(&redirect_uri), Some(verifier)).await.unwrap();
+            }
+            session_ids.push(tracked.id);
+        }
+
+        let notices = server.logout_notices(user.id, Some(&session_ids[0])).await.unwrap();
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].backchannel_logout_uri, "https://app.example.com/logout");
+        // Each client is told once
+        assert!(server.logout_notices(user.id, Some(&session_ids[0])).await.unwrap().is_empty());
+
+        let token = &notices[0].logout_token;
+        let header = decode_header(token).unwrap();
+        assert_eq!(header.typ.as_deref(), Some("logout+jwt"));
+        let jwks: JwkSet = serde_json::from_value(server.jwks().clone()).unwrap();
+        let key = DecodingKey::from_jwk(jwks.find(&header.kid.unwrap()).unwrap()).unwrap();
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.set_audience(&[&with_logout.client_id]);
+        validation.set_issuer(&["https://sso.example.com"]);
+        let claims = decode::<Value>(token, &key, &validation).unwrap().claims;
+        assert_eq!(claims["sub"], user.id.to_string());
+        assert_eq!(claims["sid"], session_ids[0].as_str());
+        assert!(claims["events"][BACKCHANNEL_LOGOUT_EVENT].is_object());
+        assert!(claims.get("nonce").is_none());
+
+        // Ending every session covers the rest
+        assert_eq!(server.logout_notices(user.id, None).await.unwrap().len(), 1);
+    }
+}
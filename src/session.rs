@@ -1,53 +1,213 @@
 use axum::{
     async_trait,
-    extract::{FromRequestParts, Request},
-    http::{request::Parts, StatusCode},
-    middleware::Next,
-    response::{IntoResponse, Response},
+    extract::{ConnectInfo, FromRef, FromRequestParts, Request},
+    http::{header, request::Parts, Method, Uri},
+    middleware::{self, Next},
+    response::Response,
+    Router,
 };
-use serde::{Deserialize, Serialize};
-use tower_sessions::{Session, SessionManagerLayer, MemoryStore};
-use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use std::{convert::Infallible, marker::PhantomData, net::SocketAddr};
+use sqlx::sqlite::SqlitePool;
+use tokio::task::JoinHandle;
+use tower_sessions::{Expiry, Session, SessionManagerLayer, MemoryStore};
 
 use crate::{
+    auth::sanitize_return_to,
+    config::{Config, ConfigError, SessionStoreKind},
+    database::UserRepository,
     error::{AppError, AuthError},
-    models::{SessionData, User},
+    models::{Role, SessionData, User},
 };
 
+pub mod keys;
+pub mod store;
+
+pub use keys::SessionKeys;
+pub use store::{AppSessionStore, SqliteSessionStore};
+
+// Name of the session cookie
+pub const SESSION_COOKIE_NAME: &str = "sso_session";
+
 // Session keys
 const USER_SESSION_KEY: &str = "user_session";
 const CSRF_TOKEN_KEY: &str = "csrf_token";
+const RETURN_TO_KEY: &str = "return_to";
+
+// Longest user agent kept with a session
+const MAX_USER_AGENT_LEN: usize = 256;
+
+// Minimum time between sliding renewals, so not every request writes the session
+const SESSION_RENEWAL_INTERVAL_SECS: i64 = 60;
+
+/// Idle and absolute lifetime of an authenticated session.
+///
+/// The idle timeout slides forward as the user keeps making requests; the
+/// absolute lifetime is counted from sign-in and never extends.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionPolicy {
+    pub idle_timeout: Duration,
+    pub absolute_lifetime: Duration,
+}
+
+impl SessionPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            idle_timeout: Duration::minutes(config.session_idle_timeout_minutes),
+            absolute_lifetime: Duration::hours(config.session_absolute_lifetime_hours),
+        }
+    }
+
+    pub fn check(&self, session_data: &SessionData, now: DateTime<Utc>) -> Result<(), AuthError> {
+        if now - session_data.issued_at > self.absolute_lifetime {
+            tracing::info!("Session for user {} reached its absolute lifetime", session_data.user_id);
+            return Err(AuthError::SessionExpired);
+        }
+
+        if now - session_data.last_seen > self.idle_timeout {
+            tracing::info!("Session for user {} timed out after inactivity", session_data.user_id);
+            return Err(AuthError::SessionExpired);
+        }
+
+        Ok(())
+    }
+
+    /// Whether a session issued and last seen at these times is still live.
+    pub fn is_live(&self, issued_at: DateTime<Utc>, last_seen: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        now - issued_at <= self.absolute_lifetime && now - last_seen <= self.idle_timeout
+    }
+
+    pub fn needs_renewal(&self, session_data: &SessionData, now: DateTime<Utc>) -> bool {
+        now - session_data.last_seen >= Duration::seconds(SESSION_RENEWAL_INTERVAL_SECS)
+    }
+}
+
+impl Default for SessionPolicy {
+    fn default() -> Self {
+        Self::from_config(&Config::default())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct SessionManager {
-    store: MemoryStore,
+    store: AppSessionStore,
+    keys: SessionKeys,
+    policy: SessionPolicy,
 }
 
 impl SessionManager {
-    pub fn new() -> Self {
-        Self {
-            store: MemoryStore::default(),
-        }
+    pub fn new(config: &Config, pool: SqlitePool) -> Result<Self, ConfigError> {
+        let keys = SessionKeys::from_config(config)?;
+
+        let store = match config.session_store {
+            SessionStoreKind::Sqlite => AppSessionStore::Sqlite(SqliteSessionStore::new(pool)),
+            SessionStoreKind::Memory => {
+                tracing::warn!("Using in-memory session store; sessions are lost on restart");
+                AppSessionStore::Memory(MemoryStore::default())
+            }
+        };
+
+        Ok(Self {
+            store,
+            keys,
+            policy: SessionPolicy::from_config(config),
+        })
+    }
+
+    pub fn store(&self) -> &AppSessionStore {
+        &self.store
+    }
+
+    pub fn policy(&self) -> SessionPolicy {
+        self.policy
+    }
+
+    /// Periodically delete expired sessions from the persistent store, along
+    /// with the tracking rows of sessions that can no longer be used.
+    pub fn spawn_expiry_cleanup(
+        &self,
+        user_repository: UserRepository,
+        period: std::time::Duration,
+    ) -> Option<JoinHandle<()>> {
+        let AppSessionStore::Sqlite(store) = self.store.clone() else {
+            return None;
+        };
+        let policy = self.policy;
+
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match store.delete_expired().await {
+                    Ok(0) => {}
+                    Ok(deleted) => tracing::debug!("Deleted {} expired sessions", deleted),
+                    Err(e) => tracing::error!("Failed to delete expired sessions: {}", e),
+                }
+
+                let now = Utc::now();
+                match user_repository
+                    .delete_stale_sessions(now - policy.idle_timeout, now - policy.absolute_lifetime)
+                    .await
+                {
+                    Ok(0) => {}
+                    Ok(deleted) => tracing::debug!("Forgot {} stale user sessions", deleted),
+                    Err(e) => tracing::error!("Failed to delete stale user sessions: {}", e),
+                }
+            }
+        }))
     }
 
-    pub fn layer(&self) -> SessionManagerLayer<MemoryStore> {
+    pub fn layer(&self) -> SessionManagerLayer<AppSessionStore> {
         SessionManagerLayer::new(self.store.clone())
             .with_secure(false) // Set to true in production with HTTPS
             .with_same_site(tower_sessions::cookie::SameSite::Lax) // More permissive for development
             .with_http_only(true) // Enable HttpOnly for security
-            .with_name("sso_session")
+            .with_name(SESSION_COOKIE_NAME)
             .with_path("/")
+            .with_expiry(Expiry::OnInactivity(tower_sessions::cookie::time::Duration::seconds(
+                self.policy.idle_timeout.num_seconds(),
+            )))
+    }
+
+    /// Add the session layer to `router`, signing (or encrypting) the cookie
+    /// with the current secret and accepting cookies sealed with previous ones.
+    pub fn apply<S>(&self, router: Router<S>) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let router = if self.keys.encrypted() {
+            router.layer(self.layer().with_private(self.keys.current().clone()))
+        } else {
+            router.layer(self.layer().with_signed(self.keys.current().clone()))
+        };
+
+        router.layer(middleware::map_request_with_state(
+            self.keys.clone(),
+            keys::upgrade_session_cookie,
+        ))
     }
 }
 
 // Session extension trait for easier session management
+#[allow(async_fn_in_trait)]
 pub trait SessionExt {
     async fn get_user_session(&self) -> Result<Option<SessionData>, AppError>;
-    async fn set_user_session(&self, user: &User) -> Result<(), AppError>;
+    async fn set_user_session(
+        &self,
+        user: &User,
+        provider: &str,
+        roles: Vec<Role>,
+        session_id: String,
+    ) -> Result<(), AppError>;
+    async fn refresh_user_session(&self, session_data: &SessionData) -> Result<(), AppError>;
     async fn clear_user_session(&self) -> Result<(), AppError>;
+    async fn destroy_session(&self) -> Result<(), AppError>;
     async fn get_csrf_token(&self) -> Result<Option<String>, AppError>;
     async fn set_csrf_token(&self, token: String) -> Result<(), AppError>;
     async fn clear_csrf_token(&self) -> Result<(), AppError>;
+    async fn get_return_to(&self) -> Result<Option<String>, AppError>;
+    async fn set_return_to(&self, path: String) -> Result<(), AppError>;
+    async fn clear_return_to(&self) -> Result<(), AppError>;
 }
 
 impl SessionExt for Session {
@@ -61,11 +221,29 @@ impl SessionExt for Session {
         }
     }
 
-    async fn set_user_session(&self, user: &User) -> Result<(), AppError> {
+    async fn set_user_session(
+        &self,
+        user: &User,
+        provider: &str,
+        roles: Vec<Role>,
+        session_id: String,
+    ) -> Result<(), AppError> {
+        // Issue a fresh session ID on login so a planted pre-login cookie is useless
+        if let Err(e) = self.cycle_id().await {
+            tracing::error!("Failed to cycle session ID: {}", e);
+            return Err(AppError::Auth(AuthError::InvalidSession));
+        }
+
+        let now = Utc::now();
         let session_data = SessionData {
             user_id: user.id,
             username: user.username.clone(),
-            provider: user.provider.clone(),
+            // The provider used for this sign-in, which may not be the one the account started with
+            provider: provider.to_string(),
+            roles,
+            session_id: Some(session_id),
+            issued_at: now,
+            last_seen: now,
         };
 
         match self.insert(USER_SESSION_KEY, session_data).await {
@@ -80,6 +258,16 @@ impl SessionExt for Session {
         }
     }
 
+    async fn refresh_user_session(&self, session_data: &SessionData) -> Result<(), AppError> {
+        match self.insert(USER_SESSION_KEY, session_data).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!("Failed to refresh user session: {}", e);
+                Err(AppError::Auth(AuthError::InvalidSession))
+            }
+        }
+    }
+
     async fn clear_user_session(&self) -> Result<(), AppError> {
         match self.remove::<SessionData>(USER_SESSION_KEY).await {
             Ok(_) => {
@@ -93,6 +281,19 @@ impl SessionExt for Session {
         }
     }
 
+    async fn destroy_session(&self) -> Result<(), AppError> {
+        match self.flush().await {
+            Ok(_) => {
+                tracing::info!("Session destroyed");
+                Ok(())
+            }
+            Err(e) => {
+                tracing::error!("Failed to destroy session: {}", e);
+                Err(AppError::Auth(AuthError::InvalidSession))
+            }
+        }
+    }
+
     async fn get_csrf_token(&self) -> Result<Option<String>, AppError> {
         match self.get::<String>(CSRF_TOKEN_KEY).await {
             Ok(token) => Ok(token),
@@ -122,9 +323,55 @@ impl SessionExt for Session {
             }
         }
     }
+
+    async fn get_return_to(&self) -> Result<Option<String>, AppError> {
+        match self.get::<String>(RETURN_TO_KEY).await {
+            Ok(path) => Ok(path),
+            Err(e) => {
+                tracing::error!("Failed to get return-to path: {}", e);
+                Err(AppError::Auth(AuthError::InvalidSession))
+            }
+        }
+    }
+
+    async fn set_return_to(&self, path: String) -> Result<(), AppError> {
+        match self.insert(RETURN_TO_KEY, path).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!("Failed to set return-to path: {}", e);
+                Err(AppError::Auth(AuthError::InvalidSession))
+            }
+        }
+    }
+
+    async fn clear_return_to(&self) -> Result<(), AppError> {
+        match self.remove::<String>(RETURN_TO_KEY).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!("Failed to clear return-to path: {}", e);
+                Err(AppError::Auth(AuthError::InvalidSession))
+            }
+        }
+    }
+}
+
+// Remember where an unauthenticated page view was headed, to return there after sign-in
+async fn remember_return_to(session: &Session, method: &Method, uri: &Uri) -> Result<(), AppError> {
+    // Only page views can be replayed by redirecting back to them
+    if method != Method::GET {
+        return Ok(());
+    }
+
+    let target = uri.path_and_query().map(|path| path.as_str()).unwrap_or("/");
+    if let Some(path) = sanitize_return_to(target) {
+        session.set_return_to(path).await?;
+    }
+    Ok(())
 }
 
-// Authenticated user extractor
+// Authenticated user extractor; enforces the session policy, ends revoked
+// sessions and those of disabled or deleted users, reloads the user's roles
+// and slides the idle timeout
 #[derive(Debug, Clone)]
 pub struct AuthenticatedUser {
     pub session_data: SessionData,
@@ -134,18 +381,179 @@ pub struct AuthenticatedUser {
 impl<S> FromRequestParts<S> for AuthenticatedUser
 where
     S: Send + Sync,
+    SessionPolicy: FromRef<S>,
+    UserRepository: FromRef<S>,
 {
     type Rejection = AppError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Self::authenticate(parts, state, true).await
+    }
+}
+
+impl AuthenticatedUser {
+    /// Like the extractor, but an anonymous request leaves the session untouched.
+    ///
+    /// For callers that are not pages, such as the JSON API: remembering them
+    /// as the place to return to would save a session for every anonymous call.
+    pub async fn from_request_parts_without_return_to<S>(parts: &mut Parts, state: &S) -> Result<Self, AppError>
+    where
+        S: Send + Sync,
+        SessionPolicy: FromRef<S>,
+        UserRepository: FromRef<S>,
+    {
+        Self::authenticate(parts, state, false).await
+    }
+
+    async fn authenticate<S>(parts: &mut Parts, state: &S, remember: bool) -> Result<Self, AppError>
+    where
+        S: Send + Sync,
+        SessionPolicy: FromRef<S>,
+        UserRepository: FromRef<S>,
+    {
         let session = Session::from_request_parts(parts, state)
             .await
             .map_err(|_| AppError::Auth(AuthError::InvalidSession))?;
 
-        match session.get_user_session().await? {
-            Some(session_data) => Ok(AuthenticatedUser { session_data }),
-            None => Err(AppError::Auth(AuthError::NotAuthenticated)),
+        let Some(mut session_data) = session.get_user_session().await? else {
+            if remember {
+                remember_return_to(&session, &parts.method, &parts.uri).await?;
+            }
+            return Err(AppError::Auth(AuthError::NotAuthenticated));
+        };
+
+        let policy = SessionPolicy::from_ref(state);
+        let now = Utc::now();
+
+        let users = UserRepository::from_ref(state);
+
+        if let Err(e) = policy.check(&session_data, now) {
+            if let Some(session_id) = &session_data.session_id {
+                users.revoke_session(session_data.user_id, session_id).await?;
+            }
+            session.clear_user_session().await?;
+            return Err(e.into());
+        }
+
+        // The account may have been disabled or deleted since sign-in
+        match users.find_by_id(session_data.user_id).await? {
+            Some(user) if !user.is_disabled() => {}
+            Some(user) => {
+                tracing::warn!("Ending session of disabled user ID {}", user.id);
+                session.destroy_session().await?;
+                return Err(AuthError::AccountDisabled(user.disabled_reason).into());
+            }
+            None => {
+                tracing::warn!("User ID {} no longer exists, ending session", session_data.user_id);
+                session.destroy_session().await?;
+                return Err(AuthError::AccountNotFound.into());
+            }
         }
+
+        // Revoked from another session or by an admin; sessions from before
+        // tracking have no row and end as well
+        let Some(session_id) = session_data.session_id.clone() else {
+            session.destroy_session().await?;
+            return Err(AuthError::SessionRevoked.into());
+        };
+        if !users.session_exists(session_data.user_id, &session_id).await? {
+            tracing::info!("Session of user ID {} was revoked", session_data.user_id);
+            session.destroy_session().await?;
+            return Err(AuthError::SessionRevoked.into());
+        }
+
+        // Roles may have been granted or revoked since sign-in
+        let roles = users.list_roles(session_data.user_id).await?;
+        let roles_changed = roles != session_data.roles;
+        session_data.roles = roles;
+
+        if policy.needs_renewal(&session_data, now) {
+            session_data.last_seen = now;
+            session.refresh_user_session(&session_data).await?;
+            users.touch_session(&session_id, now).await?;
+        } else if roles_changed {
+            session.refresh_user_session(&session_data).await?;
+        }
+
+        Ok(AuthenticatedUser { session_data })
+    }
+}
+
+/// A role that [`RequireRole`] checks for.
+pub trait RoleRequirement {
+    const ROLE: Role;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AdminRole;
+
+impl RoleRequirement for AdminRole {
+    const ROLE: Role = Role::Admin;
+}
+
+// Authenticated user extractor that also requires the user to hold role `R`
+#[derive(Debug, Clone)]
+pub struct RequireRole<R> {
+    pub user: AuthenticatedUser,
+    role: PhantomData<R>,
+}
+
+pub type RequireAdmin = RequireRole<AdminRole>;
+
+#[async_trait]
+impl<S, R> FromRequestParts<S> for RequireRole<R>
+where
+    S: Send + Sync,
+    SessionPolicy: FromRef<S>,
+    UserRepository: FromRef<S>,
+    R: RoleRequirement + Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthenticatedUser::from_request_parts(parts, state).await?;
+
+        if !user.session_data.has_role(R::ROLE) {
+            tracing::warn!(
+                "User {} lacks the {} role for {}",
+                user.session_data.user_id,
+                R::ROLE.as_str(),
+                parts.uri.path()
+            );
+            return Err(AppError::Auth(AuthError::Forbidden));
+        }
+
+        Ok(RequireRole { user, role: PhantomData })
+    }
+}
+
+// Where a request came from, recorded with each new session
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Only available when served with `into_make_service_with_connect_info`
+        let ip_address = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LEN).collect());
+
+        Ok(ClientInfo { ip_address, user_agent })
     }
 }
 
@@ -163,11 +571,21 @@ pub async fn auth_middleware(
         }
         None => {
             // User is not authenticated, redirect to login
+            remember_return_to(&session, request.method(), request.uri()).await?;
             Err(AppError::Auth(AuthError::NotAuthenticated))
         }
     }
 }
 
+// Admin-only middleware, for use with `middleware::from_fn_with_state`
+pub async fn require_admin_middleware(
+    _admin: RequireAdmin,
+    request: Request,
+    next: Next,
+) -> Response {
+    next.run(request).await
+}
+
 // Optional authentication middleware (doesn't redirect if not authenticated)
 pub async fn optional_auth_middleware(
     session: Session,
@@ -189,9 +607,317 @@ mod tests {
 
     #[tokio::test]
     async fn test_session_manager_creation() {
-        let session_manager = SessionManager::new();
-        let _layer = session_manager.layer();
-        // Test passes if no panic occurs
+        let pool = SqlitePool::connect_lazy("sqlite::memory:").unwrap();
+
+        let config = Config {
+            session_secret: "test_session_secret_key_for_testing_purposes".to_string(),
+            ..Config::default()
+        };
+
+        let session_manager = SessionManager::new(&config, pool.clone()).unwrap();
+        assert!(matches!(session_manager.store(), AppSessionStore::Sqlite(_)));
+        let _router: Router = session_manager.apply(Router::new());
+
+        let config = Config {
+            session_store: SessionStoreKind::Memory,
+            ..config
+        };
+        let session_manager = SessionManager::new(&config, pool).unwrap();
+        assert!(matches!(session_manager.store(), AppSessionStore::Memory(_)));
+        let user_repository = UserRepository::new(SqlitePool::connect_lazy("sqlite::memory:").unwrap());
+        assert!(session_manager
+            .spawn_expiry_cleanup(user_repository, std::time::Duration::from_secs(60))
+            .is_none());
+
+        // Secrets too short to derive a key from are rejected
+        let config = Config {
+            session_secret: "short".to_string(),
+            ..Config::default()
+        };
+        assert!(SessionManager::new(&config, SqlitePool::connect_lazy("sqlite::memory:").unwrap()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_session_survives_secret_rotation() {
+        use axum::routing::get;
+        use axum_test::TestServer;
+
+        const OLD_SECRET: &str = "old_session_secret_for_testing_purposes_only";
+        const NEW_SECRET: &str = "new_session_secret_for_testing_purposes_only";
+
+        async fn visits(session: Session) -> String {
+            let count = session.get::<u32>("visits").await.unwrap().unwrap_or(0) + 1;
+            session.insert("visits", count).await.unwrap();
+            count.to_string()
+        }
+
+        // Servers share one store, as a restarted process shares the database
+        let pool = SqlitePool::connect_lazy("sqlite::memory:").unwrap();
+        let store = MemoryStore::default();
+        let server = |secret: &str, previous: &[&str]| {
+            let config = Config {
+                session_secret: secret.to_string(),
+                previous_session_secrets: previous.iter().map(|s| s.to_string()).collect(),
+                ..Config::default()
+            };
+            let mut manager = SessionManager::new(&config, pool.clone()).unwrap();
+            manager.store = AppSessionStore::Memory(store.clone());
+            let mut server = TestServer::new(manager.apply(Router::new().route("/", get(visits)))).unwrap();
+            server.do_save_cookies();
+            server
+        };
+
+        let old_server = server(OLD_SECRET, &[]);
+        assert_eq!(old_server.get("/").await.text(), "1");
+        let cookie = old_server.get("/").await.cookie(SESSION_COOKIE_NAME);
+
+        // The old cookie is still accepted when the old secret is listed as previous
+        let rotated = server(NEW_SECRET, &[OLD_SECRET]);
+        assert_eq!(rotated.get("/").add_cookie(cookie.clone()).await.text(), "3");
+
+        // Without it the session is lost
+        let rotated = server(NEW_SECRET, &[]);
+        assert_eq!(rotated.get("/").add_cookie(cookie).await.text(), "1");
+    }
+
+    // Tracking row of the sessions built by `session_data`
+    const TEST_SESSION_ID: &str = "test-session";
+
+    fn session_data(issued_ago: Duration, seen_ago: Duration) -> SessionData {
+        let now = Utc::now();
+        SessionData {
+            user_id: 1,
+            username: "testuser".to_string(),
+            provider: "github".to_string(),
+            roles: Vec::new(),
+            session_id: Some(TEST_SESSION_ID.to_string()),
+            issued_at: now - issued_ago,
+            last_seen: now - seen_ago,
+        }
+    }
+
+    #[test]
+    fn test_session_policy_check() {
+        let policy = SessionPolicy {
+            idle_timeout: Duration::minutes(30),
+            absolute_lifetime: Duration::hours(12),
+        };
+        let now = Utc::now();
+
+        let fresh = session_data(Duration::hours(1), Duration::minutes(5));
+        assert!(policy.check(&fresh, now).is_ok());
+        assert!(policy.needs_renewal(&fresh, now));
+
+        let just_seen = session_data(Duration::hours(1), Duration::zero());
+        assert!(!policy.needs_renewal(&just_seen, now));
+
+        let idle = session_data(Duration::hours(1), Duration::minutes(31));
+        assert!(matches!(policy.check(&idle, now), Err(AuthError::SessionExpired)));
+
+        // Activity does not extend the absolute lifetime
+        let too_old = session_data(Duration::hours(13), Duration::zero());
+        assert!(matches!(policy.check(&too_old, now), Err(AuthError::SessionExpired)));
+    }
+
+    #[test]
+    fn test_session_data_without_timestamps_is_expired() {
+        let legacy: SessionData =
+            serde_json::from_str(r#"{"user_id":1,"username":"testuser","provider":"github"}"#).unwrap();
+        assert!(SessionPolicy::default().check(&legacy, Utc::now()).is_err());
+    }
+
+    // Router state for extractor tests
+    #[derive(Clone, FromRef)]
+    struct ExtractorState {
+        policy: SessionPolicy,
+        users: UserRepository,
+    }
+
+    // Serve the routes with a database holding the user and tracked session of `session_data`
+    async fn extractor_test_server(
+        routes: impl FnOnce(ExtractorState) -> Router<ExtractorState>,
+    ) -> (axum_test::TestServer, UserRepository, tempfile::NamedTempFile) {
+        use crate::{database::Database, models::CreateUser};
+
+        let temp_file = tempfile::NamedTempFile::new().unwrap();
+        let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
+        let database = Database::new(&database_url).await.unwrap();
+        let users = UserRepository::new(database.pool().clone());
+        users
+            .create_user(CreateUser {
+                provider: "github".to_string(),
+                provider_id: "12345".to_string(),
+                username: "testuser".to_string(),
+                email: None,
+                avatar_url: None,
+            })
+            .await
+            .unwrap();
+        track_test_session(database.pool()).await;
+
+        let config = Config {
+            session_secret: "test_session_secret_key_for_testing_purposes".to_string(),
+            session_store: SessionStoreKind::Memory,
+            ..Config::default()
+        };
+        let manager = SessionManager::new(&config, database.pool().clone()).unwrap();
+        let state = ExtractorState {
+            policy: manager.policy(),
+            users: users.clone(),
+        };
+        let app = routes(state.clone()).with_state(state);
+        let mut server = axum_test::TestServer::new(manager.apply(app)).unwrap();
+        server.do_save_cookies();
+
+        (server, users, temp_file)
+    }
+
+    async fn track_test_session(pool: &SqlitePool) {
+        let now = Utc::now().to_rfc3339();
+        sqlx::query("INSERT OR IGNORE INTO user_sessions (id, user_id, created_at, last_seen) VALUES (?, 1, ?, ?)")
+            .bind(TEST_SESSION_ID)
+            .bind(&now)
+            .bind(&now)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_authenticated_user_enforces_policy_and_slides() {
+        use axum::{extract::Path, routing::get};
+
+        // Store a user session that was issued and last seen the given minutes ago
+        async fn seed(session: Session, Path((issued, seen)): Path<(i64, i64)>) {
+            let data = session_data(Duration::minutes(issued), Duration::minutes(seen));
+            session.refresh_user_session(&data).await.unwrap();
+        }
+
+        async fn me(user: AuthenticatedUser) -> String {
+            user.session_data.last_seen.to_rfc3339()
+        }
+
+        let (server, _users, _db_file) = extractor_test_server(|_| {
+            Router::new()
+                .route("/seed/:issued/:seen", get(seed))
+                .route("/me", get(me))
+        })
+        .await;
+
+        // An active session slides its last_seen forward
+        server.get("/seed/60/5").await;
+        let first = server.get("/me").await.text();
+        assert_eq!(server.get("/me").await.text(), first);
+        assert!(Utc::now() - DateTime::parse_from_rfc3339(&first).unwrap().with_timezone(&Utc) < Duration::minutes(1));
+
+        // An idle session is expired and cleared
+        server.get("/seed/60/45").await;
+        let response = server.get("/me").await;
+        let location = response.headers().get("location").unwrap().to_str().unwrap();
+        assert!(location.contains("session%20has%20expired"));
+        assert_eq!(server.get("/me").await.headers().get("location").unwrap(), "/login");
+    }
+
+    #[tokio::test]
+    async fn test_authenticated_user_ends_sessions_of_disabled_users() {
+        use axum::routing::get;
+
+        async fn seed(session: Session) {
+            let data = session_data(Duration::zero(), Duration::zero());
+            session.refresh_user_session(&data).await.unwrap();
+        }
+
+        async fn me(user: AuthenticatedUser) -> String {
+            user.session_data.username
+        }
+
+        let (server, users, _db_file) =
+            extractor_test_server(|_| Router::new().route("/seed", get(seed)).route("/me", get(me))).await;
+
+        server.get("/seed").await;
+        assert_eq!(server.get("/me").await.text(), "testuser");
+
+        users.disable_user(1, Some("Left the company")).await.unwrap();
+        let response = server.get("/me").await;
+        let location = response.headers().get("location").unwrap().to_str().unwrap();
+        assert!(location.contains("disabled%3A%20Left%20the%20company"));
+
+        // The session is gone even once the account is enabled again
+        users.enable_user(1).await.unwrap();
+        assert_eq!(server.get("/me").await.headers().get("location").unwrap(), "/login");
+
+        // A deleted account ends the session as well
+        server.get("/seed").await;
+        users.delete_user(1).await.unwrap();
+        let response = server.get("/me").await;
+        assert!(response.headers().get("location").unwrap().to_str().unwrap().contains("no%20longer%20exists"));
+    }
+
+    #[tokio::test]
+    async fn test_authenticated_user_ends_revoked_sessions() {
+        use axum::routing::get;
+
+        async fn seed(session: Session) {
+            let data = session_data(Duration::zero(), Duration::zero());
+            session.refresh_user_session(&data).await.unwrap();
+        }
+
+        async fn me(user: AuthenticatedUser) -> String {
+            user.session_data.username
+        }
+
+        let (server, users, _db_file) =
+            extractor_test_server(|_| Router::new().route("/seed", get(seed)).route("/me", get(me))).await;
+
+        server.get("/seed").await;
+        assert_eq!(server.get("/me").await.text(), "testuser");
+
+        assert!(users.revoke_session(1, TEST_SESSION_ID).await.unwrap());
+        let response = server.get("/me").await;
+        assert!(response.headers().get("location").unwrap().to_str().unwrap().contains("signed%20out"));
+        assert_eq!(server.get("/me").await.headers().get("location").unwrap(), "/login");
+    }
+
+    #[tokio::test]
+    async fn test_require_admin_checks_current_roles() {
+        use axum::routing::get;
+
+        async fn seed(session: Session) {
+            let data = session_data(Duration::zero(), Duration::zero());
+            session.refresh_user_session(&data).await.unwrap();
+        }
+
+        async fn admin_page(admin: RequireAdmin) -> String {
+            admin.user.session_data.username
+        }
+
+        let (server, users, _db_file) = extractor_test_server(|state| {
+            let guarded = Router::new()
+                .route("/guarded", get(|| async { "ok" }))
+                .route_layer(middleware::from_fn_with_state(state, require_admin_middleware));
+            Router::new()
+                .route("/seed", get(seed))
+                .route("/admin", get(admin_page))
+                .merge(guarded)
+        })
+        .await;
+
+        // Anonymous users are sent to login rather than refused
+        assert_eq!(server.get("/admin").await.headers().get("location").unwrap(), "/login");
+
+        server.get("/seed").await;
+        server.get("/admin").expect_failure().await.assert_status(axum::http::StatusCode::FORBIDDEN);
+        server.get("/guarded").expect_failure().await.assert_status(axum::http::StatusCode::FORBIDDEN);
+
+        // A role granted after sign-in applies to the live session
+        users.grant_role(1, Role::Admin).await.unwrap();
+        assert_eq!(server.get("/admin").await.text(), "testuser");
+        assert_eq!(server.get("/guarded").await.text(), "ok");
+
+        // And so does revoking it
+        users.revoke_role(1, Role::Admin).await.unwrap();
+        server.get("/admin").expect_failure().await.assert_status(axum::http::StatusCode::FORBIDDEN);
+        server.get("/guarded").expect_failure().await.assert_status(axum::http::StatusCode::FORBIDDEN);
     }
 
     #[tokio::test]
@@ -200,6 +926,10 @@ mod tests {
             user_id: 1,
             username: "testuser".to_string(),
             provider: "github".to_string(),
+            roles: vec![Role::Admin],
+            session_id: Some(TEST_SESSION_ID.to_string()),
+            issued_at: Utc::now(),
+            last_seen: Utc::now(),
         };
 
         // Test serialization
@@ -212,21 +942,49 @@ mod tests {
         assert_eq!(deserialized.user_id, 1);
         assert_eq!(deserialized.username, "testuser");
         assert_eq!(deserialized.provider, "github");
+        assert!(deserialized.has_role(Role::Admin));
     }
 
     #[tokio::test]
     async fn test_session_ext_methods() {
-        use tower_sessions::SessionManagerLayer;
-        
-        let store = MemoryStore::default();
-        let session_layer = SessionManagerLayer::new(store);
-        
-        // Create a mock session for testing
-        // Note: This is a simplified test - in practice, sessions are created by the middleware
-        let session_id = Uuid::new_v4().to_string();
-        
-        // Test would require more complex setup with actual HTTP request/response cycle
-        // For now, we'll test the data structures
-        assert!(true); // Placeholder test
-    }
-}
\ No newline at end of file
+        use std::sync::Arc;
+
+        let store = Arc::new(MemoryStore::default());
+        let session = Session::new(None, store, None);
+
+        let user = User {
+            id: 7,
+            provider: "github".to_string(),
+            provider_id: "12345".to_string(),
+            username: "testuser".to_string(),
+            email: None,
+            avatar_url: None,
+            created_at: Utc::now(),
+            last_login: Utc::now(),
+            username_edited_at: None,
+            disabled_at: None,
+            disabled_reason: None,
+        };
+
+        // User session round trip
+        assert!(session.get_user_session().await.unwrap().is_none());
+        session
+            .set_user_session(&user, "microsoft", vec![Role::Admin], "session-7".to_string())
+            .await
+            .unwrap();
+        let session_data = session.get_user_session().await.unwrap().unwrap();
+        assert_eq!(session_data.user_id, 7);
+        assert_eq!(session_data.username, "testuser");
+        assert_eq!(session_data.provider, "microsoft");
+        assert_eq!(session_data.roles, vec![Role::Admin]);
+        assert_eq!(session_data.session_id.as_deref(), Some("session-7"));
+        session.clear_user_session().await.unwrap();
+        assert!(session.get_user_session().await.unwrap().is_none());
+
+        // CSRF token round trip
+        session.set_csrf_token("token".to_string()).await.unwrap();
+        assert_eq!(session.get_csrf_token().await.unwrap(), Some("token".to_string()));
+        session.clear_csrf_token().await.unwrap();
+        assert!(session.get_csrf_token().await.unwrap().is_none());
+    }
+}
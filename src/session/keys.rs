@@ -0,0 +1,231 @@
+use axum::{
+    extract::{Request, State},
+    http::{header::COOKIE, HeaderMap, HeaderValue},
+};
+use tower_sessions::cookie::{Cookie, CookieJar, Key};
+
+use crate::config::{Config, ConfigError, MIN_SESSION_SECRET_LEN};
+
+/// Cookie keys derived from `SESSION_SECRET` and any previous secrets.
+///
+/// The current key seals every cookie the app issues. Previous keys are only
+/// used to recognise cookies issued before a rotation, which are re-sealed with
+/// the current key on the way in so the session survives.
+#[derive(Clone)]
+pub struct SessionKeys {
+    current: Key,
+    previous: Vec<Key>,
+    encrypted: bool,
+}
+
+impl std::fmt::Debug for SessionKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionKeys")
+            .field("previous", &self.previous.len())
+            .field("encrypted", &self.encrypted)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionKeys {
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let derive = |secret: &String| {
+            if secret.len() < MIN_SESSION_SECRET_LEN {
+                return Err(ConfigError::Invalid(format!(
+                    "session secrets must be at least {} bytes long",
+                    MIN_SESSION_SECRET_LEN
+                )));
+            }
+            Ok(Key::derive_from(secret.as_bytes()))
+        };
+
+        Ok(Self {
+            current: derive(&config.session_secret)?,
+            previous: config
+                .previous_session_secrets
+                .iter()
+                .map(derive)
+                .collect::<Result<_, _>>()?,
+            encrypted: config.session_cookie_encrypted,
+        })
+    }
+
+    pub fn current(&self) -> &Key {
+        &self.current
+    }
+
+    pub fn encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    // Verify (and decrypt) a sealed cookie value with the given key
+    fn open(&self, key: &Key, name: &str, value: &str) -> Option<String> {
+        let mut jar = CookieJar::new();
+        jar.add_original(Cookie::new(name.to_string(), value.to_string()));
+
+        let cookie = if self.encrypted {
+            jar.private(key).get(name)
+        } else {
+            jar.signed(key).get(name)
+        };
+
+        cookie.map(|cookie| cookie.value().to_string())
+    }
+
+    // Seal a plain cookie value with the current key
+    fn seal(&self, name: &str, value: &str) -> Option<String> {
+        let mut jar = CookieJar::new();
+        let cookie = Cookie::new(name.to_string(), value.to_string());
+
+        if self.encrypted {
+            jar.private_mut(&self.current).add(cookie);
+        } else {
+            jar.signed_mut(&self.current).add(cookie);
+        }
+
+        jar.get(name).map(|cookie| cookie.value().to_string())
+    }
+
+    // Re-seal a cookie value issued under a previous key; `None` if no change is needed
+    fn reseal(&self, name: &str, value: &str) -> Option<String> {
+        if self.previous.is_empty() || self.open(&self.current, name, value).is_some() {
+            return None;
+        }
+
+        let plain = self
+            .previous
+            .iter()
+            .find_map(|key| self.open(key, name, value))?;
+
+        self.seal(name, &plain)
+    }
+
+    /// Rewrite the named cookie in the request headers if it was sealed with a previous key.
+    pub fn upgrade_request_cookies(&self, headers: &mut HeaderMap, name: &str) {
+        let mut changed = false;
+        let mut rewritten = Vec::new();
+
+        for header in headers.get_all(COOKIE) {
+            let Ok(header) = header.to_str() else {
+                return;
+            };
+
+            let pairs = header
+                .split(';')
+                .map(|pair| {
+                    let pair = pair.trim();
+                    match pair.split_once('=') {
+                        Some((cookie_name, value)) if cookie_name == name => {
+                            match self.reseal(name, value) {
+                                Some(resealed) => {
+                                    changed = true;
+                                    format!("{}={}", name, resealed)
+                                }
+                                None => pair.to_string(),
+                            }
+                        }
+                        _ => pair.to_string(),
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            rewritten.push(pairs.join("; "));
+        }
+
+        if !changed {
+            return;
+        }
+
+        tracing::debug!("Re-sealed session cookie issued under a previous session secret");
+        headers.remove(COOKIE);
+        for header in rewritten {
+            if let Ok(value) = HeaderValue::from_str(&header) {
+                headers.append(COOKIE, value);
+            }
+        }
+    }
+}
+
+// Request mapper that upgrades cookies sealed with a rotated-out secret
+pub async fn upgrade_session_cookie(
+    State(keys): State<SessionKeys>,
+    mut request: Request,
+) -> Request {
+    keys.upgrade_request_cookies(request.headers_mut(), super::SESSION_COOKIE_NAME);
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_SECRET: &str = "old_session_secret_for_testing_purposes_only";
+    const NEW_SECRET: &str = "new_session_secret_for_testing_purposes_only";
+
+    fn keys(current: &str, previous: &[&str], encrypted: bool) -> SessionKeys {
+        SessionKeys::from_config(&Config {
+            session_secret: current.to_string(),
+            previous_session_secrets: previous.iter().map(|s| s.to_string()).collect(),
+            session_cookie_encrypted: encrypted,
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    fn cookie_header(headers: &HeaderMap) -> &str {
+        headers.get(COOKIE).unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn test_short_secret_is_rejected() {
+        let result = SessionKeys::from_config(&Config {
+            session_secret: "short".to_string(),
+            ..Config::default()
+        });
+        assert!(matches!(result, Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_cookie_from_previous_secret_is_resealed() {
+        for encrypted in [false, true] {
+            let old_keys = keys(OLD_SECRET, &[], encrypted);
+            let new_keys = keys(NEW_SECRET, &[OLD_SECRET], encrypted);
+            let old_value = old_keys.seal("sso_session", "session-id").unwrap();
+
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                COOKIE,
+                HeaderValue::from_str(&format!("other=1; sso_session={}", old_value)).unwrap(),
+            );
+            new_keys.upgrade_request_cookies(&mut headers, "sso_session");
+
+            let header = cookie_header(&headers);
+            assert!(header.starts_with("other=1; sso_session="));
+            let new_value = header.trim_start_matches("other=1; sso_session=");
+            assert_ne!(new_value, old_value);
+            assert_eq!(
+                new_keys.open(new_keys.current(), "sso_session", new_value),
+                Some("session-id".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_current_and_unknown_cookies_are_untouched() {
+        let new_keys = keys(NEW_SECRET, &[OLD_SECRET], false);
+        let current_value = new_keys.seal("sso_session", "session-id").unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_str(&format!("sso_session={}", current_value)).unwrap(),
+        );
+        new_keys.upgrade_request_cookies(&mut headers, "sso_session");
+        assert_eq!(cookie_header(&headers), format!("sso_session={}", current_value));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_static("sso_session=forged"));
+        new_keys.upgrade_request_cookies(&mut headers, "sso_session");
+        assert_eq!(cookie_header(&headers), "sso_session=forged");
+    }
+}
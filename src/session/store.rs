@@ -0,0 +1,233 @@
+use axum::async_trait;
+use sqlx::{sqlite::SqlitePool, Row};
+use tower_sessions::{
+    cookie::time::OffsetDateTime,
+    session::{Id, Record},
+    session_store, MemoryStore, SessionStore,
+};
+
+/// Session store persisted in the application's SQLite database.
+///
+/// Records live in the `sessions` table so logins survive restarts and can be
+/// shared by several processes pointing at the same database file.
+#[derive(Debug, Clone)]
+pub struct SqliteSessionStore {
+    pool: SqlitePool,
+}
+
+impl SqliteSessionStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn delete_expired(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM sessions WHERE expiry_date <= ?")
+            .bind(OffsetDateTime::now_utc().unix_timestamp())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+fn backend_error(e: sqlx::Error) -> session_store::Error {
+    session_store::Error::Backend(e.to_string())
+}
+
+fn encode_data(record: &Record) -> session_store::Result<String> {
+    serde_json::to_string(&record.data).map_err(|e| session_store::Error::Encode(e.to_string()))
+}
+
+#[async_trait]
+impl SessionStore for SqliteSessionStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        let data = encode_data(record)?;
+
+        // Retry with a fresh ID in the unlikely event of a collision
+        loop {
+            let result = sqlx::query(
+                "INSERT OR IGNORE INTO sessions (id, data, expiry_date) VALUES (?, ?, ?)"
+            )
+            .bind(record.id.to_string())
+            .bind(&data)
+            .bind(record.expiry_date.unix_timestamp())
+            .execute(&self.pool)
+            .await
+            .map_err(backend_error)?;
+
+            if result.rows_affected() == 1 {
+                return Ok(());
+            }
+
+            record.id = Id::default();
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        sqlx::query(
+            "INSERT INTO sessions (id, data, expiry_date) VALUES (?, ?, ?)
+             ON CONFLICT(id) DO UPDATE SET data = excluded.data, expiry_date = excluded.expiry_date"
+        )
+        .bind(record.id.to_string())
+        .bind(encode_data(record)?)
+        .bind(record.expiry_date.unix_timestamp())
+        .execute(&self.pool)
+        .await
+        .map_err(backend_error)?;
+
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let row = sqlx::query("SELECT data, expiry_date FROM sessions WHERE id = ? AND expiry_date > ?")
+            .bind(session_id.to_string())
+            .bind(OffsetDateTime::now_utc().unix_timestamp())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(backend_error)?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let data: String = row.try_get("data").map_err(backend_error)?;
+        let expiry_date: i64 = row.try_get("expiry_date").map_err(backend_error)?;
+
+        Ok(Some(Record {
+            id: *session_id,
+            data: serde_json::from_str(&data)
+                .map_err(|e| session_store::Error::Decode(e.to_string()))?,
+            expiry_date: OffsetDateTime::from_unix_timestamp(expiry_date)
+                .map_err(|e| session_store::Error::Decode(e.to_string()))?,
+        }))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        sqlx::query("DELETE FROM sessions WHERE id = ?")
+            .bind(session_id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(backend_error)?;
+
+        Ok(())
+    }
+}
+
+// Session store selected at startup from `Config::session_store`
+#[derive(Debug, Clone)]
+pub enum AppSessionStore {
+    Memory(MemoryStore),
+    Sqlite(SqliteSessionStore),
+}
+
+#[async_trait]
+impl SessionStore for AppSessionStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        match self {
+            AppSessionStore::Memory(store) => store.create(record).await,
+            AppSessionStore::Sqlite(store) => store.create(record).await,
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        match self {
+            AppSessionStore::Memory(store) => store.save(record).await,
+            AppSessionStore::Sqlite(store) => store.save(record).await,
+        }
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        match self {
+            AppSessionStore::Memory(store) => store.load(session_id).await,
+            AppSessionStore::Sqlite(store) => store.load(session_id).await,
+        }
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        match self {
+            AppSessionStore::Memory(store) => store.delete(session_id).await,
+            AppSessionStore::Sqlite(store) => store.delete(session_id).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use tempfile::NamedTempFile;
+    use tower_sessions::cookie::time::Duration;
+
+    async fn setup_test_store() -> (SqliteSessionStore, NamedTempFile) {
+        let temp_file = NamedTempFile::new().unwrap();
+        let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
+        let db = Database::new(&database_url).await.unwrap();
+        (SqliteSessionStore::new(db.pool().clone()), temp_file)
+    }
+
+    fn test_record(expiry_date: OffsetDateTime) -> Record {
+        let mut data = std::collections::HashMap::new();
+        data.insert("user".to_string(), serde_json::json!({ "user_id": 1 }));
+        Record {
+            id: Id::default(),
+            data,
+            expiry_date,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_load_and_delete() {
+        let (store, _db_file) = setup_test_store().await;
+        let mut record = test_record(OffsetDateTime::now_utc() + Duration::hours(1));
+
+        store.create(&mut record).await.unwrap();
+        let loaded = store.load(&record.id).await.unwrap().unwrap();
+        assert_eq!(loaded.data, record.data);
+        assert_eq!(loaded.expiry_date.unix_timestamp(), record.expiry_date.unix_timestamp());
+
+        store.delete(&record.id).await.unwrap();
+        assert!(store.load(&record.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_save_updates_existing_record() {
+        let (store, _db_file) = setup_test_store().await;
+        let mut record = test_record(OffsetDateTime::now_utc() + Duration::hours(1));
+        store.create(&mut record).await.unwrap();
+
+        record
+            .data
+            .insert("user".to_string(), serde_json::json!({ "user_id": 2 }));
+        store.save(&record).await.unwrap();
+
+        let loaded = store.load(&record.id).await.unwrap().unwrap();
+        assert_eq!(loaded.data["user"]["user_id"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_expired_records_are_not_loaded_and_get_cleaned_up() {
+        let (store, _db_file) = setup_test_store().await;
+        let mut expired = test_record(OffsetDateTime::now_utc() - Duration::minutes(1));
+        let mut active = test_record(OffsetDateTime::now_utc() + Duration::hours(1));
+        store.create(&mut expired).await.unwrap();
+        store.create(&mut active).await.unwrap();
+
+        assert!(store.load(&expired.id).await.unwrap().is_none());
+        assert_eq!(store.delete_expired().await.unwrap(), 1);
+        assert!(store.load(&active.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_records_survive_a_new_store_instance() {
+        let (store, db_file) = setup_test_store().await;
+        let mut record = test_record(OffsetDateTime::now_utc() + Duration::hours(1));
+        store.create(&mut record).await.unwrap();
+
+        // Simulate a restart by opening the same database again
+        let database_url = format!("sqlite:{}", db_file.path().to_str().unwrap());
+        let db = Database::new(&database_url).await.unwrap();
+        let restarted = SqliteSessionStore::new(db.pool().clone());
+
+        assert!(restarted.load(&record.id).await.unwrap().is_some());
+    }
+}
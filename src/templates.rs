@@ -1,14 +1,46 @@
 use askama::Template;
 
+use chrono::{DateTime, Utc};
+
+use crate::{
+    api_tokens::{ApiScope, ApiToken, CreatedApiToken, TOKEN_LIFETIME_DAYS},
+    audit::AuditEvent,
+    models::{Identity, User, UserSession, UserSort},
+    oauth_server::{AuthorizationRequest, OAuthClient, RegisteredClient},
+};
+
+// Providers with their own branded button on the login page
+const BRANDED_PROVIDERS: [&str; 2] = ["microsoft", "github"];
+
+// Provider offered as a sign-in or account-link button
+#[derive(Debug, Clone)]
+pub struct ProviderOption {
+    pub slug: String,
+    pub display_name: String,
+}
+
 #[derive(Template)]
 #[template(path = "login.html")]
 pub struct LoginTemplate {
     pub error: Option<String>,
+    pub extra_providers: Vec<ProviderOption>,
 }
 
 impl LoginTemplate {
     pub fn new(error: Option<String>) -> Self {
-        Self { error }
+        Self {
+            error,
+            extra_providers: Vec::new(),
+        }
+    }
+
+    // Configured providers without a branded button get a generic one
+    pub fn with_providers(mut self, providers: Vec<ProviderOption>) -> Self {
+        self.extra_providers = providers
+            .into_iter()
+            .filter(|provider| !BRANDED_PROVIDERS.contains(&provider.slug.as_str()))
+            .collect();
+        self
     }
 }
 
@@ -17,11 +49,216 @@ impl LoginTemplate {
 pub struct DashboardTemplate {
     pub username: String,
     pub email: Option<String>,
+    pub avatar_url: Option<String>,
     pub provider: String,
+    pub created_at: DateTime<Utc>,
+    pub last_login: DateTime<Utc>,
+    pub identities: Vec<Identity>,
+    pub providers: Vec<ProviderOption>,
+    pub error: Option<String>,
+    pub is_admin: bool,
+    pub sessions: Vec<UserSession>,
+    pub current_session_id: Option<String>,
+    pub api_tokens: Vec<ApiToken>,
+    // Set right after creation, the only time the token is shown
+    pub created_token: Option<CreatedApiToken>,
 }
 
 impl DashboardTemplate {
-    pub fn new(username: String, email: Option<String>, provider: String) -> Self {
-        Self { username, email, provider }
+    pub fn new(user: User) -> Self {
+        Self {
+            username: user.username,
+            email: user.email,
+            avatar_url: user.avatar_url,
+            provider: user.provider,
+            created_at: user.created_at,
+            last_login: user.last_login,
+            identities: Vec::new(),
+            providers: Vec::new(),
+            error: None,
+            is_admin: false,
+            sessions: Vec::new(),
+            current_session_id: None,
+            api_tokens: Vec::new(),
+            created_token: None,
+        }
     }
-}
\ No newline at end of file
+
+    pub fn with_identities(mut self, identities: Vec<Identity>, providers: Vec<ProviderOption>) -> Self {
+        self.identities = identities;
+        self.providers = providers;
+        self
+    }
+
+    pub fn with_sessions(mut self, sessions: Vec<UserSession>, current_session_id: Option<String>) -> Self {
+        self.sessions = sessions;
+        self.current_session_id = current_session_id;
+        self
+    }
+
+    pub fn is_current_session(&self, session: &UserSession) -> bool {
+        self.current_session_id.as_deref() == Some(session.id.as_str())
+    }
+
+    pub fn with_api_tokens(mut self, api_tokens: Vec<ApiToken>, created_token: Option<CreatedApiToken>) -> Self {
+        self.api_tokens = api_tokens;
+        self.created_token = created_token;
+        self
+    }
+
+    // The admin scope is only offered to admins
+    pub fn token_scopes(&self) -> Vec<ApiScope> {
+        ApiScope::ALL
+            .into_iter()
+            .filter(|scope| *scope != ApiScope::Admin || self.is_admin)
+            .collect()
+    }
+
+    pub fn token_lifetimes(&self) -> &'static [i64] {
+        TOKEN_LIFETIME_DAYS
+    }
+
+    pub fn with_error(mut self, error: Option<String>) -> Self {
+        self.error = error;
+        self
+    }
+
+    // Admins get a link to the admin pages
+    pub fn with_admin(mut self, is_admin: bool) -> Self {
+        self.is_admin = is_admin;
+        self
+    }
+
+    // Only offer unlinking while another sign-in method remains
+    pub fn can_unlink(&self) -> bool {
+        self.identities.len() > 1
+    }
+}
+#[derive(Template)]
+#[template(path = "admin_users.html")]
+pub struct AdminUsersTemplate {
+    pub users: Vec<User>,
+    pub search: String,
+    pub sort: UserSort,
+    pub page: i64,
+    pub total_pages: i64,
+    pub total_users: i64,
+    // The signed-in admin, who cannot disable or delete themselves
+    pub current_user_id: i64,
+    pub error: Option<String>,
+}
+
+impl AdminUsersTemplate {
+    pub fn previous_page_url(&self) -> String {
+        self.list_url(self.page - 1, self.sort)
+    }
+
+    pub fn next_page_url(&self) -> String {
+        self.list_url(self.page + 1, self.sort)
+    }
+
+    // Flip the last-login ordering, starting again from the first page
+    pub fn toggle_sort_url(&self) -> String {
+        self.list_url(1, self.sort.reversed())
+    }
+
+    // Keep the search when moving between pages and orderings
+    fn list_url(&self, page: i64, sort: UserSort) -> String {
+        let mut url = format!("/admin/users?page={}&sort={}", page, sort.as_str());
+        if !self.search.is_empty() {
+            url.push_str("&q=");
+            url.push_str(&urlencoding::encode(&self.search));
+        }
+        url
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin_audit.html")]
+pub struct AdminAuditTemplate {
+    pub events: Vec<AuditEvent>,
+    // Filters as entered, echoed back into the form
+    pub user_id: String,
+    pub from: String,
+    pub to: String,
+    pub page: i64,
+    pub total_pages: i64,
+    pub total_events: i64,
+    pub error: Option<String>,
+}
+
+impl AdminAuditTemplate {
+    pub fn previous_page_url(&self) -> String {
+        format!("/admin/audit?page={}&{}", self.page - 1, self.filter_query())
+    }
+
+    pub fn next_page_url(&self) -> String {
+        format!("/admin/audit?page={}&{}", self.page + 1, self.filter_query())
+    }
+
+    // Export exactly what the page is showing
+    pub fn export_url(&self) -> String {
+        format!("/admin/audit/export?{}", self.filter_query())
+    }
+
+    fn filter_query(&self) -> String {
+        format!(
+            "user_id={}&from={}&to={}",
+            urlencoding::encode(&self.user_id),
+            urlencoding::encode(&self.from),
+            urlencoding::encode(&self.to)
+        )
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin_clients.html")]
+pub struct AdminClientsTemplate {
+    pub clients: Vec<OAuthClient>,
+    // Set right after registration, the only time the secret is shown
+    pub registered: Option<RegisteredClient>,
+    pub issuer: String,
+    // Registration form as entered, echoed back after an error
+    pub name: String,
+    pub redirect_uris: String,
+    pub backchannel_logout_uri: String,
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "oauth_consent.html")]
+pub struct ConsentTemplate {
+    pub client_name: String,
+    pub username: String,
+    pub scopes: Vec<String>,
+    // The authorization request, posted back with the user's decision
+    pub request: AuthorizationRequest,
+}
+
+impl ConsentTemplate {
+    pub fn describe_scope(&self, scope: &str) -> &'static str {
+        match scope {
+            "openid" => "Your user ID in this app",
+            "profile" => "Your name and avatar",
+            "email" => "Your email address",
+            _ => "Other account details",
+        }
+    }
+
+    pub fn hidden_fields(&self) -> Vec<(&'static str, String)> {
+        let request = &self.request;
+        [
+            ("response_type", &request.response_type),
+            ("client_id", &request.client_id),
+            ("redirect_uri", &request.redirect_uri),
+            ("scope", &request.scope),
+            ("state", &request.state),
+            ("nonce", &request.nonce),
+            ("code_challenge", &request.code_challenge),
+            ("code_challenge_method", &request.code_challenge_method),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.clone().map(|value| (name, value)))
+        .collect()
+    }
+}
@@ -1,24 +1,88 @@
 use axum::{
+    async_trait,
     http::StatusCode,
     Router,
 };
-use axum_test::TestServer;
+use axum_test::{TestResponse, TestServer};
+use oauth2::{CsrfToken, PkceCodeChallenge, PkceCodeVerifier};
 use tempfile::NamedTempFile;
+use serde_json::json;
+use tower_sessions::cookie::Cookie;
+use wiremock::{
+    matchers::{body_string_contains, header, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
 
 use sso_web_app::{
-    AppState, AuthService, Config, Database, OAuth2Config, SessionManager, UserRepository,
-    dashboard_handler, github_auth_handler, github_callback_handler,
-    login_handler, logout_handler, microsoft_auth_handler, microsoft_callback_handler,
-    root_handler,
+    auth::AuthorizationRequest, AdminBootstrap, AppState, AuditLog, AuthError, AuthService, Config, Database,
+    IdentityProvider, LoginStateStore, MicrosoftTenant, OAuth2Config, ProfileSyncPolicy, ProviderProfile,
+    SessionManager, UserRepository, auth_callback_handler, auth_handler, dashboard_handler,
+    link_handler, login_handler, logout_handler, root_handler, unlink_handler,
+    admin_delete_user_handler, admin_disable_user_handler, admin_enable_user_handler,
+    admin_audit_export_handler, admin_audit_handler, admin_revoke_sessions_handler, admin_users_handler,
+    admin_clients_handler, admin_delete_client_handler, admin_register_client_handler,
+    authorize_decision_handler, authorize_handler, discovery_handler, jwks_handler, token_handler, userinfo_handler,
+    OAuthServer, revoke_session_handler, ApiTokenStore, create_api_token_handler, revoke_api_token_handler,
+    sign_out_everywhere_handler, api_router, json_errors_middleware,
 };
 
-async fn setup_test_app() -> TestServer {
+// Provider that completes every login without leaving the process;
+// the authorization code doubles as the provider account ID
+#[derive(Debug)]
+struct StubProvider(&'static str);
+
+#[async_trait]
+impl IdentityProvider for StubProvider {
+    fn slug(&self) -> &str {
+        self.0
+    }
+
+    fn display_name(&self) -> &str {
+        self.0
+    }
+
+    fn authorize_url(&self) -> AuthorizationRequest {
+        let (_, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let csrf_token = CsrfToken::new_random();
+        AuthorizationRequest {
+            url: format!("https://idp.example/authorize?state={}", csrf_token.secret()),
+            csrf_token,
+            pkce_verifier,
+            nonce: None,
+        }
+    }
+
+    async fn exchange_code(
+        &self,
+        code: String,
+        _pkce_verifier: PkceCodeVerifier,
+    ) -> Result<String, AuthError> {
+        Ok(format!("token-for-{}", code))
+    }
+
+    async fn fetch_profile(&self, access_token: &str) -> Result<ProviderProfile, AuthError> {
+        Ok(ProviderProfile {
+            provider_id: access_token.trim_start_matches("token-for-").to_string(),
+            username: "stubuser".to_string(),
+            email: Some("stub@example.com".to_string()),
+            email_verified: true,
+            avatar_url: None,
+        })
+    }
+}
+
+async fn setup_test_app() -> (TestServer, NamedTempFile) {
+    setup_test_app_with(|_| {}).await
+}
+
+// Like `setup_test_app`, letting the test adjust config (e.g. point providers at a mock server)
+async fn setup_test_app_with(configure: impl FnOnce(&mut Config)) -> (TestServer, NamedTempFile) {
     // Create test database
     let temp_file = NamedTempFile::new().unwrap();
     let database_url = format!("sqlite:{}", temp_file.path().to_str().unwrap());
     
     // Create test configuration
-    let config = Config {
+    let mut config = Config {
         database_url: database_url.clone(),
         microsoft_client_id: "test_ms_client_id".to_string(),
         microsoft_client_secret: "test_ms_client_secret".to_string(),
@@ -26,42 +90,112 @@ async fn setup_test_app() -> TestServer {
         github_client_secret: "test_gh_client_secret".to_string(),
         session_secret: "test_session_secret_key_for_testing_purposes".to_string(),
         base_url: "http://localhost:3000".to_string(),
+        ..Config::default()
     };
+    configure(&mut config);
 
     // Initialize database
     let database = Database::new(&database_url).await.unwrap();
     let user_repository = UserRepository::new(database.pool().clone());
 
     // Initialize OAuth2 clients
-    let oauth2_config = OAuth2Config::new(&config).unwrap();
-    let auth_service = AuthService::new(oauth2_config, user_repository);
+    let mut oauth2_config = OAuth2Config::new(&config).unwrap();
+    oauth2_config.register(StubProvider("stub"));
+    oauth2_config.register(StubProvider("stub2"));
+    let login_states = LoginStateStore::new(database.pool().clone(), config.login_state_mode);
+    let auth_service = AuthService::new(oauth2_config, user_repository.clone(), login_states)
+        .with_profile_sync(ProfileSyncPolicy::from_config(&config))
+        .with_admin_bootstrap(AdminBootstrap::from_config(&config))
+        .with_upstream_logout(config.upstream_logout);
 
     // Set up session management
-    let session_manager = SessionManager::new();
-    let session_layer = session_manager.layer();
+    let session_manager = SessionManager::new(&config, database.pool().clone()).unwrap();
+
+    let oauth_server = OAuthServer::new(database.pool().clone(), &config.base_url).await.unwrap();
 
     // Create application state
-    let app_state = AppState { auth_service };
+    let app_state = AppState {
+        auth_service,
+        user_repository,
+        session_policy: session_manager.policy(),
+        audit_log: AuditLog::new(database.pool().clone()),
+        oauth_server,
+        api_tokens: ApiTokenStore::new(database.pool().clone()),
+    };
 
     // Build test application
     let app = Router::new()
         .route("/", axum::routing::get(root_handler))
         .route("/login", axum::routing::get(login_handler))
-        .route("/auth/microsoft", axum::routing::get(microsoft_auth_handler))
-        .route("/auth/github", axum::routing::get(github_auth_handler))
-        .route("/auth/callback/microsoft", axum::routing::get(microsoft_callback_handler))
-        .route("/auth/callback/github", axum::routing::get(github_callback_handler))
+        .route("/auth/:provider", axum::routing::get(auth_handler))
+        .route("/auth/callback/:provider", axum::routing::get(auth_callback_handler))
         .route("/dashboard", axum::routing::get(dashboard_handler))
+        .route("/account/link/:provider", axum::routing::get(link_handler))
+        .route("/account/identities/:id/unlink", axum::routing::post(unlink_handler))
+        .route("/account/sessions/:id/revoke", axum::routing::post(revoke_session_handler))
+        .route("/account/sessions/revoke-all", axum::routing::post(sign_out_everywhere_handler))
+        .route("/account/tokens", axum::routing::post(create_api_token_handler))
+        .route("/account/tokens/:id/revoke", axum::routing::post(revoke_api_token_handler))
         .route("/logout", axum::routing::post(logout_handler))
-        .with_state(app_state)
-        .layer(session_layer);
+        .route("/admin/users", axum::routing::get(admin_users_handler))
+        .route("/admin/users/:id/disable", axum::routing::post(admin_disable_user_handler))
+        .route("/admin/users/:id/enable", axum::routing::post(admin_enable_user_handler))
+        .route("/admin/users/:id/delete", axum::routing::post(admin_delete_user_handler))
+        .route("/admin/users/:id/sessions/revoke", axum::routing::post(admin_revoke_sessions_handler))
+        .route("/admin/audit", axum::routing::get(admin_audit_handler))
+        .route("/admin/audit/export", axum::routing::get(admin_audit_export_handler))
+        .route("/admin/clients", axum::routing::get(admin_clients_handler).post(admin_register_client_handler))
+        .route("/admin/clients/:id/delete", axum::routing::post(admin_delete_client_handler))
+        .route("/.well-known/openid-configuration", axum::routing::get(discovery_handler))
+        .route("/oauth/jwks", axum::routing::get(jwks_handler))
+        .route("/oauth/authorize", axum::routing::get(authorize_handler).post(authorize_decision_handler))
+        .route("/oauth/token", axum::routing::post(token_handler))
+        .route("/oauth/userinfo", axum::routing::get(userinfo_handler).post(userinfo_handler))
+        .nest("/api/v1", api_router())
+        .layer(axum::middleware::from_fn(json_errors_middleware))
+        .with_state(app_state);
+    let app = session_manager.apply(app);
+
+    (TestServer::new(app).unwrap(), temp_file)
+}
+
+fn location(response: &TestResponse) -> &str {
+    response.headers().get("location").unwrap().to_str().unwrap()
+}
+
+fn state_param(response: &TestResponse) -> String {
+    location(response).split("state=").nth(1).unwrap().to_string()
+}
+
+// Read a query parameter from the redirect location
+fn location_param(response: &TestResponse, name: &str) -> String {
+    let url = reqwest::Url::parse(location(response)).unwrap();
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
 
-    TestServer::new(app).unwrap()
+// Start a stub login, returning the pre-login session cookie and the state to call back with
+async fn start_stub_login(server: &TestServer) -> (Cookie<'static>, String) {
+    let response = server.get("/auth/stub").await;
+    (response.cookie("sso_session"), state_param(&response))
+}
+
+// Complete a login or link through a stub provider on a server that saves cookies
+async fn complete_stub_flow(server: &TestServer, start_path: &str, account: &str) -> TestResponse {
+    let response = server.get(start_path).await;
+    let provider = start_path.rsplit('/').next().unwrap();
+    server
+        .get(&format!("/auth/callback/{}", provider))
+        .add_query_param("code", account)
+        .add_query_param("state", state_param(&response))
+        .await
 }
 
 #[tokio::test]
 async fn test_root_redirects_to_login_when_not_authenticated() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     let response = server.get("/").await;
     
@@ -72,7 +206,7 @@ async fn test_root_redirects_to_login_when_not_authenticated() {
 
 #[tokio::test]
 async fn test_login_page_renders_successfully() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     let response = server.get("/login").await;
     
@@ -81,13 +215,20 @@ async fn test_login_page_renders_successfully() {
     assert!(body.contains("Welcome"));
     assert!(body.contains("Sign in with Microsoft 365"));
     assert!(body.contains("Sign in with GitHub"));
+
+    // Registered providers without a branded button get a generic one
+    assert!(body.contains("href=\"/auth/stub\""));
+    assert!(body.contains("Sign in with stub"));
 }
 
 #[tokio::test]
 async fn test_login_page_displays_error_message() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
-    let response = server.get("/login?error=Test%20error%20message").await;
+    let response = server
+        .get("/login")
+        .add_raw_query_param("error=Test%20error%20message")
+        .await;
     
     assert_eq!(response.status_code(), StatusCode::OK);
     let body = response.text();
@@ -96,7 +237,7 @@ async fn test_login_page_displays_error_message() {
 
 #[tokio::test]
 async fn test_microsoft_auth_initiation() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     let response = server.get("/auth/microsoft").await;
     
@@ -106,11 +247,12 @@ async fn test_microsoft_auth_initiation() {
     assert!(location.contains("login.microsoftonline.com"));
     assert!(location.contains("client_id=test_ms_client_id"));
     assert!(location.contains("scope=openid"));
+    assert!(location.contains("code_challenge_method=S256"));
 }
 
 #[tokio::test]
 async fn test_github_auth_initiation() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     let response = server.get("/auth/github").await;
     
@@ -120,13 +262,35 @@ async fn test_github_auth_initiation() {
     assert!(location.contains("github.com/login/oauth/authorize"));
     assert!(location.contains("client_id=test_gh_client_id"));
     assert!(location.contains("scope=user%3Aemail"));
+    assert!(location.contains("code_challenge_method=S256"));
+}
+
+#[tokio::test]
+async fn test_unknown_provider_is_rejected() {
+    let (server, _db_file) = setup_test_app().await;
+
+    let response = server.get("/auth/myspace").await;
+    assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
+    let location = response.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("Invalid%20login%20provider"));
+
+    let response = server
+        .get("/auth/callback/myspace")
+        .add_raw_query_param("code=test_code&state=test_state")
+        .await;
+    assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
+    let location = response.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("Invalid%20login%20provider"));
 }
 
 #[tokio::test]
 async fn test_microsoft_callback_with_missing_code() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
-    let response = server.get("/auth/callback/microsoft?state=test_state").await;
+    let response = server
+        .get("/auth/callback/microsoft")
+        .add_raw_query_param("state=test_state")
+        .await;
     
     assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
     let location = response.headers().get("location").unwrap().to_str().unwrap();
@@ -135,9 +299,12 @@ async fn test_microsoft_callback_with_missing_code() {
 
 #[tokio::test]
 async fn test_github_callback_with_oauth_error() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
-    let response = server.get("/auth/callback/github?error=access_denied").await;
+    let response = server
+        .get("/auth/callback/github")
+        .add_raw_query_param("error=access_denied")
+        .await;
     
     assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
     let location = response.headers().get("location").unwrap().to_str().unwrap();
@@ -147,7 +314,7 @@ async fn test_github_callback_with_oauth_error() {
 
 #[tokio::test]
 async fn test_dashboard_requires_authentication() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     let response = server.get("/dashboard").await;
     
@@ -158,7 +325,7 @@ async fn test_dashboard_requires_authentication() {
 
 #[tokio::test]
 async fn test_logout_clears_session() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     let response = server.post("/logout").await;
     
@@ -169,11 +336,12 @@ async fn test_logout_clears_session() {
 
 #[tokio::test]
 async fn test_oauth_callback_csrf_protection() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     // Test Microsoft callback without proper CSRF state
     let ms_response = server
-        .get("/auth/callback/microsoft?code=test_code&state=invalid_state")
+        .get("/auth/callback/microsoft")
+        .add_raw_query_param("code=test_code&state=invalid_state")
         .await;
     
     assert_eq!(ms_response.status_code(), StatusCode::SEE_OTHER);
@@ -182,7 +350,8 @@ async fn test_oauth_callback_csrf_protection() {
 
     // Test GitHub callback without proper CSRF state
     let gh_response = server
-        .get("/auth/callback/github?code=test_code&state=invalid_state")
+        .get("/auth/callback/github")
+        .add_raw_query_param("code=test_code&state=invalid_state")
         .await;
     
     assert_eq!(gh_response.status_code(), StatusCode::SEE_OTHER);
@@ -190,13 +359,40 @@ async fn test_oauth_callback_csrf_protection() {
     assert!(location.contains("/login?error="));
 }
 
+#[tokio::test]
+async fn test_oauth_callback_rejects_state_without_session() {
+    let (server, _db_file) = setup_test_app().await;
+
+    // Start a real login to obtain a valid one-time state
+    let response = server.get("/auth/github").await;
+    let location = response.headers().get("location").unwrap().to_str().unwrap();
+    let auth_url = reqwest::Url::parse(location).unwrap();
+    let state = auth_url
+        .query_pairs()
+        .find(|(key, _)| key == "state")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+
+    // Presenting that state without the originating session cookie must fail
+    let response = server
+        .get("/auth/callback/github")
+        .add_query_param("code", "test_code")
+        .add_query_param("state", &state)
+        .await;
+
+    assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
+    let location = response.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("Security%20error"));
+}
+
 #[tokio::test]
 async fn test_oauth_error_handling() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     // Test Microsoft OAuth error
     let ms_error_response = server
-        .get("/auth/callback/microsoft?error=access_denied&error_description=User%20denied%20access")
+        .get("/auth/callback/microsoft")
+        .add_raw_query_param("error=access_denied&error_description=User%20denied%20access")
         .await;
     
     assert_eq!(ms_error_response.status_code(), StatusCode::SEE_OTHER);
@@ -206,7 +402,8 @@ async fn test_oauth_error_handling() {
 
     // Test GitHub OAuth error
     let gh_error_response = server
-        .get("/auth/callback/github?error=access_denied")
+        .get("/auth/callback/github")
+        .add_raw_query_param("error=access_denied")
         .await;
     
     assert_eq!(gh_error_response.status_code(), StatusCode::SEE_OTHER);
@@ -217,7 +414,7 @@ async fn test_oauth_error_handling() {
 
 #[tokio::test]
 async fn test_invalid_routes_return_404() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     let response = server.get("/nonexistent-route").await;
     assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
@@ -225,7 +422,7 @@ async fn test_invalid_routes_return_404() {
 
 #[tokio::test]
 async fn test_method_not_allowed() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     // Test POST to GET-only route
     let response = server.post("/login").await;
@@ -238,7 +435,7 @@ async fn test_method_not_allowed() {
 
 #[tokio::test]
 async fn test_session_cookie_security() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     let response = server.get("/login").await;
     
@@ -253,7 +450,7 @@ async fn test_session_cookie_security() {
 
 #[tokio::test]
 async fn test_html_content_type() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     let response = server.get("/login").await;
     
@@ -268,11 +465,12 @@ async fn test_html_content_type() {
 
 #[tokio::test]
 async fn test_template_rendering_with_special_characters() {
-    let server = setup_test_app().await;
+    let (server, _db_file) = setup_test_app().await;
 
     // Test with URL-encoded special characters in error message
     let response = server
-        .get("/login?error=Special%20characters%3A%20%3C%3E%26%22%27")
+        .get("/login")
+        .add_raw_query_param("error=Special%20characters%3A%20%3C%3E%26%22%27")
         .await;
     
     assert_eq!(response.status_code(), StatusCode::OK);
@@ -282,4 +480,1074 @@ async fn test_template_rendering_with_special_characters() {
     assert!(body.contains("Special characters"));
     // Should not contain raw HTML entities that could cause XSS
     assert!(!body.contains("<script"));
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn test_login_cycles_session_id() {
+    let (server, _db_file) = setup_test_app().await;
+
+    let (pre_login_cookie, state) = start_stub_login(&server).await;
+    let response = server
+        .get("/auth/callback/stub")
+        .add_query_param("code", "test_code")
+        .add_query_param("state", &state)
+        .add_cookie(pre_login_cookie.clone())
+        .await;
+    assert_eq!(location(&response), "/dashboard");
+
+    // The authenticated session lives under a new cookie value
+    let session_cookie = response.cookie("sso_session");
+    assert_ne!(session_cookie.value(), pre_login_cookie.value());
+
+    let response = server.get("/dashboard").add_cookie(session_cookie).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(response.text().contains("stubuser"));
+
+    // The cookie the browser had before login is no longer valid
+    let response = server.get("/dashboard").add_cookie(pre_login_cookie).await;
+    assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
+    assert_eq!(location(&response), "/login");
+}
+
+#[tokio::test]
+async fn test_logout_destroys_session() {
+    let (server, _db_file) = setup_test_app().await;
+
+    let (pre_login_cookie, state) = start_stub_login(&server).await;
+    let response = server
+        .get("/auth/callback/stub")
+        .add_query_param("code", "test_code")
+        .add_query_param("state", &state)
+        .add_cookie(pre_login_cookie)
+        .await;
+    let session_cookie = response.cookie("sso_session");
+
+    let response = server.post("/logout").add_cookie(session_cookie.clone()).await;
+    assert_eq!(location(&response), "/login");
+
+    // Replaying the old cookie after logout finds nothing in the store
+    let response = server.get("/dashboard").add_cookie(session_cookie).await;
+    assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
+    assert_eq!(location(&response), "/login");
+}
+
+#[tokio::test]
+async fn test_link_another_account_from_dashboard() {
+    let (mut server, _db_file) = setup_test_app().await;
+    server.do_save_cookies();
+
+    let response = complete_stub_flow(&server, "/auth/stub", "alice").await;
+    assert_eq!(location(&response), "/dashboard");
+
+    // Linking requires a signed-in user and lands back on the dashboard
+    let response = complete_stub_flow(&server, "/account/link/stub2", "alice-2").await;
+    assert_eq!(location(&response), "/dashboard");
+    let body = server.get("/dashboard").await.text();
+    assert!(body.contains("Link another account"));
+    assert!(body.contains("<strong>stub</strong>"));
+    assert!(body.contains("<strong>stub2</strong>"));
+
+    // The linked account now signs in as the same user
+    server.post("/logout").await;
+    complete_stub_flow(&server, "/auth/stub2", "alice-2").await;
+    let body = server.get("/dashboard").await.text();
+    assert!(body.contains("<strong>stub</strong>"));
+
+    // Unlink the original identity, then refuse to unlink the last one
+    let response = server.post("/account/identities/1/unlink").await;
+    assert_eq!(location(&response), "/dashboard");
+    let body = server.get("/dashboard").await.text();
+    assert!(!body.contains("<strong>stub</strong>"));
+    assert!(!body.contains("Unlink</button>"));
+
+    let response = server.post("/account/identities/2/unlink").await;
+    assert!(location(&response).starts_with("/dashboard?error="));
+}
+
+#[tokio::test]
+async fn test_link_requires_authentication() {
+    let (server, _db_file) = setup_test_app().await;
+
+    let response = server.get("/account/link/stub").await;
+    assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
+    assert_eq!(location(&response), "/login");
+}
+
+#[tokio::test]
+async fn test_link_account_owned_by_another_user() {
+    let (mut server, _db_file) = setup_test_app().await;
+    server.do_save_cookies();
+
+    complete_stub_flow(&server, "/auth/stub", "bob").await;
+    server.post("/logout").await;
+    complete_stub_flow(&server, "/auth/stub", "carol").await;
+
+    let response = complete_stub_flow(&server, "/account/link/stub", "bob").await;
+    assert!(location(&response).contains("already%20linked"));
+}
+
+#[tokio::test]
+async fn test_dashboard_shows_profile_from_database() {
+    let (mut server, _db_file) = setup_test_app().await;
+    server.do_save_cookies();
+
+    complete_stub_flow(&server, "/auth/stub", "dave").await;
+
+    let body = server.get("/dashboard").await.text();
+    assert!(body.contains("stub@example.com"));
+    assert!(body.contains("Member since:"));
+    assert!(body.contains("Last login:"));
+}
+
+#[tokio::test]
+async fn test_first_user_is_bootstrapped_as_admin() {
+    let (mut server, _db_file) = setup_test_app_with(|config| {
+        config.admin_bootstrap_first_user = true;
+    })
+    .await;
+    server.do_save_cookies();
+
+    complete_stub_flow(&server, "/auth/stub", "first").await;
+    assert!(server.get("/dashboard").await.text().contains("href=\"/admin/users\""));
+
+    // Later users are not admins
+    server.post("/logout").await;
+    complete_stub_flow(&server, "/auth/stub", "second").await;
+    assert!(!server.get("/dashboard").await.text().contains("href=\"/admin/users\""));
+}
+
+#[tokio::test]
+async fn test_admin_manages_users() {
+    let (mut server, _db_file) = setup_test_app_with(|config| {
+        config.admin_bootstrap_first_user = true;
+    })
+    .await;
+    server.do_save_cookies();
+
+    complete_stub_flow(&server, "/auth/stub", "admin").await;
+    server.post("/logout").await;
+    complete_stub_flow(&server, "/auth/stub", "member").await;
+
+    // Other users are refused
+    server.get("/admin/users").expect_failure().await.assert_status(StatusCode::FORBIDDEN);
+    server.post("/admin/users/1/delete").expect_failure().await.assert_status(StatusCode::FORBIDDEN);
+    server.post("/logout").await;
+    complete_stub_flow(&server, "/auth/stub", "admin").await;
+
+    let body = server.get("/admin/users").await.text();
+    assert!(body.contains("2 user(s)"));
+    assert!(body.contains("action=\"/admin/users/2/disable\""));
+    assert!(!body.contains("action=\"/admin/users/1/disable\""));
+
+    let body = server.get("/admin/users").add_query_param("q", "nobody").await.text();
+    assert!(body.contains("0 user(s)"));
+
+    let response = server
+        .post("/admin/users/2/disable")
+        .form(&json!({ "reason": "Left the company" }))
+        .await;
+    assert_eq!(location(&response), "/admin/users");
+    let body = server.get("/admin/users").await.text();
+    assert!(body.contains("action=\"/admin/users/2/enable\""));
+    assert!(body.contains("Left the company"));
+
+    server.post("/admin/users/2/enable").await;
+    let body = server.get("/admin/users").await.text();
+    assert!(body.contains("action=\"/admin/users/2/disable\""));
+
+    // Admins cannot lock themselves out
+    let response = server.post("/admin/users/1/disable").form(&json!({})).await;
+    assert!(location(&response).contains("your%20own%20account"));
+
+    let response = server.post("/admin/users/2/sessions/revoke").await;
+    assert_eq!(location(&response), "/admin/users");
+
+    server.post("/admin/users/2/delete").await;
+    assert!(server.get("/admin/users").await.text().contains("1 user(s)"));
+    let response = server.post("/admin/users/2/delete").await;
+    assert!(location(&response).contains("could%20not%20be%20found"));
+}
+
+#[tokio::test]
+async fn test_admin_views_and_exports_audit_log() {
+    let (mut server, _db_file) = setup_test_app_with(|config| {
+        config.admin_bootstrap_first_user = true;
+    })
+    .await;
+    server.do_save_cookies();
+
+    complete_stub_flow(&server, "/auth/stub", "admin").await;
+    server.post("/logout").await;
+    complete_stub_flow(&server, "/auth/stub", "member").await;
+    server.post("/logout").await;
+
+    // A sign-in the provider refused
+    let response = server.get("/auth/stub").await;
+    server
+        .get("/auth/callback/stub")
+        .add_query_param("error", "access_denied")
+        .add_query_param("state", state_param(&response))
+        .await;
+
+    complete_stub_flow(&server, "/auth/stub", "admin").await;
+    server.post("/admin/users/2/disable").form(&json!({ "reason": "Left the company" })).await;
+
+    let body = server.get("/admin/audit").await.text();
+    assert!(body.contains("7 event(s)"));
+    assert!(body.contains("provider_error"));
+    assert!(body.contains("Left the company"));
+
+    let events = server.get("/admin/audit/export").await.json::<Vec<serde_json::Value>>();
+    assert_eq!(events.len(), 7);
+    assert_eq!(events[0]["event_type"], "user_disabled");
+    assert_eq!(events[0]["user_id"], 2);
+    assert_eq!(events[0]["actor_id"], 1);
+    let failed = events.iter().find(|event| event["outcome"] == "failure").unwrap();
+    assert_eq!(failed["event_type"], "login");
+    assert_eq!(failed["error_kind"], "provider_error");
+    assert_eq!(failed["provider"], "stub");
+
+    // Filter by user and by an inclusive date range
+    let events = server
+        .get("/admin/audit/export")
+        .add_query_param("user_id", "2")
+        .await
+        .json::<Vec<serde_json::Value>>();
+    let kinds: Vec<_> = events.iter().map(|event| event["event_type"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["user_disabled", "logout", "login"]);
+
+    let today = chrono::Utc::now().date_naive();
+    let events = server
+        .get("/admin/audit/export")
+        .add_query_param("from", today.to_string())
+        .add_query_param("to", today.to_string())
+        .await
+        .json::<Vec<serde_json::Value>>();
+    assert_eq!(events.len(), 7);
+    let yesterday = today.pred_opt().unwrap();
+    let body = server.get("/admin/audit").add_query_param("to", yesterday.to_string()).await.text();
+    assert!(body.contains("0 event(s)"));
+
+    let body = server.get("/admin/audit").add_query_param("from", "last week").await.text();
+    assert!(body.contains("YYYY-MM-DD"));
+
+    // Members cannot read the log
+    server.post("/logout").await;
+    complete_stub_flow(&server, "/auth/stub", "other").await;
+    server.get("/admin/audit/export").expect_failure().await.assert_status(StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_sign_in_returns_to_requested_page() {
+    let (mut server, _db_file) = setup_test_app().await;
+    server.do_save_cookies();
+
+    // A deep link is remembered across the provider round-trip
+    let response = server.get("/admin/users").add_query_param("q", "ann").await;
+    assert_eq!(location(&response), "/login");
+    let response = complete_stub_flow(&server, "/auth/stub", "grace").await;
+    assert_eq!(location(&response), "/admin/users?q=ann");
+
+    // ...and only used once
+    server.post("/logout").await;
+    let response = complete_stub_flow(&server, "/auth/stub", "grace").await;
+    assert_eq!(location(&response), "/dashboard");
+
+    // The login page accepts a destination, but only a local one
+    server.post("/logout").await;
+    server.get("/login").add_query_param("return_to", "/dashboard?welcome=1").await;
+    let response = complete_stub_flow(&server, "/auth/stub", "grace").await;
+    assert_eq!(location(&response), "/dashboard?welcome=1");
+
+    for target in ["https://evil.example/", "//evil.example/", "/\\evil.example/"] {
+        server.post("/logout").await;
+        server.get("/login").add_query_param("return_to", target).await;
+        let response = complete_stub_flow(&server, "/auth/stub", "grace").await;
+        assert_eq!(location(&response), "/dashboard");
+    }
+}
+
+#[tokio::test]
+async fn test_disabled_user_is_logged_out_and_cannot_sign_in() {
+    let (mut server, db_file) = setup_test_app().await;
+    server.do_save_cookies();
+
+    complete_stub_flow(&server, "/auth/stub", "erin").await;
+    assert_eq!(server.get("/dashboard").await.status_code(), StatusCode::OK);
+
+    let database_url = format!("sqlite:{}", db_file.path().to_str().unwrap());
+    let user_repository = UserRepository::new(Database::new(&database_url).await.unwrap().pool().clone());
+    user_repository.disable_user(1, Some("Under review")).await.unwrap();
+
+    // The live session ends on the next request
+    let response = server.get("/dashboard").await;
+    assert!(location(&response).contains("disabled%3A%20Under%20review"));
+    assert_eq!(location(&server.get("/dashboard").await), "/login");
+
+    // Signing in again is refused with the same message, without touching the account
+    let before = user_repository.find_by_id(1).await.unwrap().unwrap();
+    let response = complete_stub_flow(&server, "/auth/stub", "erin").await;
+    assert!(location(&response).contains("disabled%3A%20Under%20review"));
+    assert_eq!(location(&server.get("/dashboard").await), "/login");
+    assert_eq!(user_repository.find_by_id(1).await.unwrap().unwrap().last_login, before.last_login);
+
+    user_repository.enable_user(1).await.unwrap();
+    let response = complete_stub_flow(&server, "/auth/stub", "erin").await;
+    assert_eq!(location(&response), "/dashboard");
+}
+
+#[tokio::test]
+async fn test_list_and_revoke_sessions() {
+    let (mut server, _db_file) = setup_test_app().await;
+    server.do_save_cookies();
+
+    // Sign in from two browsers
+    let first_browser = complete_stub_flow(&server, "/auth/stub", "frank").await.cookie("sso_session");
+    server.clear_cookies();
+    complete_stub_flow(&server, "/auth/stub", "frank").await;
+
+    let body = server.get("/dashboard").await.text();
+    assert!(body.contains("This session"));
+    let other_session = body
+        .split("action=\"/account/sessions/")
+        .nth(1)
+        .and_then(|rest| rest.split("/revoke").next())
+        .unwrap()
+        .to_string();
+
+    let response = server.post(&format!("/account/sessions/{}/revoke", other_session)).await;
+    assert_eq!(location(&response), "/dashboard");
+    assert!(!server.get("/dashboard").await.text().contains("/revoke\""));
+
+    // The revoked browser is signed out on its next request
+    let response = server.get("/dashboard").add_cookie(first_browser).await;
+    assert!(location(&response).contains("signed%20out"));
+
+    // Signing out everywhere ends the current session too
+    let response = server.post("/account/sessions/revoke-all").await;
+    assert_eq!(location(&response), "/login");
+    assert_eq!(location(&server.get("/dashboard").await), "/login");
+}
+
+#[tokio::test]
+async fn test_deleted_user_is_logged_out() {
+    let (mut server, db_file) = setup_test_app().await;
+    server.do_save_cookies();
+
+    complete_stub_flow(&server, "/auth/stub", "erin").await;
+
+    // Remove the account behind the live session
+    let database_url = format!("sqlite:{}", db_file.path().to_str().unwrap());
+    let pool = sqlx::SqlitePool::connect(&database_url).await.unwrap();
+    sqlx::query("DELETE FROM users").execute(&pool).await.unwrap();
+
+    let response = server.get("/dashboard").await;
+    assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
+    assert!(location(&response).contains("no%20longer%20exists"));
+
+    // The session is gone as well
+    let response = server.get("/dashboard").await;
+    assert_eq!(location(&response), "/login");
+}
+
+#[tokio::test]
+async fn test_github_login_end_to_end() {
+    let mock_server = MockServer::start().await;
+    let (mut server, _db_file) = setup_test_app_with(|config| {
+        config.github_oauth_url = format!("{}/login/oauth", mock_server.uri());
+        config.github_api_url = mock_server.uri();
+    })
+    .await;
+    server.do_save_cookies();
+
+    Mock::given(method("POST"))
+        .and(path("/login/oauth/access_token"))
+        .and(body_string_contains("code=github_code"))
+        .and(body_string_contains("code_verifier="))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "gh_access_token",
+            "token_type": "bearer"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/user"))
+        .and(header("authorization", "Bearer gh_access_token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": 4242,
+            "login": "octocat",
+            "name": null,
+            "email": null,
+            "avatar_url": "https://example.com/octocat.png"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/user/emails"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            { "email": "old@example.com", "primary": false, "verified": true },
+            { "email": "octocat@example.com", "primary": true, "verified": true }
+        ])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    // The browser is sent to the (mock) provider's authorize endpoint
+    let response = server.get("/auth/github").await;
+    assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
+    assert!(location(&response).starts_with(&format!("{}/login/oauth/authorize", mock_server.uri())));
+    let state = location_param(&response, "state");
+
+    // The provider redirects back; the code is exchanged and the user created
+    let response = server
+        .get("/auth/callback/github")
+        .add_query_param("code", "github_code")
+        .add_query_param("state", state)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::SEE_OTHER);
+    assert_eq!(location(&response), "/dashboard");
+
+    let response = server.get("/dashboard").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let body = response.text();
+    assert!(body.contains("octocat"));
+    assert!(body.contains("octocat@example.com"));
+    assert!(body.contains("https://example.com/octocat.png"));
+}
+
+#[tokio::test]
+async fn test_microsoft_login_end_to_end_enforces_tenant() {
+    let mock_server = MockServer::start().await;
+    let (mut server, _db_file) = setup_test_app_with(|config| {
+        config.microsoft_login_url = mock_server.uri();
+        config.microsoft_graph_url = format!("{}/v1.0", mock_server.uri());
+        config.microsoft_tenant =
+            MicrosoftTenant::Tenants(vec!["contoso-id".to_string(), "fabrikam-id".to_string()]);
+    })
+    .await;
+    server.do_save_cookies();
+
+    // Two allowed tenants share the `organizations` authority
+    let id_token = |tid: &str| {
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &json!({ "tid": tid, "sub": "ms-subject" }),
+            &jsonwebtoken::EncodingKey::from_secret(b"unused"),
+        )
+        .unwrap()
+    };
+    Mock::given(method("POST"))
+        .and(path("/organizations/oauth2/v2.0/token"))
+        .and(body_string_contains("code=allowed"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "ms_access_token",
+            "token_type": "bearer",
+            "id_token": id_token("fabrikam-id")
+        })))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/organizations/oauth2/v2.0/token"))
+        .and(body_string_contains("code=denied"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "ms_access_token",
+            "token_type": "bearer",
+            "id_token": id_token("other-id")
+        })))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/v1.0/me"))
+        .and(header("authorization", "Bearer ms_access_token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": "ms-user-1",
+            "displayName": "Fabrikam User",
+            "userPrincipalName": "user@fabrikam.example",
+            "mail": "user@fabrikam.example"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    // An account from a tenant outside the allow-list is turned away
+    let response = server.get("/auth/microsoft").await;
+    assert!(location(&response).starts_with(&format!("{}/organizations/oauth2/v2.0/authorize", mock_server.uri())));
+    let response = server
+        .get("/auth/callback/microsoft")
+        .add_query_param("code", "denied")
+        .add_query_param("state", location_param(&response, "state"))
+        .await;
+    assert!(location(&response).starts_with("/login?error="));
+    assert!(location(&response).contains("organization%20is%20not%20allowed"));
+
+    // An account from an allowed tenant signs in
+    let response = server.get("/auth/microsoft").await;
+    let response = server
+        .get("/auth/callback/microsoft")
+        .add_query_param("code", "allowed")
+        .add_query_param("state", location_param(&response, "state"))
+        .await;
+    assert_eq!(location(&response), "/dashboard");
+
+    let body = server.get("/dashboard").await.text();
+    assert!(body.contains("Fabrikam User"));
+}
+
+#[tokio::test]
+async fn test_github_login_denied_outside_allowed_orgs() {
+    let mock_server = MockServer::start().await;
+    let (mut server, _db_file) = setup_test_app_with(|config| {
+        config.github_oauth_url = format!("{}/login/oauth", mock_server.uri());
+        config.github_api_url = mock_server.uri();
+        config.github_allowed_orgs = vec!["acme".to_string()];
+    })
+    .await;
+    server.do_save_cookies();
+
+    Mock::given(method("POST"))
+        .and(path("/login/oauth/access_token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "gh_access_token",
+            "token_type": "bearer"
+        })))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/user"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": 4242,
+            "login": "outsider",
+            "name": null,
+            "email": "outsider@example.com",
+            "avatar_url": null
+        })))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/user/orgs"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([{ "login": "elsewhere" }])))
+        .mount(&mock_server)
+        .await;
+
+    let response = server.get("/auth/github").await;
+    assert!(location(&response).contains("read%3Aorg"));
+    let response = server
+        .get("/auth/callback/github")
+        .add_query_param("code", "github_code")
+        .add_query_param("state", location_param(&response, "state"))
+        .await;
+    let error_query = location(&response).strip_prefix("/login?").unwrap().to_string();
+    assert!(error_query.starts_with("error="));
+
+    // The login page explains the denial, and no session was created
+    let body = server.get("/login").add_raw_query_param(&error_query).await.text();
+    assert!(body.contains("authorized to use this application"));
+    let response = server.get("/dashboard").await;
+    assert_eq!(location(&response), "/login");
+}
+
+// Text of the `<code>` element following `label` on a page
+fn code_after(body: &str, label: &str) -> String {
+    let rest = &body[body.find(label).unwrap()..];
+    let start = rest.find("<code>").unwrap() + "<code>".len();
+    let end = rest.find("</code>").unwrap();
+    rest[start..end].to_string()
+}
+
+// Register an application through the admin page, returning its ID and secret
+async fn register_wiki_client(server: &TestServer) -> (String, String) {
+    let body = server
+        .post("/admin/clients")
+        .form(&json!({ "name": "Wiki", "redirect_uris": "https://wiki.example.com/callback" }))
+        .await
+        .text();
+    (code_after(&body, "Client ID:"), code_after(&body, "Client secret:"))
+}
+
+#[tokio::test]
+async fn test_internal_app_signs_in_through_openid_provider() {
+    let (mut server, _db_file) = setup_test_app_with(|config| {
+        config.admin_bootstrap_first_user = true;
+    })
+    .await;
+    server.do_save_cookies();
+
+    let discovery = server.get("/.well-known/openid-configuration").await.json::<serde_json::Value>();
+    assert_eq!(discovery["issuer"], "http://localhost:3000");
+    assert_eq!(discovery["token_endpoint"], "http://localhost:3000/oauth/token");
+
+    complete_stub_flow(&server, "/auth/stub", "admin").await;
+    let (client_id, client_secret) = register_wiki_client(&server).await;
+    assert!(server.get("/admin/clients").await.text().contains(&client_id));
+    assert!(!server.get("/admin/clients").await.text().contains(&client_secret));
+
+    let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+    let authorize_params = json!({
+        "response_type": "code",
+        "client_id": client_id,
+        "redirect_uri": "https://wiki.example.com/callback",
+        "scope": "openid profile email",
+        "state": "wiki-state",
+        "nonce": "wiki-nonce",
+        "code_challenge": challenge.as_str(),
+        "code_challenge_method": "S256",
+    });
+
+    // The first visit asks for consent
+    let response = server.get("/oauth/authorize").add_query_params(&authorize_params).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(response.text().contains("Authorize Wiki"));
+
+    let mut decision = authorize_params.clone();
+    decision["decision"] = json!("allow");
+    let response = server.post("/oauth/authorize").form(&decision).await;
+    assert!(location(&response).starts_with("https://wiki.example.com/callback?code="));
+    assert_eq!(location_param(&response, "state"), "wiki-state");
+    let code = location_param(&response, "code");
+
+    let basic = base64::Engine::encode(
+        &base64::engine::general_purpose::STANDARD,
+        format!("{}:{}", client_id, client_secret),
+    );
+    let token_form = json!({
+        "grant_type": "authorization_code",
+        "code": code,
+        "redirect_uri": "https://wiki.example.com/callback",
+        "code_verifier": verifier.secret(),
+    });
+    let response = server
+        .post("/oauth/token")
+        .add_header(
+            axum::http::header::AUTHORIZATION,
+            axum::http::HeaderValue::from_str(&format!("Basic {}", basic)).unwrap(),
+        )
+        .form(&token_form)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(response.headers().get("cache-control").unwrap(), "no-store");
+    let tokens = response.json::<serde_json::Value>();
+
+    // The ID token verifies against the published keys and names the local user
+    let jwks: jsonwebtoken::jwk::JwkSet = server.get("/oauth/jwks").await.json();
+    let id_token = tokens["id_token"].as_str().unwrap();
+    let kid = jsonwebtoken::decode_header(id_token).unwrap().kid.unwrap();
+    let key = jsonwebtoken::DecodingKey::from_jwk(jwks.find(&kid).unwrap()).unwrap();
+    let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::ES256);
+    validation.set_audience(&[&client_id]);
+    validation.set_issuer(&["http://localhost:3000"]);
+    let claims = jsonwebtoken::decode::<serde_json::Value>(id_token, &key, &validation).unwrap().claims;
+    assert_eq!(claims["sub"], "1");
+    assert_eq!(claims["nonce"], "wiki-nonce");
+    assert_eq!(claims["email"], "stub@example.com");
+
+    let access_token = tokens["access_token"].as_str().unwrap();
+    let userinfo = server
+        .get("/oauth/userinfo")
+        .add_header(
+            axum::http::header::AUTHORIZATION,
+            axum::http::HeaderValue::from_str(&format!("Bearer {}", access_token)).unwrap(),
+        )
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(userinfo["sub"], "1");
+    assert_eq!(userinfo["preferred_username"], "stubuser");
+
+    // Codes are single-use, and the client must authenticate
+    let mut replay = token_form.clone();
+    replay["client_id"] = json!(client_id);
+    replay["client_secret"] = json!(client_secret);
+    let response = server.post("/oauth/token").form(&replay).expect_failure().await;
+    assert_eq!(response.json::<serde_json::Value>()["error"], "invalid_grant");
+    replay["client_secret"] = json!("wrong");
+    let response = server.post("/oauth/token").form(&replay).expect_failure().await;
+    response.assert_status(StatusCode::UNAUTHORIZED);
+
+    // Consent is remembered, so the next sign-in goes straight back to the app
+    let response = server.get("/oauth/authorize").add_query_params(&authorize_params).await;
+    assert!(location(&response).starts_with("https://wiki.example.com/callback?code="));
+}
+
+#[tokio::test]
+async fn test_openid_provider_refuses_unregistered_redirects() {
+    let (mut server, _db_file) = setup_test_app_with(|config| {
+        config.admin_bootstrap_first_user = true;
+    })
+    .await;
+    server.do_save_cookies();
+
+    complete_stub_flow(&server, "/auth/stub", "admin").await;
+    let (client_id, _) = register_wiki_client(&server).await;
+    let (challenge, _) = PkceCodeChallenge::new_random_sha256();
+    let mut params = json!({
+        "response_type": "code",
+        "client_id": client_id,
+        "redirect_uri": "https://evil.example.com/callback",
+        "scope": "openid",
+        "state": "s",
+        "code_challenge": challenge.as_str(),
+        "code_challenge_method": "S256",
+    });
+
+    // Never redirected to an address the client did not register
+    let response = server.get("/oauth/authorize").add_query_params(&params).expect_failure().await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert!(response.headers().get("location").is_none());
+
+    // Other problems go back to the client
+    params["redirect_uri"] = json!("https://wiki.example.com/callback");
+    params["decision"] = json!("deny");
+    let response = server.post("/oauth/authorize").form(&params).await;
+    assert_eq!(location_param(&response, "error"), "access_denied");
+    assert_eq!(location_param(&response, "state"), "s");
+
+    // Signed-out users sign in first and come back to the request
+    server.post("/logout").await;
+    params.as_object_mut().unwrap().remove("decision");
+    let response = server.get("/oauth/authorize").add_query_params(&params).await;
+    assert_eq!(location(&response), "/login");
+    let response = complete_stub_flow(&server, "/auth/stub", "admin").await;
+    assert!(location(&response).starts_with("/oauth/authorize?"));
+}
+
+#[tokio::test]
+async fn test_logout_signs_out_of_relying_parties() {
+    let (mut server, _db_file) = setup_test_app_with(|config| {
+        config.admin_bootstrap_first_user = true;
+    })
+    .await;
+    server.do_save_cookies();
+    let wiki = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/backchannel-logout"))
+        .and(body_string_contains("logout_token="))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&wiki)
+        .await;
+
+    complete_stub_flow(&server, "/auth/stub", "admin").await;
+    let body = server
+        .post("/admin/clients")
+        .form(&json!({
+            "name": "Wiki",
+            "redirect_uris": "https://wiki.example.com/callback",
+            "backchannel_logout_uri": format!("{}/backchannel-logout", wiki.uri()),
+        }))
+        .await
+        .text();
+    let (client_id, client_secret) = (code_after(&body, "Client ID:"), code_after(&body, "Client secret:"));
+
+    // Sign in to the wiki
+    let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+    let response = server
+        .post("/oauth/authorize")
+        .form(&json!({
+            "response_type": "code",
+            "client_id": client_id,
+            "redirect_uri": "https://wiki.example.com/callback",
+            "scope": "openid",
+            "code_challenge": challenge.as_str(),
+            "code_challenge_method": "S256",
+            "decision": "allow",
+        }))
+        .await;
+    let tokens = server
+        .post("/oauth/token")
+        .form(&json!({
+            "grant_type": "authorization_code",
+            "code": location_param(&response, "code"),
+            "redirect_uri": "https://wiki.example.com/callback",
+            "code_verifier": verifier.secret(),
+            "client_id": client_id,
+            "client_secret": client_secret,
+        }))
+        .await
+        .json::<serde_json::Value>();
+
+    // Both tokens verify against the published keys, with the wiki as audience
+    let jwks: jsonwebtoken::jwk::JwkSet = server.get("/oauth/jwks").await.json();
+    let verify = |token: &str| {
+        let header = jsonwebtoken::decode_header(token).unwrap();
+        let key = jsonwebtoken::DecodingKey::from_jwk(jwks.find(&header.kid.unwrap()).unwrap()).unwrap();
+        let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::ES256);
+        validation.set_audience(&[&client_id]);
+        validation.set_issuer(&["http://localhost:3000"]);
+        (header.typ, jsonwebtoken::decode::<serde_json::Value>(token, &key, &validation).unwrap().claims)
+    };
+    let (_, id_claims) = verify(tokens["id_token"].as_str().unwrap());
+    assert!(id_claims["sid"].is_string());
+
+    // Stub sign-ins have no end-session endpoint, so logout lands on the login page
+    let response = server.post("/logout").await;
+    assert_eq!(location(&response), "/login");
+
+    // The wiki is told which session ended; delivery happens in the background
+    let mut received = Vec::new();
+    for _ in 0..50 {
+        received = wiki.received_requests().await.unwrap_or_default();
+        if !received.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(received.len(), 1);
+    let (_, logout_token) = oauth2::url::form_urlencoded::parse(&received[0].body)
+        .find(|(name, _)| name == "logout_token")
+        .unwrap();
+    let (typ, logout_claims) = verify(&logout_token);
+    assert_eq!(typ.as_deref(), Some("logout+jwt"));
+    assert_eq!(logout_claims["sid"], id_claims["sid"]);
+
+    // Tokens issued under the ended session stop working
+    let response = server
+        .get("/oauth/userinfo")
+        .add_header(
+            axum::http::header::AUTHORIZATION,
+            axum::http::HeaderValue::from_str(&format!("Bearer {}", tokens["access_token"].as_str().unwrap())).unwrap(),
+        )
+        .expect_failure()
+        .await;
+    response.assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_personal_api_tokens_are_shown_once_and_revocable() {
+    let (mut server, _db_file) = setup_test_app().await;
+    server.do_save_cookies();
+    complete_stub_flow(&server, "/auth/stub", "user").await;
+
+    // Non-admins cannot grant the admin scope
+    let response = server
+        .post("/account/tokens")
+        .form(&json!({ "name": "Deploy", "lifetime_days": 30, "read": "true", "admin": "true" }))
+        .await;
+    assert!(location(&response).starts_with("/dashboard?error="));
+    assert!(!server.get("/dashboard").await.text().contains("name=\"admin\""));
+
+    let body = server
+        .post("/account/tokens")
+        .form(&json!({ "name": "Deploy", "lifetime_days": 30, "read": "true", "write": "true" }))
+        .await
+        .text();
+    let token = code_after(&body, "Token:");
+    assert!(token.starts_with("sso_pat_"));
+
+    // Listed with its scopes, but the token itself never appears again
+    let dashboard = server.get("/dashboard").await.text();
+    assert!(dashboard.contains("Deploy"));
+    assert!(dashboard.contains("read, write"));
+    assert!(dashboard.contains("never used"));
+    assert!(!dashboard.contains(&token));
+
+    let revoke_path = {
+        let start = dashboard.find("/account/tokens/").unwrap();
+        let end = start + dashboard[start..].find('"').unwrap();
+        dashboard[start..end].to_string()
+    };
+    let response = server.post(&revoke_path).await;
+    assert_eq!(location(&response), "/dashboard");
+    assert!(!server.get("/dashboard").await.text().contains("Deploy"));
+
+    // Revoking twice is reported on the dashboard
+    let response = server.post(&revoke_path).await;
+    assert!(location(&response).contains("already%20been%20revoked"));
+}
+
+#[tokio::test]
+async fn test_json_api_for_current_user_and_directory() {
+    let (mut server, _db_file) = setup_test_app_with(|config| config.admin_bootstrap_first_user = true).await;
+    server.do_save_cookies();
+    let bearer = |token: &str| axum::http::HeaderValue::from_str(&format!("Bearer {}", token)).unwrap();
+    let accept_json = axum::http::HeaderValue::from_static("application/json");
+
+    // The first user is an admin with an admin-scoped token, the second a regular user
+    complete_stub_flow(&server, "/auth/stub", "admin").await;
+    let body = server
+        .post("/account/tokens")
+        .form(&json!({ "name": "Directory", "lifetime_days": 30, "read": "true", "admin": "true" }))
+        .await
+        .text();
+    let admin_token = code_after(&body, "Token:");
+    complete_stub_flow(&server, "/auth/stub", "user").await;
+    let body = server
+        .post("/account/tokens")
+        .form(&json!({ "name": "Profile", "lifetime_days": 30, "read": "true", "write": "true" }))
+        .await
+        .text();
+    let user_token = code_after(&body, "Token:");
+    server.clear_cookies();
+
+    // API clients get JSON errors, never the login page
+    let response = server.get("/api/v1/me").await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().get("location").is_none());
+    assert_eq!(response.json::<serde_json::Value>()["error"], "not_authenticated");
+    let response = server.get("/dashboard").add_header(axum::http::header::ACCEPT, accept_json).await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    let response = server.get("/api/v1/me").add_header(axum::http::header::AUTHORIZATION, bearer("sso_pat_nope")).await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().get("www-authenticate").is_some());
+    assert_eq!(response.json::<serde_json::Value>()["error"], "invalid_api_token");
+
+    let me = server
+        .get("/api/v1/me")
+        .add_header(axum::http::header::AUTHORIZATION, bearer(&user_token))
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(me["provider_id"], "user");
+    let user_id = me["id"].as_i64().unwrap();
+
+    let response = server
+        .patch("/api/v1/me")
+        .add_header(axum::http::header::AUTHORIZATION, bearer(&user_token))
+        .json(&json!({ "username": "  Renamed User " }))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let me = response.json::<serde_json::Value>();
+    assert_eq!(me["username"], "Renamed User");
+    assert!(!me["username_edited_at"].is_null());
+
+    // Only editable fields are accepted
+    let response = server
+        .patch("/api/v1/me")
+        .add_header(axum::http::header::AUTHORIZATION, bearer(&user_token))
+        .json(&json!({ "email": "someone@example.com" }))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<serde_json::Value>()["error"], "bad_request");
+
+    // The directory is for admins, and their tokens need the admin scope
+    let response = server.get("/api/v1/users").add_header(axum::http::header::AUTHORIZATION, bearer(&user_token)).await;
+    assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+    assert_eq!(response.json::<serde_json::Value>()["error"], "forbidden");
+
+    let page = server
+        .get("/api/v1/users")
+        .add_query_param("per_page", 1)
+        .add_query_param("page", 2)
+        .add_header(axum::http::header::AUTHORIZATION, bearer(&admin_token))
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(page["total"], 2);
+    assert_eq!(page["total_pages"], 2);
+    assert_eq!(page["users"].as_array().unwrap().len(), 1);
+
+    let page = server
+        .get("/api/v1/users")
+        .add_query_param("q", "renamed")
+        .add_query_param("disabled", false)
+        .add_header(axum::http::header::AUTHORIZATION, bearer(&admin_token))
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["users"][0]["id"], user_id);
+
+    let user = server
+        .get(&format!("/api/v1/users/{}", user_id))
+        .add_header(axum::http::header::AUTHORIZATION, bearer(&admin_token))
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(user["username"], "Renamed User");
+    let response = server.get("/api/v1/users/9999").add_header(axum::http::header::AUTHORIZATION, bearer(&admin_token)).await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    assert_eq!(response.json::<serde_json::Value>()["error"], "not_found");
+    let response = server
+        .get("/api/v1/users")
+        .add_query_param("per_page", 500)
+        .add_header(axum::http::header::AUTHORIZATION, bearer(&admin_token))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_microsoft_mail_does_not_match_admin_emails() {
+    let mock_server = MockServer::start().await;
+    let (mut server, _db_file) = setup_test_app_with(|config| {
+        config.microsoft_login_url = mock_server.uri();
+        config.microsoft_graph_url = format!("{}/v1.0", mock_server.uri());
+        config.admin_emails = vec!["ceo@contoso.example".to_string()];
+    })
+    .await;
+    server.do_save_cookies();
+
+    // A tenant anyone can create, whose admin set `mail` to the real admin's address
+    let id_token = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &json!({ "tid": "attacker-id", "sub": "ms-subject" }),
+        &jsonwebtoken::EncodingKey::from_secret(b"unused"),
+    )
+    .unwrap();
+    Mock::given(method("POST"))
+        .and(path("/common/oauth2/v2.0/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "access_token": "ms_access_token",
+            "token_type": "bearer",
+            "id_token": id_token
+        })))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/v1.0/me"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "id": "ms-user-1",
+            "displayName": "Mallory",
+            "userPrincipalName": "mallory@attacker.onmicrosoft.com",
+            "mail": "ceo@contoso.example"
+        })))
+        .mount(&mock_server)
+        .await;
+
+    let response = server.get("/auth/microsoft").await;
+    let response = server
+        .get("/auth/callback/microsoft")
+        .add_query_param("code", "attacker")
+        .add_query_param("state", location_param(&response, "state"))
+        .await;
+    assert_eq!(location(&response), "/dashboard");
+
+    // Signed in, but not an admin
+    let response = server.get("/admin/users").await;
+    assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_anonymous_api_calls_do_not_create_sessions() {
+    let (server, db_file) = setup_test_app().await;
+    let database_url = format!("sqlite:{}", db_file.path().to_str().unwrap());
+    let pool = sqlx::SqlitePool::connect(&database_url).await.unwrap();
+    let stored_sessions = || async {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM sessions").fetch_one(&pool).await.unwrap()
+    };
+
+    for path in ["/api/v1/me", "/api/v1/users"] {
+        let response = server.get(path).await;
+        assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get("set-cookie").is_none());
+    }
+    assert_eq!(stored_sessions().await, 0);
+
+    // Pages still remember where to return to after sign-in
+    let response = server.get("/dashboard").await;
+    assert!(response.headers().get("set-cookie").is_some());
+    assert_eq!(stored_sessions().await, 1);
+}
+
+#[tokio::test]
+async fn test_profile_refreshed_at_sign_in_is_audited() {
+    let (mut server, db_file) = setup_test_app_with(|config| config.admin_bootstrap_first_user = true).await;
+    server.do_save_cookies();
+    complete_stub_flow(&server, "/auth/stub", "admin").await;
+
+    // The address changed at the provider since the last sign-in
+    let database_url = format!("sqlite:{}", db_file.path().to_str().unwrap());
+    let pool = sqlx::SqlitePool::connect(&database_url).await.unwrap();
+    sqlx::query("UPDATE users SET email = 'old@example.com'").execute(&pool).await.unwrap();
+    complete_stub_flow(&server, "/auth/stub", "admin").await;
+
+    let events = server.get("/admin/audit/export").await.json::<Vec<serde_json::Value>>();
+    let updates: Vec<_> = events.iter().filter(|event| event["event_type"] == "profile_updated").collect();
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0]["user_id"], 1);
+    assert_eq!(updates[0]["provider"], "stub");
+    assert!(updates[0]["details"].as_str().unwrap().contains("email"));
+}